@@ -0,0 +1,79 @@
+use log::warn;
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::{Fingerprint, HashAlg, PublicKey};
+use std::fs;
+use std::path::PathBuf;
+
+/// Keys allowed to be added or used, read from the `.pub` files in a directory
+#[derive(Debug, Serialize)]
+pub struct AllowedKeys {
+    dir: PathBuf,
+    #[serde(serialize_with = "crate::policy::display_all")]
+    fingerprints: Vec<Fingerprint>,
+}
+
+impl AllowedKeys {
+    /// Fingerprint every `.pub` file in `dir`, skipping files that do not hold a public key
+    pub fn load(dir: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut fingerprints = Vec::new();
+        for entry in fs::read_dir(&dir).map_err(|e| format!("{}: {e}", dir.display()))? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "pub") {
+                continue;
+            }
+            match PublicKey::read_openssh_file(&path) {
+                Ok(key) => fingerprints.push(key.fingerprint(HashAlg::Sha256)),
+                Err(e) => warn!("Skipping allowed key {}: {e}", path.display()),
+            }
+        }
+        if fingerprints.is_empty() {
+            warn!(
+                "No public keys in {}; every key will be denied",
+                dir.display()
+            );
+        }
+        Ok(Self { dir, fingerprints })
+    }
+
+    pub fn contains(&self, fingerprint: &Fingerprint) -> bool {
+        self.fingerprints.contains(fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey};
+
+    fn key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    #[test]
+    fn loads_only_public_key_files() {
+        let dir = std::env::temp_dir().join(format!("ssh-agent-ac-{}-allowed", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (listed, unlisted, misnamed) = (key(), key(), key());
+        let write = |name, key: &PrivateKey| {
+            let path = dir.join(name);
+            key.public_key().write_openssh_file(&path)
+        };
+        write("listed.pub", &listed).unwrap();
+        write("misnamed.txt", &misnamed).unwrap();
+        fs::write(dir.join("garbage.pub"), "not a key").unwrap();
+
+        let allowed = AllowedKeys::load(dir.clone()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(allowed.fingerprints, [listed.fingerprint(HashAlg::Sha256)]);
+        assert!(allowed.contains(&listed.fingerprint(HashAlg::Sha256)));
+        assert!(!allowed.contains(&unlisted.fingerprint(HashAlg::Sha256)));
+        assert!(!allowed.contains(&misnamed.fingerprint(HashAlg::Sha256)));
+    }
+
+    #[test]
+    fn a_missing_directory_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("ssh-agent-ac-{}-missing", std::process::id()));
+        assert!(AllowedKeys::load(dir).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+/// What to do with a sign request whose flags a key has never signed with before
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnomalyAction {
+    /// Sign, logging a warning
+    Warn,
+    /// Refuse to sign
+    Deny,
+}
+
+/// Sign flags one key has been seen with
+#[derive(Debug, Default)]
+struct Profile {
+    signs: u32,
+    flags: BTreeSet<u32>,
+}
+
+/// Per-key baseline of sign flags, for spotting requests that break a key's usual pattern
+///
+/// A key's baseline is established after `baseline` signatures; from then on,
+/// flags outside it are an anomaly. Only signatures the backend made join the
+/// baseline, so refused requests cannot train it.
+#[derive(Debug, Serialize)]
+pub struct FlagProfiles {
+    action: Option<AnomalyAction>,
+    baseline: u32,
+    #[serde(skip)]
+    profiles: Mutex<BTreeMap<Fingerprint, Profile>>,
+}
+
+impl FlagProfiles {
+    pub fn new(action: Option<AnomalyAction>, baseline: u32) -> Self {
+        Self {
+            action,
+            baseline,
+            profiles: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The configured action if a sign request with `flags` is an anomaly, and how many
+    /// earlier signatures the key's baseline holds
+    pub fn check(&self, fingerprint: &Fingerprint, flags: u32) -> Option<(AnomalyAction, u32)> {
+        let action = self.action?;
+        let profiles = self.profiles.lock().unwrap_or_else(|e| e.into_inner());
+        let (seen, known) = profiles.get(fingerprint).map_or((0, false), |profile| {
+            (profile.signs, profile.flags.contains(&flags))
+        });
+        (seen >= self.baseline && !known).then_some((action, seen))
+    }
+
+    /// Add a signature made with `flags` to the baseline of `fingerprint`
+    pub fn signed(&self, fingerprint: &Fingerprint, flags: u32) {
+        if self.action.is_none() {
+            return;
+        }
+        let mut profiles = self.profiles.lock().unwrap_or_else(|e| e.into_inner());
+        let profile = profiles.entry(*fingerprint).or_default();
+        profile.signs = profile.signs.saturating_add(1);
+        profile.flags.insert(flags);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+
+    #[test]
+    fn flags_outside_an_established_baseline_are_anomalies() {
+        let profiles = FlagProfiles::new(Some(AnomalyAction::Deny), 2);
+        let key = KEY.parse().unwrap();
+        assert_eq!(profiles.check(&key, 4), None);
+        profiles.signed(&key, 4);
+        profiles.signed(&key, 4);
+        assert_eq!(profiles.check(&key, 4), None);
+        assert_eq!(profiles.check(&key, 2), Some((AnomalyAction::Deny, 2)));
+    }
+
+    #[test]
+    fn unsigned_requests_do_not_train_the_baseline() {
+        let profiles = FlagProfiles::new(Some(AnomalyAction::Warn), 1);
+        let key = KEY.parse().unwrap();
+        for _ in 0..10 {
+            assert_eq!(profiles.check(&key, 2), None);
+        }
+        profiles.signed(&key, 4);
+        assert_eq!(profiles.check(&key, 2), Some((AnomalyAction::Warn, 1)));
+    }
+
+    #[test]
+    fn disabled_profiles_never_flag() {
+        let profiles = FlagProfiles::new(None, 0);
+        let key = KEY.parse().unwrap();
+        profiles.signed(&key, 4);
+        assert_eq!(profiles.check(&key, 2), None);
+    }
+}
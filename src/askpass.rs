@@ -0,0 +1,341 @@
+use clap::ValueEnum;
+use log::{debug, warn};
+use std::ffi::OsString;
+use std::fmt;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// How long a prompt may stay unanswered before the watchdog warns about it
+static PENDING_WARNING: OnceLock<Duration> = OnceLock::new();
+
+/// How confirmation prompts reach the user, if configured differently from askpass alone
+static CONFIRMERS: OnceLock<CompositeConfirmer> = OnceLock::new();
+
+#[cfg(test)]
+tokio::task_local! {
+    /// Answer given to every prompt within `answering`, and the prompts it was given for
+    static ANSWERS: (Option<bool>, std::sync::Mutex<Vec<String>>);
+}
+
+/// A way of asking the user to approve a prompt
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Confirmer {
+    /// A desktop notification with Allow and Deny buttons, shown through notify-send
+    Notification,
+    /// The SSH_ASKPASS program in confirm mode
+    Askpass,
+    /// Decline without asking
+    ///
+    /// This answers the prompt rather than being unavailable, so no later
+    /// confirmer is tried and keys confirmed with `--key-confirm <FP>=allow`
+    /// are refused too.
+    Deny,
+}
+
+/// Something that can ask the user to approve a prompt
+#[ssh_agent_lib::async_trait]
+pub trait Confirm: fmt::Debug + Send + Sync {
+    /// Ask the user to approve `prompt`, or `None` if this confirmer cannot show it here
+    async fn confirm(&self, prompt: &str) -> Option<bool>;
+}
+
+#[ssh_agent_lib::async_trait]
+impl Confirm for Confirmer {
+    async fn confirm(&self, prompt: &str) -> Option<bool> {
+        match self {
+            Self::Notification => notification(prompt).await,
+            Self::Askpass => askpass(prompt).await,
+            Self::Deny => Some(false),
+        }
+    }
+}
+
+/// Confirmers tried in order until one can show the prompt
+#[derive(Debug)]
+pub struct CompositeConfirmer(Vec<Box<dyn Confirm>>);
+
+impl CompositeConfirmer {
+    pub fn new(confirmers: Vec<Box<dyn Confirm>>) -> Self {
+        Self(confirmers)
+    }
+
+    /// Ask the user to approve `prompt`, or `None` if none of the confirmers can
+    pub async fn confirm(&self, prompt: &str) -> Option<bool> {
+        for confirmer in &self.0 {
+            match confirmer.confirm(prompt).await {
+                Some(approved) => return Some(approved),
+                None => debug!("Confirmer {confirmer:?} is unavailable, trying the next"),
+            }
+        }
+        None
+    }
+}
+
+impl Default for CompositeConfirmer {
+    fn default() -> Self {
+        Self(vec![Box::new(Confirmer::Askpass)])
+    }
+}
+
+/// Ask through `confirmers`, in order, instead of through askpass alone
+pub fn confirm_through(confirmers: Vec<Confirmer>) {
+    let confirmers = confirmers
+        .into_iter()
+        .map(|confirmer| Box::new(confirmer) as Box<dyn Confirm>)
+        .collect();
+    let _ = CONFIRMERS.set(CompositeConfirmer::new(confirmers));
+}
+
+/// Warn when a prompt has been waiting for the user `after` this long, and again every `after`
+pub fn warn_pending_after(after: Duration) {
+    let _ = PENDING_WARNING.set(after);
+}
+
+/// Ask the user to approve `prompt`, the way ssh-agent confirms key use
+///
+/// This goes through SSH_ASKPASS unless other confirmers were configured; if
+/// none can ask, the request is treated as declined. Dropping the future, e.g.
+/// because the client hung up, kills the prompt.
+pub async fn confirm(prompt: &str) -> bool {
+    try_confirm(prompt).await.unwrap_or_else(|| {
+        warn!("No confirmer could ask the user, declining: {prompt}");
+        false
+    })
+}
+
+/// Ask the user to approve `prompt`, or `None` if no confirmer can ask here
+pub async fn try_confirm(prompt: &str) -> Option<bool> {
+    #[cfg(test)]
+    if let Ok(answer) = ANSWERS.try_with(|(answer, prompts)| {
+        prompts.lock().unwrap().push(prompt.to_string());
+        *answer
+    }) {
+        return answer;
+    }
+    let confirmers = CONFIRMERS.get_or_init(CompositeConfirmer::default);
+    watched(prompt, confirmers.confirm(prompt)).await
+}
+
+/// Run `future` with every prompt answered `answer` instead of asking the user
+///
+/// Returns the output of `future` and the prompts it asked.
+#[cfg(test)]
+pub async fn answering<T>(
+    answer: Option<bool>,
+    future: impl Future<Output = T>,
+) -> (T, Vec<String>) {
+    let answers = (answer, std::sync::Mutex::new(Vec::new()));
+    ANSWERS
+        .scope(answers, async {
+            let output = future.await;
+            let prompts = ANSWERS.with(|(_, prompts)| prompts.lock().unwrap().clone());
+            (output, prompts)
+        })
+        .await
+}
+
+/// Confirm through the askpass program, which approves by exiting successfully
+async fn askpass(prompt: &str) -> Option<bool> {
+    let program = program();
+    let status = Command::new(&program)
+        .arg(prompt)
+        .env("SSH_ASKPASS_PROMPT", "confirm")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await;
+
+    match status {
+        Ok(status) => Some(status.success()),
+        Err(e) => {
+            warn!("Failed to run {}: {e}", program.to_string_lossy());
+            None
+        }
+    }
+}
+
+/// Confirm through a desktop notification, which prints the chosen action once clicked
+///
+/// Unavailable when notify-send is missing, predates actions, or finds no
+/// notification server. Dismissing the notification declines.
+async fn notification(prompt: &str) -> Option<bool> {
+    let output = Command::new("notify-send")
+        .args(["--app-name=ssh-agent-ac", "--urgency=critical", "--wait"])
+        .args(["--action=allow=Allow", "--action=deny=Deny", "ssh-agent-ac"])
+        .arg(prompt)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .inspect_err(|e| debug!("Failed to run notify-send: {e}"))
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim() == "allow")
+}
+
+/// Ask the user to type an answer to `prompt` through SSH_ASKPASS, e.g. a one-time code
+///
+/// Returns `None` if the askpass program cannot be started or the user cancels.
+pub async fn ask(prompt: &str) -> Option<String> {
+    let program = program();
+    let output = Command::new(&program)
+        .arg(prompt)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = watched(prompt, output).await;
+
+    match output {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Failed to run {}: {e}", program.to_string_lossy());
+            None
+        }
+    }
+}
+
+/// Wait for the prompt `answer`, warning while it stays unanswered past the watchdog threshold
+async fn watched<T>(prompt: &str, answer: impl Future<Output = T>) -> T {
+    match PENDING_WARNING.get() {
+        Some(&after) => warn_while_pending(after, prompt, answer, |w| warn!("{w}")).await,
+        None => answer.await,
+    }
+}
+
+/// Wait for `answer`, handing `warn` a warning every `after` it stays pending
+async fn warn_while_pending<T>(
+    after: Duration,
+    prompt: &str,
+    answer: impl Future<Output = T>,
+    mut warn: impl FnMut(String),
+) -> T {
+    let started = Instant::now();
+    let summary = prompt.lines().next().unwrap_or_default();
+
+    tokio::pin!(answer);
+    let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + after, after);
+    loop {
+        tokio::select! {
+            answer = &mut answer => return answer,
+            _ = ticks.tick() => {
+                let secs = started.elapsed().as_secs();
+                warn(format!("Prompt awaiting the user for {secs}s: {summary}"));
+            }
+        }
+    }
+}
+
+fn program() -> OsString {
+    std::env::var_os("SSH_ASKPASS").unwrap_or_else(|| "ssh-askpass".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Confirmer giving a fixed answer and counting how often it was asked
+    #[derive(Debug)]
+    struct Stub {
+        answer: Option<bool>,
+        asked: Arc<AtomicUsize>,
+    }
+
+    #[ssh_agent_lib::async_trait]
+    impl Confirm for Stub {
+        async fn confirm(&self, _prompt: &str) -> Option<bool> {
+            self.asked.fetch_add(1, Ordering::SeqCst);
+            self.answer
+        }
+    }
+
+    /// A composite of stubs with these answers, and how often each was asked
+    fn composite(answers: &[Option<bool>]) -> (CompositeConfirmer, Vec<Arc<AtomicUsize>>) {
+        let asked: Vec<_> = answers.iter().map(|_| Arc::default()).collect();
+        let stubs = answers
+            .iter()
+            .zip(&asked)
+            .map(|(&answer, asked)| {
+                let asked = Arc::clone(asked);
+                Box::new(Stub { answer, asked }) as Box<dyn Confirm>
+            })
+            .collect();
+        (CompositeConfirmer::new(stubs), asked)
+    }
+
+    fn counts(asked: &[Arc<AtomicUsize>]) -> Vec<usize> {
+        asked.iter().map(|a| a.load(Ordering::SeqCst)).collect()
+    }
+
+    #[tokio::test]
+    async fn falls_back_past_unavailable_confirmers() {
+        let (confirmers, asked) = composite(&[None, Some(true), Some(false)]);
+        assert_eq!(confirmers.confirm("prompt").await, Some(true));
+        assert_eq!(counts(&asked), [1, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn a_decline_is_final() {
+        let (confirmers, asked) = composite(&[Some(false), Some(true)]);
+        assert_eq!(confirmers.confirm("prompt").await, Some(false));
+        assert_eq!(counts(&asked), [1, 0]);
+    }
+
+    #[tokio::test]
+    async fn none_available_asks_everyone_once() {
+        let (confirmers, asked) = composite(&[None, None]);
+        assert_eq!(confirmers.confirm("prompt").await, None);
+        assert_eq!(counts(&asked), [1, 1]);
+    }
+
+    #[tokio::test]
+    async fn answering_replaces_the_confirmers() {
+        let (approved, prompts) = answering(Some(true), confirm("first")).await;
+        assert!(approved);
+        assert_eq!(prompts, ["first"]);
+        let (approved, _) = answering(None, confirm("second")).await;
+        assert!(!approved);
+    }
+
+    #[tokio::test]
+    async fn deny_declines_without_asking() {
+        assert_eq!(Confirmer::Deny.confirm("prompt").await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn warns_repeatedly_about_a_prompt_left_pending() {
+        let mut warnings = Vec::new();
+        let answer = tokio::time::sleep(Duration::from_millis(250));
+        let prompt = "Allow use of key me@host?\nKey fingerprint SHA256:...";
+        warn_while_pending(Duration::from_millis(100), prompt, answer, |w| {
+            warnings.push(w)
+        })
+        .await;
+        assert_eq!(
+            warnings,
+            [
+                "Prompt awaiting the user for 0s: Allow use of key me@host?",
+                "Prompt awaiting the user for 0s: Allow use of key me@host?",
+            ]
+        );
+
+        let mut warnings = Vec::new();
+        let answered =
+            warn_while_pending(Duration::from_millis(100), prompt, async { true }, |w| {
+                warnings.push(w)
+            })
+            .await;
+        assert!(answered);
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,49 @@
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::ssh_key::{Fingerprint, LineEnding};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::policy::Policy;
+use crate::signer;
+
+/// Signature namespace of policy attestations, for `ssh-keygen -Y verify -n`
+pub const NAMESPACE: &str = "ssh-agent-ac-policy";
+
+/// Record of the policy a proxy started with, for auditors to check later
+///
+/// `path` holds the policy's canonical JSON, so its SHA-256 is the policy hash
+/// logged and sent with audit events; `signature`, if the policy was signed,
+/// holds an SSHSIG over the same bytes.
+#[derive(Debug)]
+pub struct Attestation {
+    pub path: PathBuf,
+    pub signature: Option<PathBuf>,
+}
+
+/// Write `policy` to `path` and, with a `key`, sign it through `backend` into `path` with `.sig` appended
+pub async fn write(
+    policy: &Policy,
+    path: &Path,
+    key: Option<&Fingerprint>,
+    backend: &mut dyn Session,
+) -> Result<Attestation, Box<dyn std::error::Error>> {
+    let json = policy.canonical_json();
+    std::fs::write(path, &json).map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+
+    let signature = match key {
+        Some(key) => {
+            let sshsig = signer::sshsig(backend, key, NAMESPACE, &json).await?;
+            let mut sig_path = OsString::from(path);
+            sig_path.push(".sig");
+            let sig_path = PathBuf::from(sig_path);
+            std::fs::write(&sig_path, sshsig.to_pem(LineEnding::LF)?)
+                .map_err(|e| format!("Failed to write {}: {e}", sig_path.display()))?;
+            Some(sig_path)
+        }
+        None => None,
+    };
+    Ok(Attestation {
+        path: path.to_owned(),
+        signature,
+    })
+}
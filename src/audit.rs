@@ -0,0 +1,132 @@
+use log::warn;
+use serde_json::{Value, json};
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::Response;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::attest::Attestation;
+use crate::client::ClientInfo;
+use crate::denial::Denial;
+use crate::dump;
+
+/// Audit sink sending one JSON event per datagram to a local collector's socket
+///
+/// Sending never blocks: while the collector is absent or behind, events are
+/// dropped and counted. Every event carries the hash of the policy in force,
+/// to correlate it with the policy attestation.
+#[derive(Debug)]
+pub struct AuditSocket {
+    path: PathBuf,
+    socket: UnixDatagram,
+    policy_hash: String,
+    dropped: AtomicU64,
+}
+
+impl AuditSocket {
+    pub fn new(path: PathBuf, policy_hash: String) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            path,
+            socket,
+            policy_hash,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    pub fn send(&self, mut event: Value) {
+        event["policy"] = self.policy_hash.clone().into();
+        let datagram = event.to_string();
+        if let Err(e) = self.socket.send_to(datagram.as_bytes(), &self.path) {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            // Warn on the 1st, 2nd, 4th, ... drop, so an absent collector does not flood the log
+            if dropped.is_power_of_two() {
+                let path = self.path.display();
+                warn!("Dropped {dropped} audit events so far; last send to {path} failed: {e}");
+            }
+        }
+    }
+}
+
+/// Audit event for the proxy starting, with where its policy attestation was written
+pub fn startup_event(attestation: Option<&Attestation>) -> Value {
+    let mut event = json!({ "time": now(), "event": "startup" });
+    if let Some(attestation) = attestation {
+        event["attestation"] = json!({
+            "file": attestation.path,
+            "signature": attestation.signature,
+        });
+    }
+    event
+}
+
+/// Audit event for one request from `client`, as decoded by `dump::request_json`
+pub fn event(
+    client: &ClientInfo,
+    request: Value,
+    result: &Result<Response, AgentError>,
+    denial: Option<&Denial>,
+) -> Value {
+    let mut event = json!({
+        "time": now(),
+        "client": { "pid": client.pid, "uid": client.uid },
+        "request": request,
+    });
+    let outcome = dump::result_json(result);
+    if let (Value::Object(event), Value::Object(outcome)) = (&mut event, outcome) {
+        event.extend(outcome);
+    }
+    if let Some(denial) = denial {
+        event["denial"] = json!({ "action": denial.action, "reason": denial.reason });
+    }
+    event
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn sends_each_event_as_one_datagram() {
+        let path = socket("audit");
+        let collector = UnixDatagram::bind(&path).unwrap();
+        let audit = AuditSocket::new(path.clone(), "policy hash".into()).unwrap();
+
+        audit.send(json!({ "event": "startup" }));
+        audit.send(json!({ "event": "other" }));
+        let mut datagram = [0; 1024];
+        for expected in ["startup", "other"] {
+            let len = collector.recv(&mut datagram).unwrap();
+            let event: Value = serde_json::from_slice(&datagram[..len]).unwrap();
+            assert_eq!(event, json!({ "event": expected, "policy": "policy hash" }));
+        }
+        assert_eq!(audit.dropped.load(Ordering::Relaxed), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn counts_events_dropped_without_a_collector() {
+        let audit = AuditSocket::new(socket("audit-absent"), "policy hash".into()).unwrap();
+        audit.send(json!({ "event": "startup" }));
+        audit.send(json!({ "event": "other" }));
+        assert_eq!(audit.dropped.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,285 @@
+use log::{debug, warn};
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::agent::service_binding::Binding;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::{Request, Response};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::ProxyError;
+use crate::memory::InProcessAgent;
+
+/// Where the proxy forwards the requests its policy lets through
+#[derive(Clone)]
+pub enum Backends {
+    /// ssh-agent sockets, in failover order
+    Sockets(Vec<PathBuf>),
+    /// Keys held by the proxy itself, shared by all connections
+    InProcess(InProcessAgent),
+}
+
+/// Open a connection to the ssh-agent listening at `path`
+pub fn connect(path: &Path) -> Result<Box<dyn Session>, ProxyError> {
+    #[cfg(unix)]
+    let binding = Binding::FilePath(path.to_owned());
+    #[cfg(windows)]
+    let binding = Binding::NamedPipe(path.as_os_str().to_owned());
+
+    let connected = binding.try_into().and_then(|stream| {
+        ssh_agent_lib::client::connect(stream).map_err(|e| match e.downcast::<io::Error>() {
+            Ok(e) => *e,
+            Err(e) => io::Error::other(e.to_string()),
+        })
+    });
+    connected.map_err(|source| ProxyError::Backend {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Backend over the first of several ssh-agents that answers an identity listing
+///
+/// The choice is made on the first request and kept for the connection. If
+/// the chosen agent fails, the connection to it is dropped and the next
+/// request picks again; the failed request itself is not sent anywhere else,
+/// since signing or changing keys again could repeat what the failed agent
+/// already did. Only an identity listing is retried at once. The failed agent
+/// is tried last, so a single agent that was restarted out-of-band is
+/// reconnected to instead of leaving the connection stale.
+pub struct Failover {
+    candidates: Vec<PathBuf>,
+    current: Option<(usize, Box<dyn Session>)>,
+    /// The agent that failed last, to try after the others when picking again
+    failed: Option<usize>,
+}
+
+impl Failover {
+    pub fn new(candidates: Vec<PathBuf>) -> Self {
+        Self {
+            candidates,
+            current: None,
+            failed: None,
+        }
+    }
+
+    /// Start from `backend`, already connected to `candidates[index]`
+    pub fn connected(candidates: Vec<PathBuf>, index: usize, backend: Box<dyn Session>) -> Self {
+        Self {
+            candidates,
+            current: Some((index, backend)),
+            failed: None,
+        }
+    }
+
+    /// The chosen agent, connecting to the first responsive candidate if there is none
+    async fn backend(&mut self) -> Option<&mut Box<dyn Session>> {
+        if self.current.is_none() {
+            self.current = self.select().await;
+        }
+        self.current.as_mut().map(|(_, backend)| backend)
+    }
+
+    /// Connect to the first responsive candidate, trying the failed one last
+    async fn select(&mut self) -> Option<(usize, Box<dyn Session>)> {
+        let failed = self.failed;
+        let order = (0..self.candidates.len())
+            .filter(|&index| Some(index) != failed)
+            .chain(failed);
+        for index in order {
+            let path = &self.candidates[index];
+            let Ok(mut backend) = connect(path) else {
+                continue;
+            };
+            if let Ok(Response::IdentitiesAnswer(_)) =
+                backend.handle(Request::RequestIdentities).await
+            {
+                debug!("Using backend ssh-agent {}", path.display());
+                return Some((index, backend));
+            }
+        }
+        None
+    }
+}
+
+#[ssh_agent_lib::async_trait]
+impl Session for Failover {
+    async fn handle(&mut self, message: Request) -> Result<Response, AgentError> {
+        let Some(backend) = self.backend().await else {
+            warn!("No backend ssh-agent is responsive");
+            return Ok(Response::Failure);
+        };
+        let listing = matches!(message, Request::RequestIdentities);
+        let error = match backend.handle(message).await {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+
+        if let Some((index, _)) = self.current.take() {
+            let path = self.candidates[index].display();
+            warn!("Backend ssh-agent {path} failed: {error}; reconnecting on the next request");
+            self.failed = Some(index);
+        }
+        // Listing is idempotent, so it alone is safe to send again
+        if !listing {
+            return Ok(Response::Failure);
+        }
+        match self.backend().await {
+            Some(backend) => backend.handle(Request::RequestIdentities).await,
+            None => Ok(Response::Failure),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::proto::SignRequest;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{UnixListener, UnixStream};
+
+    const REQUEST_IDENTITIES: u8 = 11;
+    const SIGN_REQUEST: u8 = 13;
+
+    /// ssh-agent that lists no keys, fails every sign request and counts them
+    #[derive(Default)]
+    struct MockAgent {
+        signs: AtomicUsize,
+        /// Close the connection after the next request instead of answering it
+        hang_up: AtomicBool,
+    }
+
+    impl MockAgent {
+        fn spawn(name: &str) -> (Arc<Self>, PathBuf) {
+            let path = std::env::temp_dir()
+                .join(format!("ssh-agent-ac-{}-{name}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+            let agent = Arc::new(Self::default());
+            let serving = agent.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serving.clone().serve(stream));
+                }
+            });
+            (agent, path)
+        }
+
+        async fn serve(self: Arc<Self>, mut stream: UnixStream) {
+            loop {
+                let Ok(len) = stream.read_u32().await else {
+                    return;
+                };
+                let mut message = vec![0; len as usize];
+                if stream.read_exact(&mut message).await.is_err() {
+                    return;
+                }
+                if message[0] == SIGN_REQUEST {
+                    self.signs.fetch_add(1, Ordering::SeqCst);
+                }
+                if self.hang_up.swap(false, Ordering::SeqCst) {
+                    return;
+                }
+                let answer: &[u8] = match message[0] {
+                    REQUEST_IDENTITIES => &[12, 0, 0, 0, 0],
+                    _ => &[5],
+                };
+                let framed = [&(answer.len() as u32).to_be_bytes()[..], answer].concat();
+                if stream.write_all(&framed).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        fn signs(&self) -> usize {
+            self.signs.load(Ordering::SeqCst)
+        }
+    }
+
+    fn sign_request() -> Request {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        Request::SignRequest(SignRequest {
+            pubkey: key.public_key().key_data().clone(),
+            data: b"data".to_vec(),
+            flags: 0,
+        })
+    }
+
+    #[test]
+    fn connecting_to_a_missing_agent_is_an_error() {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-missing.sock", std::process::id()));
+        match connect(&path) {
+            Err(e @ ProxyError::Backend { .. }) => assert!(e.backend_gone()),
+            Err(e) => panic!("unexpected {e}"),
+            Ok(_) => panic!("connected to a missing agent"),
+        }
+    }
+
+    #[tokio::test]
+    async fn failover_without_a_responsive_agent_fails_requests() {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-none.sock", std::process::id()));
+        let mut backend = Failover::new(vec![path]);
+        let response = backend.handle(Request::RequestIdentities).await.unwrap();
+        assert!(matches!(response, Response::Failure));
+    }
+
+    #[tokio::test]
+    async fn failed_sign_is_not_repeated_on_the_next_agent() {
+        let (first, first_path) = MockAgent::spawn("failover-first");
+        let (second, second_path) = MockAgent::spawn("failover-second");
+        let mut backend = Failover::new(vec![first_path.clone(), second_path.clone()]);
+
+        backend.handle(sign_request()).await.unwrap();
+        first.hang_up.store(true, Ordering::SeqCst);
+        let response = backend.handle(sign_request()).await.unwrap();
+        assert!(matches!(response, Response::Failure));
+        assert_eq!((first.signs(), second.signs()), (2, 0));
+
+        backend.handle(sign_request()).await.unwrap();
+        assert_eq!((first.signs(), second.signs()), (2, 1));
+
+        let _ = std::fs::remove_file(first_path);
+        let _ = std::fs::remove_file(second_path);
+    }
+
+    #[tokio::test]
+    async fn restarted_agent_is_reconnected_on_the_next_request() {
+        let (agent, path) = MockAgent::spawn("reconnect");
+        let mut backend = Failover::new(vec![path.clone()]);
+
+        backend.handle(sign_request()).await.unwrap();
+        agent.hang_up.store(true, Ordering::SeqCst);
+        let response = backend.handle(sign_request()).await.unwrap();
+        assert!(matches!(response, Response::Failure));
+        assert!(
+            backend.current.is_none(),
+            "reconnected before the next request"
+        );
+        assert_eq!(agent.signs(), 2);
+
+        backend.handle(sign_request()).await.unwrap();
+        assert_eq!(agent.signs(), 3);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn failed_listing_is_retried_on_the_next_agent() {
+        let (first, first_path) = MockAgent::spawn("listing-first");
+        let (_second, second_path) = MockAgent::spawn("listing-second");
+        let mut backend = Failover::new(vec![first_path.clone(), second_path.clone()]);
+
+        backend.handle(Request::RequestIdentities).await.unwrap();
+        first.hang_up.store(true, Ordering::SeqCst);
+        let response = backend.handle(Request::RequestIdentities).await.unwrap();
+        assert!(matches!(response, Response::IdentitiesAnswer(_)));
+
+        let _ = std::fs::remove_file(first_path);
+        let _ = std::fs::remove_file(second_path);
+    }
+}
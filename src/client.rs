@@ -0,0 +1,108 @@
+use std::fmt;
+
+/// Process on the other end of a proxy connection, as far as it can be determined
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub pid: Option<i32>,
+    pub uid: Option<u32>,
+    pub cmdline: Option<String>,
+}
+
+impl ClientInfo {
+    #[cfg(unix)]
+    pub fn from_stream(stream: &tokio::net::UnixStream, with_cmdline: bool) -> Self {
+        let cred = stream.peer_cred().ok();
+        let pid = cred.and_then(|c| c.pid());
+        Self {
+            pid,
+            uid: cred.map(|c| c.uid()),
+            cmdline: pid.filter(|_| with_cmdline).and_then(cmdline),
+        }
+    }
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "pid={pid}")?,
+            None => write!(f, "pid=?")?,
+        }
+        match self.uid {
+            Some(uid) => write!(f, " uid={uid}")?,
+            None => write!(f, " uid=?")?,
+        }
+        if let Some(cmdline) = &self.cmdline {
+            write!(f, " cmdline={cmdline:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Command line of `pid`, or `None` if it already exited or `/proc` is unavailable
+#[cfg(target_os = "linux")]
+pub fn cmdline(pid: i32) -> Option<String> {
+    let raw = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    // Zombies and kernel threads have an empty command line
+    if raw.is_empty() {
+        return None;
+    }
+    let args: Vec<_> = raw
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    Some(args.join(" "))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn cmdline(_pid: i32) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_our_own_command_line() {
+        let ours: Vec<_> = std::env::args().collect();
+        let pid = i32::try_from(std::process::id()).unwrap();
+        assert_eq!(cmdline(pid), Some(ours.join(" ")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exited_processes_have_no_command_line() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = i32::try_from(child.id()).unwrap();
+        child.wait().unwrap();
+        assert_eq!(cmdline(pid), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn identifies_the_connecting_process() {
+        let (ours, theirs) = tokio::net::UnixStream::pair().unwrap();
+        let client = ClientInfo::from_stream(&ours, true);
+        drop(theirs);
+        let pid = i32::try_from(std::process::id()).unwrap();
+        assert_eq!(client.pid, Some(pid));
+        assert_eq!(client.uid, Some(nix::unistd::getuid().as_raw()));
+        if cfg!(target_os = "linux") {
+            assert!(client.cmdline.is_some_and(|c| !c.is_empty()));
+        }
+    }
+
+    #[test]
+    fn displays_unknown_fields() {
+        let client = ClientInfo::default();
+        assert_eq!(client.to_string(), "pid=? uid=?");
+        let client = ClientInfo {
+            pid: Some(7),
+            uid: Some(1000),
+            cmdline: Some("ssh host".into()),
+        };
+        assert_eq!(client.to_string(), "pid=7 uid=1000 cmdline=\"ssh host\"");
+    }
+}
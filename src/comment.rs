@@ -0,0 +1,68 @@
+use log::warn;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// How long the filter may take before the original comment is kept
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// External program rewriting the comments of added keys
+///
+/// The program gets the original comment on stdin and prints the new one on
+/// stdout. If it fails, times out or prints something other than UTF-8, the
+/// original comment is kept.
+///
+/// Chained proxies each run their own filter on the same add, so a filter
+/// should leave comments it already rewrote unchanged, e.g. by not adding a
+/// prefix that is already there.
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct CommentFilter {
+    path: PathBuf,
+}
+
+impl CommentFilter {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// What `comment` is rewritten to, or `None` to keep it
+    pub async fn rewrite(&self, comment: &str) -> Option<String> {
+        match tokio::time::timeout(TIMEOUT, self.run(comment)).await {
+            Ok(Ok(rewritten)) => Some(rewritten),
+            Ok(Err(e)) => {
+                warn!("Comment filter {} failed: {e}", self.path.display());
+                None
+            }
+            Err(_) => {
+                let secs = TIMEOUT.as_secs();
+                warn!("Comment filter {} took over {secs}s", self.path.display());
+                None
+            }
+        }
+    }
+
+    async fn run(&self, comment: &str) -> Result<String, String> {
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // A filter that exits without reading its input is not an error
+        let _ = stdin.write_all(comment.as_bytes()).await;
+        drop(stdin);
+
+        let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(output.status.to_string());
+        }
+        let rewritten = String::from_utf8(output.stdout).map_err(|_| "output is not UTF-8")?;
+        Ok(rewritten.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
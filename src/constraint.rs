@@ -0,0 +1,107 @@
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+
+/// Constraint the proxy adds to every key that does not already carry it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnforcedConstraint {
+    /// Require confirmation for every use of the key
+    #[default]
+    Confirm,
+    /// Expire the key after at most this many seconds
+    Lifetime(u32),
+    /// Require confirmation for keys added without a lifetime
+    ConfirmOrLifetime,
+    /// Forward adds unchanged
+    None,
+}
+
+impl FromStr for EnforcedConstraint {
+    type Err = String;
+
+    /// Parse `confirm`, `lifetime=<SECONDS>` or `none`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "confirm" => Ok(Self::Confirm),
+            "none" => Ok(Self::None),
+            _ => {
+                let secs = s
+                    .strip_prefix("lifetime=")
+                    .ok_or("expected confirm, lifetime=<SECONDS> or none")?;
+                let secs = secs.parse().map_err(|e| format!("lifetime: {e}"))?;
+                if secs == 0 {
+                    return Err("lifetime must be positive".into());
+                }
+                Ok(Self::Lifetime(secs))
+            }
+        }
+    }
+}
+
+impl fmt::Display for EnforcedConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Confirm => write!(f, "confirm"),
+            Self::Lifetime(secs) => write!(f, "lifetime={secs}"),
+            Self::ConfirmOrLifetime => write!(f, "confirm-or-lifetime"),
+            Self::None => write!(f, "none"),
+        }
+    }
+}
+
+/// Keys added without constraints that the proxy confirms at sign time instead
+///
+/// Used when plain adds are forwarded as they are rather than upgraded to
+/// confirm-constrained adds; the backend then signs without asking.
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct SignConfirmations {
+    enabled: bool,
+    /// Comment of each key, for the prompt
+    #[serde(skip)]
+    keys: Mutex<BTreeMap<Fingerprint, String>>,
+}
+
+impl SignConfirmations {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            keys: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Whether plain adds are forwarded unchanged, with confirmation moved to sign time
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record that signing with `fingerprint` must be confirmed by the proxy, or no longer must
+    pub fn set(&self, fingerprint: Fingerprint, comment: String, confirm: bool) {
+        let mut keys = self.lock();
+        if confirm {
+            keys.insert(fingerprint, comment);
+        } else {
+            keys.remove(&fingerprint);
+        }
+    }
+
+    /// Comment of `fingerprint` if signing with it must be confirmed by the proxy
+    pub fn comment(&self, fingerprint: &Fingerprint) -> Option<String> {
+        self.lock().get(fingerprint).cloned()
+    }
+
+    pub fn removed(&self, fingerprint: &Fingerprint) {
+        self.lock().remove(fingerprint);
+    }
+
+    pub fn removed_all(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<Fingerprint, String>> {
+        self.keys.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
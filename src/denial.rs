@@ -0,0 +1,54 @@
+use ssh_agent_lib::proto::Extension;
+use ssh_agent_lib::ssh_encoding::Encode;
+use ssh_agent_lib::ssh_key::rand_core::{OsRng, RngCore};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Name of the extension reporting the last denial on a connection
+pub const WHY_DENIED_EXTENSION: &str = "why-denied@ssh-agent-ac";
+
+/// A request refused by the proxy's own policy
+#[derive(Clone, Debug)]
+pub struct Denial {
+    /// What was refused, e.g. `signing with SHA256:...`
+    pub action: String,
+    pub reason: String,
+}
+
+impl Denial {
+    pub fn new(action: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            action: action.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.action, self.reason)
+    }
+}
+
+/// Sleep until `min` plus a random jitter of up to `min` has passed since `started`
+///
+/// A denial then takes about as long as a request answered by the backend, so
+/// its timing does not reveal that the proxy refused it.
+pub async fn pad(started: Instant, min: Duration) {
+    let jitter = min.mul_f64(f64::from(OsRng.next_u32()) / f64::from(u32::MAX));
+    tokio::time::sleep_until((started + min + jitter).into()).await;
+}
+
+/// Build the why-denied response: a single SSH string, empty if nothing was denied
+pub fn why_denied_response(denial: Option<&Denial>) -> Extension {
+    let text = denial.map(ToString::to_string).unwrap_or_default();
+
+    let mut details = Vec::new();
+    text.encode(&mut details)
+        .expect("encoding into a Vec cannot fail");
+
+    Extension {
+        name: WHY_DENIED_EXTENSION.to_string(),
+        details: details.into(),
+    }
+}
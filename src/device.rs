@@ -0,0 +1,98 @@
+#[cfg(test)]
+tokio::task_local! {
+    /// Machine state reported within `simulating`: screen locked, and the battery
+    static SIMULATED: (Option<bool>, Option<Battery>);
+}
+
+/// Whether the user's graphical session is locked, if that can be told
+///
+/// On Linux this asks logind (over D-Bus, through `loginctl`) for the
+/// session's LockedHint, which screen lockers set. Elsewhere it is unknown.
+pub async fn is_screen_locked() -> Option<bool> {
+    #[cfg(test)]
+    if let Ok((locked, _)) = SIMULATED.try_with(|state| *state) {
+        return locked;
+    }
+    read_screen_locked().await
+}
+
+#[cfg(target_os = "linux")]
+async fn read_screen_locked() -> Option<bool> {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "self".to_string());
+    let output = tokio::process::Command::new("loginctl")
+        .args(["show-session", &session, "--property=LockedHint", "--value"])
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn read_screen_locked() -> Option<bool> {
+    None
+}
+
+/// Charge of a battery the machine is running on
+#[derive(Clone, Copy, Debug)]
+pub struct Battery {
+    /// Whether the machine draws from the battery rather than external power
+    pub discharging: bool,
+    /// Remaining charge, 0 to 100
+    pub percent: u8,
+}
+
+/// State of the first battery, if the machine has one and it can be read
+///
+/// On Linux this is read from the kernel's power supply class in sysfs, the
+/// same source UPower reports from. Elsewhere it is unknown.
+pub fn battery_state() -> Option<Battery> {
+    #[cfg(test)]
+    if let Ok((_, battery)) = SIMULATED.try_with(|state| *state) {
+        return battery;
+    }
+    read_battery_state()
+}
+
+#[cfg(target_os = "linux")]
+fn read_battery_state() -> Option<Battery> {
+    let read = |path: std::path::PathBuf| std::fs::read_to_string(path).ok();
+
+    let supplies = std::fs::read_dir("/sys/class/power_supply").ok()?;
+    let mut paths: Vec<_> = supplies.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    paths.into_iter().find_map(|path| {
+        if read(path.join("type"))?.trim() != "Battery" {
+            return None;
+        }
+        let status = read(path.join("status"))?;
+        let percent = read(path.join("capacity"))?.trim().parse().ok()?;
+        Some(Battery {
+            discharging: status.trim() == "Discharging",
+            percent,
+        })
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_battery_state() -> Option<Battery> {
+    None
+}
+
+/// Run `future` as if the screen were `locked` and the machine had `battery`, instead of reading either
+#[cfg(test)]
+pub async fn simulating<T>(
+    locked: Option<bool>,
+    battery: Option<Battery>,
+    future: impl Future<Output = T>,
+) -> T {
+    SIMULATED.scope((locked, battery), future).await
+}
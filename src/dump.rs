@@ -0,0 +1,220 @@
+use serde_json::{Value, json};
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::message::KeyConstraint;
+use ssh_agent_lib::proto::{Credential, Request, Response};
+use ssh_agent_lib::ssh_key::HashAlg;
+use ssh_agent_lib::ssh_key::public::KeyData;
+
+use crate::keys::credential_comment;
+use crate::redact;
+use crate::request::{request_key, request_name};
+
+/// Print `request` to stderr as one line of JSON
+pub fn request(request: &Request) {
+    let line = json!({ "request": request_json(request) }).to_string();
+    redact::eprintln(&line);
+}
+
+/// Print the outcome of a request to stderr as one line of JSON
+pub fn response(result: &Result<Response, AgentError>) {
+    redact::eprintln(&result_json(result).to_string());
+}
+
+/// The outcome of a request as `{"response": ...}` or `{"error": ...}`
+pub fn result_json(result: &Result<Response, AgentError>) -> Value {
+    match result {
+        Ok(response) => json!({ "response": response_json(response) }),
+        Err(e) => json!({ "error": e.to_string() }),
+    }
+}
+
+/// Decoded request fields, with private keys, PINs, passphrases and signed data redacted
+pub fn request_json(request: &Request) -> Value {
+    let mut json = json!({ "type": request_name(request) });
+    if let Some(key) = request_key(request) {
+        json["key"] = key_json(&key);
+    }
+
+    match request {
+        Request::SignRequest(sign) => {
+            json["data"] = redacted(sign.data.len());
+            json["flags"] = sign.flags.into();
+        }
+        Request::AddIdentity(add) => json["credential"] = credential_json(&add.credential),
+        Request::AddIdConstrained(add) => {
+            json["credential"] = credential_json(&add.identity.credential);
+            json["constraints"] = constraints_json(&add.constraints);
+        }
+        Request::AddSmartcardKey(key) | Request::RemoveSmartcardKey(key) => {
+            json["id"] = key.id.clone().into();
+            json["pin"] = "<redacted>".into();
+        }
+        Request::AddSmartcardKeyConstrained(add) => {
+            json["id"] = add.key.id.clone().into();
+            json["pin"] = "<redacted>".into();
+            json["constraints"] = constraints_json(&add.constraints);
+        }
+        Request::Lock(_) | Request::Unlock(_) => json["passphrase"] = "<redacted>".into(),
+        Request::Extension(extension) => {
+            json["name"] = extension.name.clone().into();
+            json["details"] = redacted(extension.details.as_ref().len());
+        }
+        Request::RequestIdentities | Request::RemoveIdentity(_) | Request::RemoveAllIdentities => {}
+    }
+    json
+}
+
+fn response_json(response: &Response) -> Value {
+    match response {
+        Response::Failure => json!({ "type": "failure" }),
+        Response::Success => json!({ "type": "success" }),
+        Response::IdentitiesAnswer(identities) => json!({
+            "type": "identities",
+            "identities": identities
+                .iter()
+                .map(|id| json!({ "key": key_json(&id.pubkey), "comment": id.comment }))
+                .collect::<Vec<_>>(),
+        }),
+        Response::SignResponse(signature) => json!({
+            "type": "signature",
+            "algorithm": signature.algorithm().to_string(),
+            "signature": redacted(signature.as_bytes().len()),
+        }),
+        Response::ExtensionFailure => json!({ "type": "extension-failure" }),
+        Response::ExtensionResponse(extension) => json!({
+            "type": "extension-response",
+            "name": extension.name,
+            "details": redacted(extension.details.as_ref().len()),
+        }),
+    }
+}
+
+fn key_json(key: &KeyData) -> Value {
+    json!({
+        "algorithm": key.algorithm().to_string(),
+        "fingerprint": key.fingerprint(HashAlg::Sha256).to_string(),
+    })
+}
+
+fn credential_json(credential: &Credential) -> Value {
+    let kind = match credential {
+        Credential::Key { .. } => "key",
+        Credential::Cert { .. } => "cert",
+    };
+    json!({
+        "kind": kind,
+        "comment": credential_comment(credential),
+        "private_key": "<redacted>",
+    })
+}
+
+fn constraints_json(constraints: &[KeyConstraint]) -> Value {
+    constraints
+        .iter()
+        .map(|constraint| match constraint {
+            KeyConstraint::Lifetime(secs) => json!({ "lifetime": secs }),
+            KeyConstraint::Confirm => json!("confirm"),
+            KeyConstraint::Extension(extension) => json!({
+                "extension": extension.name,
+                "details": redacted(extension.details.as_ref().len()),
+            }),
+        })
+        .collect()
+}
+
+/// Placeholder for a value that is not printed, keeping its length for diagnosis
+fn redacted(len: usize) -> Value {
+    format!("<redacted {len} bytes>").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::proto::{AddIdentity, AddIdentityConstrained, SignRequest, SmartcardKey};
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey, Signature};
+
+    fn private_key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    #[test]
+    fn redacts_signed_data() {
+        let key = private_key();
+        let request = Request::SignRequest(SignRequest {
+            pubkey: key.public_key().key_data().clone(),
+            data: b"secret data".to_vec(),
+            flags: 2,
+        });
+        let json = request_json(&request);
+        assert_eq!(json["type"], "sign");
+        assert_eq!(
+            json["key"]["fingerprint"],
+            key.fingerprint(HashAlg::Sha256).to_string()
+        );
+        assert_eq!(json["data"], "<redacted 11 bytes>");
+        assert_eq!(json["flags"], 2);
+    }
+
+    #[test]
+    fn redacts_private_keys() {
+        let key = private_key();
+        let request = Request::AddIdConstrained(AddIdentityConstrained {
+            identity: AddIdentity {
+                credential: Credential::Key {
+                    privkey: key.key_data().clone(),
+                    comment: "work".to_string(),
+                },
+            },
+            constraints: vec![KeyConstraint::Lifetime(60), KeyConstraint::Confirm],
+        });
+        let json = request_json(&request);
+        assert_eq!(json["credential"]["kind"], "key");
+        assert_eq!(json["credential"]["comment"], "work");
+        assert_eq!(json["credential"]["private_key"], "<redacted>");
+        assert_eq!(json["constraints"], json!([{ "lifetime": 60 }, "confirm"]));
+    }
+
+    #[test]
+    fn redacts_pins_and_passphrases() {
+        let add = Request::AddSmartcardKey(SmartcardKey {
+            id: "/usr/lib/opensc-pkcs11.so".to_string(),
+            pin: "123456".to_string().into(),
+        });
+        let json = request_json(&add);
+        assert_eq!(json["id"], "/usr/lib/opensc-pkcs11.so");
+        assert_eq!(json["pin"], "<redacted>");
+        assert!(!json.to_string().contains("123456"));
+
+        for request in [
+            Request::Lock("hunter2".into()),
+            Request::Unlock("hunter2".into()),
+        ] {
+            let json = request_json(&request);
+            assert_eq!(json["passphrase"], "<redacted>");
+            assert!(!json.to_string().contains("hunter2"));
+        }
+    }
+
+    #[test]
+    fn redacts_signatures() {
+        let signature = Signature::new(Algorithm::Ed25519, vec![7; 64]).unwrap();
+        let json = result_json(&Ok(Response::SignResponse(signature)));
+        assert_eq!(
+            json,
+            json!({
+                "response": {
+                    "type": "signature",
+                    "algorithm": "ssh-ed25519",
+                    "signature": "<redacted 64 bytes>",
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let json = result_json(&Err(AgentError::Failure));
+        assert_eq!(json, json!({ "error": AgentError::Failure.to_string() }));
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why the proxy could not serve, either one connection or at all
+#[derive(Debug)]
+pub enum ProxyError {
+    /// The backend ssh-agent at `path` cannot be connected to
+    Backend { path: PathBuf, source: io::Error },
+    /// The proxy socket stopped accepting connections
+    Accept(io::Error),
+}
+
+impl ProxyError {
+    /// Whether the backend ssh-agent is gone, rather than this one connection failing
+    #[cfg_attr(windows, allow(dead_code))]
+    pub fn backend_gone(&self) -> bool {
+        matches!(self, Self::Backend { source, .. }
+            if matches!(source.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused))
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Backend { path, source } => {
+                let path = path.display();
+                write!(f, "Cannot connect to ssh-agent at {path}: {source}")
+            }
+            Self::Accept(source) => write!(f, "Cannot accept connections: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Backend { source, .. } | Self::Accept(source) => Some(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    fn backend(kind: io::ErrorKind) -> ProxyError {
+        ProxyError::Backend {
+            path: PathBuf::from("/run/agent.sock"),
+            source: kind.into(),
+        }
+    }
+
+    #[test]
+    fn only_a_missing_or_refusing_backend_is_gone() {
+        assert!(backend(io::ErrorKind::NotFound).backend_gone());
+        assert!(backend(io::ErrorKind::ConnectionRefused).backend_gone());
+        assert!(!backend(io::ErrorKind::PermissionDenied).backend_gone());
+        assert!(!ProxyError::Accept(io::ErrorKind::NotFound.into()).backend_gone());
+    }
+
+    #[test]
+    fn names_the_backend_and_keeps_the_cause() {
+        let e = backend(io::ErrorKind::NotFound);
+        assert!(
+            e.to_string()
+                .starts_with("Cannot connect to ssh-agent at /run/agent.sock: ")
+        );
+        let source = e.source().and_then(|s| s.downcast_ref::<io::Error>());
+        assert_eq!(source.map(io::Error::kind), Some(io::ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,59 @@
+use serde::Serialize;
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::proto::extension::QueryResponse;
+use ssh_agent_lib::proto::{Extension, Request, Response};
+use std::collections::BTreeSet;
+use std::sync::OnceLock;
+
+/// Extensions the backend ssh-agent supports, as it answered the `query` extension
+///
+/// Until the probe has answered, or if the backend does not implement
+/// `query`, nothing is known and every extension is forwarded.
+#[derive(Debug, Serialize)]
+pub struct BackendExtensions {
+    probe: bool,
+    #[serde(skip)]
+    supported: OnceLock<BTreeSet<String>>,
+}
+
+impl BackendExtensions {
+    pub fn new(probe: bool) -> Self {
+        Self {
+            probe,
+            supported: OnceLock::new(),
+        }
+    }
+
+    pub fn probe_enabled(&self) -> bool {
+        self.probe
+    }
+
+    /// Ask the backend behind `session` which extensions it supports and remember the answer
+    ///
+    /// Returns the supported extensions, or `None` if the backend did not say.
+    pub async fn probe(&self, session: &mut dyn Session) -> Option<&BTreeSet<String>> {
+        let query = Extension {
+            name: "query".to_string(),
+            details: Vec::new().into(),
+        };
+        let Ok(Response::ExtensionResponse(answer)) =
+            session.handle(Request::Extension(query)).await
+        else {
+            return None;
+        };
+        let Ok(Some(QueryResponse { extensions })) = answer.parse_message::<QueryResponse>() else {
+            return None;
+        };
+        let supported = self
+            .supported
+            .get_or_init(|| extensions.into_iter().collect());
+        Some(supported)
+    }
+
+    /// Whether the backend is known not to support the extension `name`
+    pub fn unsupported(&self, name: &str) -> bool {
+        self.supported
+            .get()
+            .is_some_and(|supported| !supported.contains(name))
+    }
+}
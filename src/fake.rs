@@ -0,0 +1,71 @@
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+use ssh_agent_lib::proto::{Identity, Response};
+use ssh_agent_lib::ssh_key::public::KeyData;
+use ssh_agent_lib::ssh_key::{HashAlg, PublicKey};
+
+/// How sign requests for a synthetic identity are answered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FakeSignResponse {
+    /// SSH_AGENT_FAILURE, as for a refused request
+    #[default]
+    Failure,
+    /// SSH_AGENT_EXTENSION_FAILURE, which clients may handle differently
+    ExtensionFailure,
+}
+
+/// Public-only identities listed after the backend's, for testing clients against many keys
+///
+/// The backend never sees them; signing with one is answered by the proxy
+/// with the configured failure.
+#[derive(Debug, Serialize)]
+pub struct FakeIdentities {
+    #[serde(serialize_with = "fingerprints")]
+    identities: Vec<Identity>,
+    sign_response: FakeSignResponse,
+}
+
+impl FakeIdentities {
+    pub fn new(keys: Vec<PublicKey>, sign_response: FakeSignResponse) -> Self {
+        let identities = keys
+            .into_iter()
+            .map(|key| Identity {
+                comment: key.comment().to_string(),
+                pubkey: key.into(),
+            })
+            .collect();
+        Self {
+            identities,
+            sign_response,
+        }
+    }
+
+    /// Add the synthetic identities the backend did not list itself to `identities`
+    pub fn extend(&self, identities: &mut Vec<Identity>) {
+        for fake in &self.identities {
+            if !identities.iter().any(|id| id.pubkey == fake.pubkey) {
+                identities.push(fake.clone());
+            }
+        }
+    }
+
+    pub fn contains(&self, key: &KeyData) -> bool {
+        self.identities.iter().any(|id| &id.pubkey == key)
+    }
+
+    /// Answer to a sign request for a synthetic identity
+    pub fn sign_response(&self) -> Response {
+        match self.sign_response {
+            FakeSignResponse::Failure => Response::Failure,
+            FakeSignResponse::ExtensionFailure => Response::ExtensionFailure,
+        }
+    }
+}
+
+fn fingerprints<S: Serializer>(identities: &[Identity], serializer: S) -> Result<S::Ok, S::Error> {
+    let fingerprints = identities
+        .iter()
+        .map(|id| id.pubkey.fingerprint(HashAlg::Sha256).to_string());
+    serializer.collect_seq(fingerprints)
+}
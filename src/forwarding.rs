@@ -0,0 +1,16 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// What to do when a key is used on a connection forwarded from another host
+///
+/// A connection counts as forwarded once a session-bind on it reports forwarding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnForwarded {
+    /// Sign, logging a warning
+    Warn,
+    /// Sign only after the user approves through SSH_ASKPASS
+    Confirm,
+    /// Refuse to sign
+    Deny,
+}
@@ -0,0 +1,116 @@
+use log::{error, warn};
+use ssh_agent_lib::agent::ListeningSocket;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::sockpath;
+
+/// How often the socket file is checked for
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+type Bind = Box<dyn Fn(&Path) -> io::Result<UnixListener> + Send>;
+
+/// Unix listener that binds its socket again when the file is deleted
+///
+/// Deleting a socket file leaves its listener open but unreachable, so
+/// clients cannot connect although the proxy is still running. Its
+/// directory is created again too, in case a reaper removed it as well.
+pub struct HealingListener {
+    listener: UnixListener,
+    path: PathBuf,
+    bind: Bind,
+    checks: Interval,
+}
+
+impl fmt::Debug for HealingListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HealingListener")
+            .field("listener", &self.listener)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HealingListener {
+    /// Watch `listener`, bound at `path`, and replace it with `bind(path)` when the file is gone
+    pub fn new(
+        listener: UnixListener,
+        path: PathBuf,
+        bind: impl Fn(&Path) -> io::Result<UnixListener> + Send + 'static,
+    ) -> Self {
+        let mut checks = tokio::time::interval(CHECK_INTERVAL);
+        checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            listener,
+            path,
+            bind: Box::new(bind),
+            checks,
+        }
+    }
+
+    fn heal(&mut self) {
+        if self.path.symlink_metadata().is_ok() {
+            return;
+        }
+        let path = self.path.display();
+        warn!("Proxy socket {path} was deleted, binding it again");
+        let bound = sockpath::prepare(&self.path)
+            .map_err(io::Error::other)
+            .and_then(|()| (self.bind)(&self.path));
+        match bound {
+            Ok(listener) => self.listener = listener,
+            // Checked again on the next tick
+            Err(e) => error!("Failed to bind {path} again: {e}"),
+        }
+    }
+}
+
+#[ssh_agent_lib::async_trait]
+impl ListeningSocket for HealingListener {
+    type Stream = UnixStream;
+
+    async fn accept(&mut self) -> io::Result<Self::Stream> {
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => return accepted.map(|(stream, _addr)| stream),
+                _ = self.checks.tick() => self.heal(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn binds_a_deleted_socket_again() {
+        let dir = std::env::temp_dir().join(format!("ssh-agent-ac-{}-heal", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("agent.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut healing = HealingListener::new(listener, path.clone(), |path: &Path| {
+            UnixListener::bind(path)
+        });
+
+        // A reaper cleaning up the directory, socket and all
+        std::fs::remove_dir_all(&dir).unwrap();
+        healing.heal();
+        assert!(path.exists());
+
+        let client = UnixStream::connect(&path).await.unwrap();
+        let accepted = tokio::time::timeout(Duration::from_secs(5), healing.accept()).await;
+        assert!(accepted.unwrap().is_ok());
+        drop(client);
+
+        // A socket still in place is left alone
+        healing.heal();
+        let _client = UnixStream::connect(&path).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
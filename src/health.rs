@@ -0,0 +1,159 @@
+use log::{info, warn};
+use ssh_agent_lib::proto::{Request, Response};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::backend;
+
+/// Consecutive failed probes before the backend is considered unhealthy
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// Backend health as last determined by the monitor
+pub struct Health {
+    healthy: AtomicBool,
+}
+
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+}
+
+/// Probe the backends every `interval`, flipping the returned health state on failure and recovery
+///
+/// With several backends, one responsive agent is enough to count as healthy.
+pub fn spawn_monitor(backend_socket_paths: Vec<PathBuf>, interval: Duration) -> Arc<Health> {
+    let health = Arc::new(Health {
+        healthy: AtomicBool::new(true),
+    });
+
+    let monitored = health.clone();
+    tokio::spawn(async move {
+        let mut failures = 0;
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let mut ok = false;
+            for path in &backend_socket_paths {
+                if tokio::time::timeout(interval, probe(path))
+                    .await
+                    .unwrap_or(false)
+                {
+                    ok = true;
+                    break;
+                }
+            }
+
+            if ok {
+                if !monitored.is_healthy() {
+                    info!("Backend ssh-agent is healthy again");
+                }
+                failures = 0;
+                monitored.healthy.store(true, Ordering::Relaxed);
+            } else {
+                failures += 1;
+                if failures == FAILURE_THRESHOLD {
+                    warn!(
+                        "Backend ssh-agent failed {failures} health checks; failing requests until it recovers"
+                    );
+                    monitored.healthy.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+    });
+
+    health
+}
+
+/// Wait until one of the backends answers a probe, retrying every second
+///
+/// Without backend sockets (the in-process agent) there is nothing to wait for.
+pub async fn wait_for_backend(backend_socket_paths: &[PathBuf]) {
+    if backend_socket_paths.is_empty() {
+        return;
+    }
+    loop {
+        for path in backend_socket_paths {
+            if tokio::time::timeout(Duration::from_secs(5), probe(path))
+                .await
+                .unwrap_or(false)
+            {
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Whether a fresh connection to the backend can list identities
+async fn probe(backend_socket_path: &Path) -> bool {
+    let Ok(mut backend) = backend::connect(backend_socket_path) else {
+        return false;
+    };
+    matches!(
+        backend.handle(Request::RequestIdentities).await,
+        Ok(Response::IdentitiesAnswer(_))
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::memory::InProcessAgent;
+    use tokio::net::UnixListener;
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Serve an empty in-process agent at `path` until the returned task is aborted
+    fn serve(path: &Path) -> tokio::task::JoinHandle<()> {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        tokio::spawn(async move {
+            let _ = ssh_agent_lib::agent::listen(listener, InProcessAgent::default()).await;
+        })
+    }
+
+    /// Wait up to two seconds for the monitor to report `healthy`
+    async fn reaches(health: &Health, healthy: bool) -> bool {
+        for _ in 0..100 {
+            if health.is_healthy() == healthy {
+                return true;
+            }
+            tokio::time::sleep(INTERVAL).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn flips_when_the_backend_dies_and_recovers() {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-health.sock", std::process::id()));
+        let server = serve(&path);
+        let health = spawn_monitor(vec![path.clone()], INTERVAL);
+        tokio::time::sleep(INTERVAL * 5).await;
+        assert!(health.is_healthy());
+
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+        assert!(reaches(&health, false).await);
+
+        let server = serve(&path);
+        assert!(reaches(&health, true).await);
+        server.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn stays_healthy_through_fewer_failures_than_the_threshold() {
+        let path = std::env::temp_dir().join(format!(
+            "ssh-agent-ac-{}-missing-health.sock",
+            std::process::id()
+        ));
+        let health = spawn_monitor(vec![path], Duration::from_millis(200));
+        // The first probe runs at once; the threshold is reached only after two more intervals
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(health.is_healthy());
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Serialize;
+use ssh_agent_lib::proto::{Extension, Request};
+use ssh_agent_lib::ssh_encoding::Encode;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::health::Health;
+use crate::keyage::{KeyAge, KeyAges};
+use crate::policy::Policy;
+
+/// Name of the extension answered by the proxy itself
+pub const INFO_EXTENSION: &str = "info@ssh-agent-ac";
+
+/// Request counters shared by all sessions
+pub struct Stats {
+    started: Instant,
+    requests: AtomicU64,
+    signs: AtomicU64,
+    adds: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            signs: AtomicU64::new(0),
+            adds: AtomicU64::new(0),
+        }
+    }
+
+    /// Time since the proxy started
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn record(&self, request: &Request) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match request {
+            Request::SignRequest(_) => {
+                self.signs.fetch_add(1, Ordering::Relaxed);
+            }
+            Request::AddIdentity(_) | Request::AddIdConstrained(_) => {
+                self.adds.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Info<'a> {
+    version: &'static str,
+    policy_hash: String,
+    /// The policy being enforced, as hashed into `policy_hash`
+    policy: &'a Policy,
+    uptime_secs: u64,
+    requests: u64,
+    signs: u64,
+    adds: u64,
+    /// Last result of the backend health monitor, if it runs
+    backend_healthy: Option<bool>,
+    /// Keys added through this proxy, how long they have been loaded and whether they need confirmation
+    keys: Vec<KeyAge>,
+    /// Seconds since the epoch of each key's most recent signature, by fingerprint
+    last_used: BTreeMap<String, u64>,
+}
+
+/// Build the info extension response: a single SSH string holding a JSON object
+pub fn info_response(
+    policy: &Policy,
+    stats: &Stats,
+    key_ages: &KeyAges,
+    health: Option<&Health>,
+) -> Extension {
+    let info = Info {
+        version: env!("CARGO_PKG_VERSION"),
+        policy_hash: policy.hash(),
+        policy,
+        uptime_secs: stats.uptime().as_secs(),
+        requests: stats.requests.load(Ordering::Relaxed),
+        signs: stats.signs.load(Ordering::Relaxed),
+        adds: stats.adds.load(Ordering::Relaxed),
+        backend_healthy: health.map(Health::is_healthy),
+        keys: key_ages.ages(),
+        last_used: key_ages.last_used(),
+    };
+    let json = serde_json::to_string(&info).expect("info serializes to JSON");
+
+    let mut details = Vec::new();
+    json.encode(&mut details)
+        .expect("encoding into a Vec cannot fail");
+
+    Extension {
+        name: INFO_EXTENSION.to_string(),
+        details: details.into(),
+    }
+}
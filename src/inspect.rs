@@ -0,0 +1,77 @@
+/// Whether `data` looks like a DER, TLS or TLS 1.3 structure rather than SSH data
+///
+/// SSH signs user-authentication requests (starting with the length of the
+/// session identifier) and SSHSIG blobs, neither of which matches these
+/// patterns. The check is heuristic, though: arbitrary data signed through the
+/// agent could match by accident and be denied.
+pub fn looks_like_foreign_protocol(data: &[u8]) -> bool {
+    is_der_sequence(data) || is_tls_record(data) || is_tls13_signed_content(data)
+}
+
+/// A single ASN.1 DER SEQUENCE spanning the whole payload, e.g. an X.509 TBSCertificate
+fn is_der_sequence(data: &[u8]) -> bool {
+    let [0x30, first, rest @ ..] = data else {
+        return false;
+    };
+    let (len, content) = match *first {
+        len @ 0..=0x7f => (usize::from(len), rest),
+        long @ 0x81..=0x84 => {
+            let n = usize::from(long & 0x7f);
+            if rest.len() < n {
+                return false;
+            }
+            let (len, content) = rest.split_at(n);
+            let len = len.iter().fold(0, |acc, &b| (acc << 8) | usize::from(b));
+            (len, content)
+        }
+        _ => return false,
+    };
+    len == content.len()
+}
+
+/// A TLS handshake record header (content type 22, protocol version 3.x)
+fn is_tls_record(data: &[u8]) -> bool {
+    matches!(data, [0x16, 0x03, 0x00..=0x04, ..])
+}
+
+/// TLS 1.3 CertificateVerify content: 64 spaces followed by a context string
+fn is_tls13_signed_content(data: &[u8]) -> bool {
+    data.len() > 64 && data[..64].iter().all(|&b| b == 0x20)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_der_sequences() {
+        assert!(looks_like_foreign_protocol(&[0x30, 0x03, 1, 2, 3]));
+        let mut long = vec![0x30, 0x82, 0x01, 0x00];
+        long.resize(4 + 0x100, 0);
+        assert!(looks_like_foreign_protocol(&long));
+        // A length that does not span the payload is not one DER structure
+        assert!(!looks_like_foreign_protocol(&[0x30, 0x05, 1, 2, 3]));
+        assert!(!looks_like_foreign_protocol(&[0x30, 0x82, 0x01]));
+    }
+
+    #[test]
+    fn recognizes_tls_structures() {
+        assert!(looks_like_foreign_protocol(&[0x16, 0x03, 0x03, 0x00, 0x10]));
+        let mut verify = vec![0x20; 64];
+        verify.extend_from_slice(b"TLS 1.3, server CertificateVerify\0");
+        assert!(looks_like_foreign_protocol(&verify));
+        assert!(!looks_like_foreign_protocol(&[0x20; 64]));
+    }
+
+    #[test]
+    fn passes_ssh_data() {
+        // User authentication: string session identifier, byte SSH_MSG_USERAUTH_REQUEST, ...
+        let mut userauth = vec![0, 0, 0, 32];
+        userauth.extend_from_slice(&[0xab; 32]);
+        userauth.extend_from_slice(&[50, 0, 0, 0, 3]);
+        userauth.extend_from_slice(b"git");
+        assert!(!looks_like_foreign_protocol(&userauth));
+        assert!(!looks_like_foreign_protocol(b"SSHSIG\0\0\0\x03git"));
+        assert!(!looks_like_foreign_protocol(&[]));
+    }
+}
@@ -0,0 +1,282 @@
+use log::warn;
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::state::Store;
+
+/// Section of the state store holding last-used times
+pub const STATE_SECTION: &str = "last_used";
+
+struct Loaded {
+    at: Instant,
+    /// Added with a lifetime, so the backend removes it by itself
+    expires: bool,
+    /// Added with a confirm constraint, so every use prompts
+    confirm: bool,
+    warned: bool,
+    /// Signed with since it was added
+    used: bool,
+    warned_unused: bool,
+}
+
+/// How long each key added through the proxy has been loaded, and when keys last signed
+pub struct KeyAges {
+    warn_after: Option<Duration>,
+    warn_unused_after: Option<Duration>,
+    loaded: Mutex<BTreeMap<Fingerprint, Loaded>>,
+    /// Seconds since the epoch of each key's most recent signature
+    last_used: Mutex<BTreeMap<Fingerprint, u64>>,
+    store: Option<Store>,
+}
+
+/// Load age of one key, as reported by the info extension
+#[derive(Serialize)]
+pub struct KeyAge {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    loaded_secs: u64,
+    /// Whether signing with the key asks the user for confirmation first
+    confirm_required: bool,
+}
+
+impl KeyAges {
+    /// Track keys, warning about those loaded longer than `warn_after` without a lifetime
+    /// or longer than `warn_unused_after` without signing
+    ///
+    /// Last-used times are resumed from `store`, and updated in it after every signature.
+    pub fn new(
+        warn_after: Option<Duration>,
+        warn_unused_after: Option<Duration>,
+        store: Option<Store>,
+    ) -> Self {
+        let last_used = store
+            .as_ref()
+            .and_then(|store| store.get(STATE_SECTION))
+            .map(load)
+            .unwrap_or_default();
+
+        Self {
+            warn_after,
+            warn_unused_after,
+            loaded: Mutex::new(BTreeMap::new()),
+            last_used: Mutex::new(last_used),
+            store,
+        }
+    }
+
+    pub fn added(&self, fingerprint: Fingerprint, expires: bool, confirm: bool) {
+        self.lock().insert(
+            fingerprint,
+            Loaded {
+                at: Instant::now(),
+                expires,
+                confirm,
+                warned: false,
+                used: false,
+                warned_unused: false,
+            },
+        );
+    }
+
+    pub fn removed(&self, fingerprint: &Fingerprint) {
+        self.lock().remove(fingerprint);
+    }
+
+    pub fn removed_all(&self) {
+        self.lock().clear();
+    }
+
+    /// Forget keys the backend no longer holds, e.g. because their lifetime ran out
+    pub fn retain_listed(&self, listed: &[Fingerprint]) {
+        self.lock()
+            .retain(|fingerprint, _| listed.contains(fingerprint));
+    }
+
+    /// Warn once about `fingerprint` if it has been loaded for too long without a lifetime,
+    /// and once if it has been loaded for too long without signing
+    pub fn check(&self, fingerprint: &Fingerprint) {
+        self.check_at(fingerprint, Instant::now());
+    }
+
+    fn check_at(&self, fingerprint: &Fingerprint, now: Instant) {
+        let mut loaded = self.lock();
+        let Some(key) = loaded.get_mut(fingerprint) else {
+            return;
+        };
+        let age = now.saturating_duration_since(key.at);
+
+        if let Some(warn_after) = self.warn_after
+            && !key.expires
+            && !key.warned
+            && age > warn_after
+        {
+            key.warned = true;
+            warn!(
+                "Key {fingerprint} has been loaded for {}s without a lifetime",
+                age.as_secs()
+            );
+        }
+        if let Some(warn_after) = self.warn_unused_after
+            && !key.used
+            && !key.warned_unused
+            && age > warn_after
+        {
+            key.warned_unused = true;
+            warn!(
+                "Key {fingerprint} has been loaded for {}s and never used",
+                age.as_secs()
+            );
+        }
+    }
+
+    /// Record a signature made with `fingerprint`
+    pub fn signed(&self, fingerprint: &Fingerprint) {
+        if let Some(key) = self.lock().get_mut(fingerprint) {
+            key.used = true;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut last_used = self.last_used.lock().unwrap_or_else(|e| e.into_inner());
+        last_used.insert(*fingerprint, now);
+
+        if let Some(store) = &self.store {
+            let state: BTreeMap<String, u64> = last_used
+                .iter()
+                .map(|(fingerprint, secs)| (fingerprint.to_string(), *secs))
+                .collect();
+            let json = serde_json::to_value(state).expect("last-used state serializes to JSON");
+            store.set(STATE_SECTION, json);
+        }
+    }
+
+    /// Seconds since the epoch of each key's most recent signature, by fingerprint
+    pub fn last_used(&self) -> BTreeMap<String, u64> {
+        let last_used = self.last_used.lock().unwrap_or_else(|e| e.into_inner());
+        last_used
+            .iter()
+            .map(|(fingerprint, secs)| (fingerprint.to_string(), *secs))
+            .collect()
+    }
+
+    pub fn ages(&self) -> Vec<KeyAge> {
+        self.ages_at(Instant::now())
+    }
+
+    fn ages_at(&self, now: Instant) -> Vec<KeyAge> {
+        self.lock()
+            .iter()
+            .map(|(fingerprint, key)| KeyAge {
+                fingerprint: *fingerprint,
+                loaded_secs: now.saturating_duration_since(key.at).as_secs(),
+                confirm_required: key.confirm,
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<Fingerprint, Loaded>> {
+        self.loaded.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Parse saved last-used times, skipping entries whose fingerprint does not parse
+fn load(saved: serde_json::Value) -> BTreeMap<Fingerprint, u64> {
+    let state: BTreeMap<String, u64> = serde_json::from_value(saved).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable last-used state: {e}");
+        BTreeMap::new()
+    });
+    state
+        .into_iter()
+        .filter_map(|(fingerprint, secs)| Some((fingerprint.parse().ok()?, secs)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::JsonFile;
+    use std::fs;
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn loaded_secs(ages: &KeyAges, now: Instant) -> Vec<u64> {
+        ages.ages_at(now)
+            .iter()
+            .map(|age| age.loaded_secs)
+            .collect()
+    }
+
+    #[test]
+    fn ages_grow_while_loaded() {
+        let ages = KeyAges::new(None, None, None);
+        let key = KEY.parse().unwrap();
+        ages.added(key, false, true);
+        let now = Instant::now();
+        assert_eq!(loaded_secs(&ages, now + HOUR), [3600]);
+        assert_eq!(loaded_secs(&ages, now + 2 * HOUR), [7200]);
+        assert!(ages.ages_at(now)[0].confirm_required);
+        ages.removed(&key);
+        assert!(ages.ages_at(now).is_empty());
+    }
+
+    #[test]
+    fn warns_once_about_keys_loaded_too_long_without_a_lifetime() {
+        let ages = KeyAges::new(Some(HOUR), None, None);
+        let (key, expiring) = (KEY.parse().unwrap(), Fingerprint::Sha256([7; 32]));
+        ages.added(key, false, false);
+        ages.added(expiring, true, false);
+        let now = Instant::now();
+        let warned = |fingerprint| ages.lock()[fingerprint].warned;
+
+        ages.check_at(&key, now + HOUR / 2);
+        assert!(!warned(&key));
+        ages.check_at(&key, now + 2 * HOUR);
+        ages.check_at(&expiring, now + 2 * HOUR);
+        assert!(warned(&key));
+        assert!(!warned(&expiring));
+    }
+
+    #[test]
+    fn warns_about_keys_never_used() {
+        let ages = KeyAges::new(None, Some(HOUR), None);
+        let (key, used) = (KEY.parse().unwrap(), Fingerprint::Sha256([7; 32]));
+        ages.added(key, true, false);
+        ages.added(used, true, false);
+        ages.signed(&used);
+        let now = Instant::now();
+        ages.check_at(&key, now + 2 * HOUR);
+        ages.check_at(&used, now + 2 * HOUR);
+        assert!(ages.lock()[&key].warned_unused);
+        assert!(!ages.lock()[&used].warned_unused);
+        assert!(ages.last_used().contains_key(&used.to_string()));
+    }
+
+    #[test]
+    fn last_used_times_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "ssh-agent-ac-{}-last-used.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let key: Fingerprint = KEY.parse().unwrap();
+
+        let store = JsonFile::open_section(path.clone(), STATE_SECTION);
+        let ages = KeyAges::new(None, None, Some(store.clone()));
+        ages.signed(&key);
+        let used = ages.last_used();
+        assert!(used.contains_key(KEY));
+        store.flush().unwrap();
+
+        let store = JsonFile::open_section(path.clone(), STATE_SECTION);
+        let restarted = KeyAges::new(None, None, Some(store));
+        assert_eq!(restarted.last_used(), used);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::str::FromStr;
+
+/// What to do with a sign request the user must confirm when no confirmer can ask
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Unconfirmable {
+    /// Refuse to sign, for keys that must never be used unattended
+    Deny,
+    /// Sign, logging a warning
+    Allow,
+}
+
+/// A key whose every signature must be confirmed
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyConfirm {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    otherwise: Unconfirmable,
+}
+
+impl FromStr for KeyConfirm {
+    type Err = String;
+
+    /// Parse `<FINGERPRINT>=deny` or `<FINGERPRINT>=allow`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fingerprint, otherwise) = s
+            .rsplit_once('=')
+            .ok_or("expected <FINGERPRINT>=deny or <FINGERPRINT>=allow")?;
+
+        let fingerprint = fingerprint.parse().map_err(|e| format!("{e}"))?;
+        let otherwise = match otherwise {
+            "deny" => Unconfirmable::Deny,
+            "allow" => Unconfirmable::Allow,
+            _ => return Err(format!("{otherwise}: expected deny or allow")),
+        };
+
+        Ok(Self {
+            fingerprint,
+            otherwise,
+        })
+    }
+}
+
+/// Whether signing with `fingerprint` must be confirmed, and what to do if that cannot be asked
+pub fn required(confirms: &[KeyConfirm], fingerprint: &Fingerprint) -> Option<Unconfirmable> {
+    confirms
+        .iter()
+        .find(|c| &c.fingerprint == fingerprint)
+        .map(|c| c.otherwise)
+}
@@ -0,0 +1,32 @@
+use ssh_agent_lib::proto::Credential;
+use ssh_agent_lib::ssh_key::public::{KeyData, RsaPublicKey};
+
+/// Public half of the key a credential adds
+pub fn credential_key(credential: &Credential) -> Option<KeyData> {
+    match credential {
+        Credential::Key { privkey, .. } => KeyData::try_from(privkey).ok(),
+        Credential::Cert { certificate, .. } => Some(certificate.public_key().clone()),
+    }
+}
+
+/// Comment a credential is added with
+pub fn credential_comment(credential: &Credential) -> &str {
+    match credential {
+        Credential::Key { comment, .. } | Credential::Cert { comment, .. } => comment,
+    }
+}
+
+/// Replace the comment a credential is added with
+pub fn set_credential_comment(credential: &mut Credential, new: String) {
+    match credential {
+        Credential::Key { comment, .. } | Credential::Cert { comment, .. } => *comment = new,
+    }
+}
+
+/// Size of an RSA key's modulus in bits
+pub fn rsa_bits(key: &RsaPublicKey) -> u32 {
+    match key.n.as_positive_bytes() {
+        Some([first, rest @ ..]) => rest.len() as u32 * 8 + (8 - first.leading_zeros()),
+        _ => 0,
+    }
+}
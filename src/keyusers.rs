@@ -0,0 +1,69 @@
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use ssh_agent_lib::ssh_key::public::KeyData;
+use std::str::FromStr;
+
+/// UIDs allowed to see and sign with one key
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyUsers {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    uids: Vec<u32>,
+}
+
+impl FromStr for KeyUsers {
+    type Err = String;
+
+    /// Parse `<FINGERPRINT>=<UID>[,<UID>...]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fingerprint, uids) = s
+            .rsplit_once('=')
+            .ok_or("expected <FINGERPRINT>=<UID>[,<UID>...]")?;
+
+        let fingerprint = fingerprint.parse().map_err(|e| format!("{e}"))?;
+        let uids = uids
+            .split(',')
+            .map(|uid| uid.parse().map_err(|e| format!("uid {uid}: {e}")))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { fingerprint, uids })
+    }
+}
+
+/// Whether a client with `uid` may use `fingerprint`
+///
+/// Keys without an entry are open to everyone; restricted keys are closed to
+/// clients whose UID is unknown.
+pub fn permitted(users: &[KeyUsers], fingerprint: &Fingerprint, uid: Option<u32>) -> bool {
+    users
+        .iter()
+        .filter(|u| &u.fingerprint == fingerprint)
+        .all(|u| uid.is_some_and(|uid| u.uids.contains(&uid)))
+}
+
+/// Keys a client with `uid` may sign with without confirming, as listed in `bypass`
+pub fn bypassed(bypass: &[KeyUsers], uid: Option<u32>) -> Vec<Fingerprint> {
+    let Some(uid) = uid else {
+        return Vec::new();
+    };
+    bypass
+        .iter()
+        .filter(|u| u.uids.contains(&uid))
+        .map(|u| u.fingerprint)
+        .collect()
+}
+
+/// Whether `key` is a FIDO security key (`sk-*`), whose private half stays on the hardware
+pub fn is_security_key(key: &KeyData) -> bool {
+    matches!(key, KeyData::SkEcdsaSha2NistP256(_) | KeyData::SkEd25519(_))
+}
+
+/// Whether a client with `uid` may use `key`, if security keys are restricted to `sk_users`
+///
+/// Other key types are unaffected.
+pub fn sk_permitted(sk_users: Option<&[u32]>, key: &KeyData, uid: Option<u32>) -> bool {
+    match sk_users {
+        Some(uids) if is_security_key(key) => uid.is_some_and(|uid| uids.contains(&uid)),
+        _ => true,
+    }
+}
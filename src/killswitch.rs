@@ -0,0 +1,122 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use ssh_agent_lib::proto::Request;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a kill-switch existence check is reused before touching the filesystem again
+const CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Requests blocked while the kill-switch file exists
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KillSwitchScope {
+    /// Deny every request that would reach the backend
+    All,
+    /// Deny only signing requests
+    Sign,
+}
+
+/// File whose existence freezes the agent until it is removed
+#[derive(Debug, Serialize)]
+pub struct KillSwitch {
+    path: PathBuf,
+    scope: KillSwitchScope,
+    #[serde(skip)]
+    cached: Mutex<Option<(Instant, bool)>>,
+}
+
+impl KillSwitch {
+    pub fn new(path: PathBuf, scope: KillSwitchScope) -> Self {
+        Self {
+            path,
+            scope,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Whether `request` must be denied because the kill switch is engaged
+    pub fn blocks(&self, request: &Request) -> bool {
+        self.blocks_at(request, Instant::now())
+    }
+
+    fn blocks_at(&self, request: &Request, now: Instant) -> bool {
+        let in_scope = match self.scope {
+            KillSwitchScope::All => true,
+            KillSwitchScope::Sign => matches!(request, Request::SignRequest(_)),
+        };
+        in_scope && self.engaged(now)
+    }
+
+    fn engaged(&self, now: Instant) -> bool {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((checked_at, engaged)) = *cached
+            && now.saturating_duration_since(checked_at) < CACHE_TTL
+        {
+            return engaged;
+        }
+
+        let engaged = self.path.exists();
+        *cached = Some((now, engaged));
+        engaged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::proto::SignRequest;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey};
+
+    fn kill_switch(name: &str, scope: KillSwitchScope) -> KillSwitch {
+        let path = std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        KillSwitch::new(path, scope)
+    }
+
+    #[test]
+    fn blocks_while_the_file_exists() {
+        let switch = kill_switch("kill-all", KillSwitchScope::All);
+        let now = Instant::now();
+        assert!(!switch.blocks_at(&Request::RequestIdentities, now));
+
+        std::fs::write(switch.path(), "").unwrap();
+        let later = now + CACHE_TTL;
+        assert!(switch.blocks_at(&Request::RequestIdentities, later));
+
+        std::fs::remove_file(switch.path()).unwrap();
+        assert!(!switch.blocks_at(&Request::RequestIdentities, later + CACHE_TTL));
+    }
+
+    #[test]
+    fn reuses_a_recent_check() {
+        let switch = kill_switch("kill-cached", KillSwitchScope::All);
+        let now = Instant::now();
+        assert!(!switch.blocks_at(&Request::RequestIdentities, now));
+        std::fs::write(switch.path(), "").unwrap();
+        assert!(!switch.blocks_at(&Request::RequestIdentities, now + CACHE_TTL / 2));
+        assert!(switch.blocks_at(&Request::RequestIdentities, now + CACHE_TTL));
+        std::fs::remove_file(switch.path()).unwrap();
+    }
+
+    #[test]
+    fn sign_scope_lets_other_requests_through() {
+        let switch = kill_switch("kill-sign", KillSwitchScope::Sign);
+        std::fs::write(switch.path(), "").unwrap();
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let sign = Request::SignRequest(SignRequest {
+            pubkey: key.public_key().key_data().clone(),
+            data: b"data".to_vec(),
+            flags: 0,
+        });
+        assert!(switch.blocks(&sign));
+        assert!(!switch.blocks(&Request::RequestIdentities));
+        std::fs::remove_file(switch.path()).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use log::{Level, LevelFilter};
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::{Request, Response};
+use ssh_agent_lib::ssh_key::HashAlg;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::request::{REQUEST_NAMES, request_key, request_name};
+
+/// Pseudo request name selecting the level of policy denials
+pub const DENIED: &str = "denied";
+
+/// Level one kind of request, or denials, is logged at
+#[derive(Clone, Debug)]
+pub struct LevelOverride {
+    kind: String,
+    level: LevelFilter,
+}
+
+impl FromStr for LevelOverride {
+    type Err = String;
+
+    /// Parse `<REQUEST>=<LEVEL>`, where REQUEST is a request name such as `sign` or `denied`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, level) = s.rsplit_once('=').ok_or("expected <REQUEST>=<LEVEL>")?;
+        if kind != DENIED && !REQUEST_NAMES.contains(&kind) {
+            let names = REQUEST_NAMES.join(", ");
+            return Err(format!("{kind}: expected {DENIED} or one of {names}"));
+        }
+        let level = level
+            .parse()
+            .map_err(|_| format!("{level}: expected off, error, warn, info, debug or trace"))?;
+        Ok(Self {
+            kind: kind.to_string(),
+            level,
+        })
+    }
+}
+
+/// Levels handled requests are logged at, so routine listing does not drown out signing
+///
+/// By default signs and changes to the loaded keys are logged at info,
+/// listings and extensions at debug, and denials at warn.
+#[derive(Debug, Default)]
+pub struct RequestLevels {
+    overrides: BTreeMap<String, LevelFilter>,
+}
+
+impl RequestLevels {
+    pub fn new(overrides: Vec<LevelOverride>) -> Self {
+        Self {
+            overrides: overrides.into_iter().map(|o| (o.kind, o.level)).collect(),
+        }
+    }
+
+    /// Level to log `request` at once handled, or `None` if it is not logged
+    pub fn request(&self, request: &Request) -> Option<Level> {
+        let name = request_name(request);
+        let default = match request {
+            Request::RequestIdentities | Request::Extension(_) => LevelFilter::Debug,
+            _ => LevelFilter::Info,
+        };
+        self.overrides.get(name).unwrap_or(&default).to_level()
+    }
+
+    /// Level to log policy denials at, or `None` if they are not logged
+    pub fn denied(&self) -> Option<Level> {
+        let level = self.overrides.get(DENIED).unwrap_or(&LevelFilter::Warn);
+        level.to_level()
+    }
+}
+
+/// Short description of `request`, naming the key it operates on
+pub fn summary(request: &Request) -> String {
+    let name = request_name(request);
+    match request_key(request) {
+        Some(key) => format!("{name} request with {}", key.fingerprint(HashAlg::Sha256)),
+        None => format!("{name} request"),
+    }
+}
+
+/// Short description of how a request was answered
+pub fn outcome(result: &Result<Response, AgentError>) -> String {
+    match result {
+        Ok(Response::Success) | Ok(Response::ExtensionResponse(_)) => "success".to_string(),
+        Ok(Response::Failure) | Ok(Response::ExtensionFailure) => "failure".to_string(),
+        Ok(Response::IdentitiesAnswer(identities)) => format!("{} identities", identities.len()),
+        Ok(Response::SignResponse(_)) => "signed".to_string(),
+        Err(e) => format!("error: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::proto::{Extension, SignRequest};
+    use ssh_agent_lib::ssh_key::public::{Ed25519PublicKey, KeyData};
+
+    fn sign() -> Request {
+        Request::SignRequest(SignRequest {
+            pubkey: KeyData::Ed25519(Ed25519PublicKey([7; 32])),
+            data: b"data".to_vec(),
+            flags: 0,
+        })
+    }
+
+    fn extension() -> Request {
+        Request::Extension(Extension {
+            name: "query".into(),
+            details: Vec::new().into(),
+        })
+    }
+
+    fn levels(overrides: &[&str]) -> RequestLevels {
+        RequestLevels::new(overrides.iter().map(|o| o.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn signs_are_logged_above_listings_by_default() {
+        let levels = levels(&[]);
+        assert_eq!(levels.request(&sign()), Some(Level::Info));
+        assert_eq!(
+            levels.request(&Request::RemoveAllIdentities),
+            Some(Level::Info)
+        );
+        assert_eq!(
+            levels.request(&Request::RequestIdentities),
+            Some(Level::Debug)
+        );
+        assert_eq!(levels.request(&extension()), Some(Level::Debug));
+        assert_eq!(levels.denied(), Some(Level::Warn));
+    }
+
+    #[test]
+    fn overrides_replace_the_default_of_their_request_only() {
+        let custom = levels(&["sign=debug", "request-identities=off", "denied=error"]);
+        assert_eq!(custom.request(&sign()), Some(Level::Debug));
+        assert_eq!(custom.request(&Request::RequestIdentities), None);
+        assert_eq!(custom.request(&extension()), Some(Level::Debug));
+        assert_eq!(custom.denied(), Some(Level::Error));
+
+        let silent = levels(&["denied=off"]);
+        assert_eq!(silent.denied(), None);
+        assert_eq!(silent.request(&sign()), Some(Level::Info));
+    }
+
+    #[test]
+    fn refuses_unknown_requests_and_levels() {
+        let unknown = "signing=info".parse::<LevelOverride>().unwrap_err();
+        assert!(unknown.starts_with("signing: expected denied or one of"));
+        let level = "sign=loud".parse::<LevelOverride>().unwrap_err();
+        assert!(level.starts_with("loud: expected off"));
+        assert!("sign".parse::<LevelOverride>().is_err());
+    }
+}
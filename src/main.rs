@@ -1,14 +1,74 @@
-use clap::Parser;
+mod allowlist;
+mod anomaly;
+mod askpass;
+mod attest;
+#[cfg(unix)]
+mod audit;
+mod backend;
+mod client;
+mod comment;
+mod constraint;
+mod denial;
+mod device;
+mod dump;
+mod error;
+mod extensions;
+mod fake;
+mod forwarding;
+#[cfg(unix)]
+mod heal;
+mod health;
+mod info;
+mod inspect;
+mod keyage;
+mod keyconfirm;
+mod keys;
+mod keyusers;
+mod killswitch;
+mod loglevel;
+mod memory;
+mod notify;
+mod openssh;
+mod order;
+#[cfg(feature = "otel")]
+mod otel;
+mod policy;
+mod quota;
+mod ratelimit;
+mod redact;
+mod replay;
+mod request;
+mod rotation;
+#[cfg(feature = "policy-script")]
+mod script;
+mod seed;
+mod server;
+mod sigalg;
+mod signer;
+mod signprefix;
+mod sockpath;
+mod state;
+mod totp;
+mod verify;
+#[cfg(all(feature = "vsock", target_os = "linux"))]
+mod vsock;
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use log::{LevelFilter, debug, error, info, log, warn};
 use ssh_agent_lib::agent::Agent;
-use ssh_agent_lib::agent::service_binding::Binding;
-use ssh_agent_lib::client::connect;
 use ssh_agent_lib::error::AgentError;
-use ssh_agent_lib::proto::{Request, Response};
+use ssh_agent_lib::proto::extension::{MessageExtension, SessionBind};
+use ssh_agent_lib::proto::{Credential, Extension, RemoveIdentity, Request, Response, SignRequest};
+use ssh_agent_lib::ssh_key::public::KeyData;
+use ssh_agent_lib::ssh_key::{Algorithm, Fingerprint, HashAlg, PublicKey, Signature};
 use std::fs;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::signal;
-use tokio::sync::watch;
+use tokio::sync::{Semaphore, watch};
 use tokio::process::Command;
 
 #[cfg(windows)]
@@ -17,219 +77,3673 @@ use ssh_agent_lib::agent::NamedPipeListener as Listener;
 use tokio::net::UnixListener as Listener;
 
 use ssh_agent_lib::proto::message::KeyConstraint;
-use ssh_agent_lib::{
-    agent::{Session, listen},
-    proto::AddIdentityConstrained,
-};
+use ssh_agent_lib::{agent::Session, proto::AddIdentityConstrained};
+
+use anomaly::{AnomalyAction, FlagProfiles};
+use askpass::Confirmer;
+use backend::{Backends, Failover};
+use client::ClientInfo;
+use comment::CommentFilter;
+use constraint::{EnforcedConstraint, SignConfirmations};
+use denial::{Denial, WHY_DENIED_EXTENSION, why_denied_response};
+use error::ProxyError;
+use extensions::BackendExtensions;
+use fake::{FakeIdentities, FakeSignResponse};
+use forwarding::OnForwarded;
+use health::Health;
+use info::{INFO_EXTENSION, Stats, info_response};
+use keyage::KeyAges;
+use keyconfirm::{KeyConfirm, Unconfirmable};
+use keyusers::KeyUsers;
+use killswitch::{KillSwitch, KillSwitchScope};
+use loglevel::{LevelOverride, RequestLevels};
+use memory::InProcessAgent;
+use notify::NotifyReady;
+use openssh::LogFormat;
+use order::IdentityOrder;
+use policy::Policy;
+use quota::{KeyQuota, Quotas};
+use ratelimit::{KeyRateLimit, RateLimiter};
+use replay::{Digest, ReplayGuard};
+use rotation::Rotations;
+use server::listen;
+use sigalg::KeySignatureAlgorithms;
+use signprefix::KeySignPrefix;
+use state::{JsonFile, Store};
+use totp::{KeyTotp, Totps};
+use verify::{Verification, verify_signature};
 
 #[derive(Parser, Debug)]
 #[command(
     author,
     version,
-    about = "SSH agent wrapper that forces confirm constraint on key additions"
+    about = "SSH agent wrapper that forces confirm constraint on key additions",
+    subcommand_negates_reqs = true
 )]
 struct Args {
     /// Path to the socket to bind to (optional, defaults to a temp path)
     #[arg(short = 's', long = "sock", value_name = "PATH")]
     socket: Option<PathBuf>,
 
+    /// Write `export SSH_AUTH_SOCK=...` for the proxy socket to this file once it is listening
+    #[arg(long = "export-env", value_name = "PATH")]
+    export_env: Option<PathBuf>,
+
+    /// Print `export SSH_AUTH_SOCK=...` for the proxy socket to stdout once it is listening
+    #[arg(long = "print-env")]
+    print_env: bool,
+
+    /// Backend ssh-agent socket (repeatable; the first responsive one is used, defaults to SSH_AUTH_SOCK)
+    #[arg(long = "backend-sock", value_name = "PATH")]
+    backend_sock: Vec<PathBuf>,
+
+    /// Keep keys in the proxy's own memory instead of forwarding to a backend ssh-agent
+    #[arg(long = "in-process-backend", conflicts_with_all = ["backend_sock", "health_interval"])]
+    in_process_backend: bool,
+
+    /// Give this group read/write access to the proxy socket (mode 0660)
+    #[cfg(unix)]
+    #[arg(long = "socket-group", value_name = "NAME")]
+    socket_group: Option<String>,
+
+    /// Queue up to this many pending connections on the proxy socket (default: the system's)
+    #[cfg(unix)]
+    #[arg(long = "listen-backlog", value_name = "N")]
+    listen_backlog: Option<u32>,
+
+    /// Bind the proxy socket again if its file is deleted while the proxy runs
+    #[cfg(unix)]
+    #[arg(long = "self-heal-socket")]
+    self_heal_socket: bool,
+
+    /// Close client connections that send no request for this long, releasing their backend connection
+    #[arg(long = "session-idle", value_name = "SECONDS")]
+    session_idle: Option<u64>,
+
+    /// Accept at most this many client connections at once; others wait in the listen backlog
+    #[arg(long = "accept-concurrency", value_name = "N")]
+    accept_concurrency: Option<usize>,
+
+    /// Answer the info@ssh-agent-ac extension with proxy version, policy, uptime and request counts
+    #[arg(long = "enable-info-extension")]
+    enable_info_extension: bool,
+
+    /// Answer the why-denied@ssh-agent-ac extension with the last denial on the connection
+    #[arg(long = "enable-why-denied-extension")]
+    enable_why_denied_extension: bool,
+
+    /// Retry a failed identity listing up to N times with exponential backoff
+    #[arg(long = "list-retries", value_name = "N", default_value_t = 0)]
+    list_retries: u32,
+
+    /// Deny requests while this file exists; the info and why-denied extensions are still answered
+    #[arg(long = "kill-switch", value_name = "PATH")]
+    kill_switch: Option<PathBuf>,
+
+    /// Which requests the kill switch denies
+    #[arg(long = "kill-switch-scope", value_enum, default_value_t = KillSwitchScope::All)]
+    kill_switch_scope: KillSwitchScope,
+
+    /// Verify every signature returned by the backend and fail the request if it does not check out
+    #[arg(long = "verify-signatures")]
+    verify_signatures: bool,
+
+    /// Ask the backend which extensions it supports at startup and fail others without forwarding them
+    #[arg(long = "probe-extensions")]
+    probe_extensions: bool,
+
+    /// Order of listed keys: backend, comment, or pinned=<FINGERPRINT>[,...] to list those first
+    #[arg(
+        long = "identity-order",
+        value_name = "ORDER",
+        default_value_t = IdentityOrder::Backend
+    )]
+    identity_order: IdentityOrder,
+
+    /// List this OpenSSH public key as if the backend held it, for testing clients (repeatable)
+    #[arg(long = "fake-identity", value_name = "PUBKEY")]
+    fake_identity: Vec<PublicKey>,
+
+    /// How the proxy answers sign requests for --fake-identity keys
+    #[arg(
+        long = "fake-sign-response",
+        value_enum,
+        default_value_t = FakeSignResponse::Failure
+    )]
+    fake_sign_response: FakeSignResponse,
+
+    /// Constraint added to every key: confirm, lifetime=<SECONDS> (also caps longer lifetimes) or none
+    #[arg(
+        long = "enforce-constraint",
+        value_name = "CONSTRAINT",
+        default_value_t = EnforcedConstraint::Confirm
+    )]
+    enforce_constraint: EnforcedConstraint,
+
+    /// Forward plain adds unchanged when confirm is all the proxy would add, and confirm at sign time instead
+    #[arg(long = "no-upgrade-unconstrained")]
+    no_upgrade_unconstrained: bool,
+
+    /// Only add confirm to keys that have neither confirm nor a lifetime, instead of to every key
+    #[arg(long = "require-protection", conflicts_with = "enforce_constraint")]
+    require_protection: bool,
+
+    /// Deny signing data that looks like an X.509 or TLS structure (heuristic, may deny unusual payloads)
+    #[arg(long = "reject-foreign-sign")]
+    reject_foreign_sign: bool,
+
+    /// Ask for confirmation through SSH_ASKPASS before locking or unlocking the agent
+    #[arg(long = "confirm-lock-unlock")]
+    confirm_lock_unlock: bool,
+
+    /// Ask for confirmation through SSH_ASKPASS before removing one or all keys
+    #[arg(long = "confirm-removals")]
+    confirm_removals: bool,
+
+    /// Refuse to unlock the agent, leaving unlocking to the operator out of band
+    #[arg(long = "block-unlock")]
+    block_unlock: bool,
+
+    /// Remove a key from the backend when a new key is added with the same comment
+    #[arg(long = "remove-rotated-keys")]
+    remove_rotated_keys: bool,
+
+    /// Lifetime applied to added keys that do not specify one, capped by --enforce-constraint lifetime=N
+    #[arg(long = "default-lifetime", value_name = "SECONDS")]
+    default_lifetime: Option<u32>,
+
+    /// Only accept keys during this many seconds after startup, freezing the key set afterwards
+    #[arg(long = "add-window", value_name = "SECONDS")]
+    add_window: Option<u64>,
+
+    /// Add this private key at startup, applying the same policy as keys added by clients (repeatable)
+    #[arg(long = "add-key", value_name = "PATH")]
+    add_key: Vec<PathBuf>,
+
+    /// Print every request and response to stderr as JSON, with secrets redacted
+    #[arg(long = "debug-proto")]
+    debug_proto: bool,
+
+    /// Log format for requests and responses; openssh-debug traces them like `ssh-agent -d`
+    #[arg(long = "log-format", value_enum, default_value_t = LogFormat::Plain)]
+    log_format: LogFormat,
+
+    /// Log handled requests of one type at this level, e.g. sign=warn or request-identities=off;
+    /// `denied` sets the level of denials (repeatable)
+    #[arg(long = "log-level-for", value_name = "REQUEST=LEVEL")]
+    log_level_for: Vec<LevelOverride>,
+
+    /// Delay policy denials to at least this long plus random jitter, so they time like forwarded requests
+    #[arg(long = "constant-time-denies", value_name = "MILLIS")]
+    constant_time_denies: Option<u64>,
+
+    /// Log each connecting client with its PID, UID and command line (Linux only)
+    #[arg(long = "log-client-cmdline")]
+    log_client_cmdline: bool,
+
+    /// Replace user names and home directories in logged paths with placeholders
+    #[arg(long = "redact-paths")]
+    redact_paths: bool,
+
+    /// What to do when signing on a connection forwarded from another host
+    #[arg(long = "on-forwarded", value_enum, default_value_t = OnForwarded::Warn)]
+    on_forwarded: OnForwarded,
+
+    /// Refuse session-bind@openssh.com to other host keys than this one (repeatable)
+    #[arg(long = "allow-bind-host", value_name = "FINGERPRINT")]
+    allow_bind_host: Vec<Fingerprint>,
+
+    /// Only sign on connections bound (via session-bind@openssh.com) to this host key fingerprint (repeatable)
+    #[arg(long = "allow-host", value_name = "FINGERPRINT")]
+    allow_host: Vec<Fingerprint>,
+
+    /// Only let one key produce these comma-separated signature algorithms, e.g. SHA256:...=rsa-sha2-512 (repeatable)
+    #[arg(long = "key-sign-alg", value_name = "FINGERPRINT=ALGORITHMS")]
+    key_sign_alg: Vec<KeySignatureAlgorithms>,
+
+    /// Only let one key sign data starting with this text, or hex:<HEX> bytes (repeatable; any may match)
+    #[arg(long = "key-sign-prefix", value_name = "FINGERPRINT=PREFIX")]
+    key_sign_prefix: Vec<KeySignPrefix>,
+
+    /// Only show and sign with one key for these comma-separated client UIDs, e.g. SHA256:...=1000 (repeatable)
+    #[arg(long = "key-uid", value_name = "FINGERPRINT=UIDS")]
+    key_uid: Vec<KeyUsers>,
+
+    /// Only show and sign with FIDO security keys (sk-*) for these comma-separated client UIDs
+    #[arg(long = "sk-key-uid", value_name = "UIDS", value_delimiter = ',')]
+    sk_key_uid: Option<Vec<u32>>,
+
+    /// Ask for a TOTP code (HMAC-SHA256, 6 digits, 30s) through SSH_ASKPASS before signing with one key;
+    /// PATH holds the base32 secret (repeatable)
+    #[arg(long = "key-totp", value_name = "FINGERPRINT=PATH")]
+    key_totp: Vec<KeyTotp>,
+
+    /// Confirm every signature with one key; when no confirmer can ask, deny or allow (repeatable; --confirmer deny always denies)
+    #[arg(long = "key-confirm", value_name = "FINGERPRINT=deny|allow")]
+    key_confirm: Vec<KeyConfirm>,
+
+    /// Let these comma-separated client UIDs sign with one key without the proxy's confirmation,
+    /// logging every bypass (repeatable); a backend ssh-agent still prompts for confirm-constrained keys
+    #[arg(long = "confirm-bypass", value_name = "FINGERPRINT=UIDS")]
+    confirm_bypass: Vec<KeyUsers>,
+
+    /// Limit signing with one key, e.g. SHA256:...=1/60 for one signature per minute (repeatable)
+    #[arg(long = "key-rate-limit", value_name = "FINGERPRINT=COUNT/SECONDS")]
+    key_rate_limit: Vec<KeyRateLimit>,
+
+    /// Warn about or deny sign requests whose flags a key never signed with before its baseline
+    #[arg(long = "anomaly", value_enum, value_name = "ACTION")]
+    anomaly: Option<AnomalyAction>,
+
+    /// Signatures per key that make up its baseline for --anomaly
+    #[arg(long = "anomaly-baseline", value_name = "COUNT", default_value_t = 10)]
+    anomaly_baseline: u32,
+
+    /// Refuse to add RSA keys shorter than this and DSA keys altogether
+    #[arg(long = "min-rsa-bits", value_name = "BITS")]
+    min_rsa_bits: Option<u32>,
+
+    /// Only add and sign with keys that have a matching .pub file in this directory
+    #[arg(long = "allowed-keys-dir", value_name = "PATH")]
+    allowed_keys_dir: Option<PathBuf>,
+
+    /// Only accept keys of this type on add, e.g. ssh-ed25519 (repeatable)
+    #[arg(long = "allow-key-type", value_name = "ALGORITHM")]
+    allow_key_type: Vec<Algorithm>,
+
+    /// Only accept keys added with these constraint extensions, e.g. restrict-destination-v00@openssh.com (repeatable)
+    #[arg(long = "allow-constraint-extension", value_name = "NAME")]
+    allow_constraint_extension: Vec<String>,
+
+    /// Refuse adds carrying a constraint extension payload larger than this
+    #[arg(long = "max-constraint-extension-bytes", value_name = "BYTES")]
+    max_constraint_extension_bytes: Option<usize>,
+
+    /// Refuse extension requests whose payload is larger than this, before inspecting them
+    #[arg(long = "max-extension-bytes", value_name = "BYTES")]
+    max_extension_bytes: Option<usize>,
+
+    /// Refuse to sign while the screen is locked (Linux, through logind)
+    #[arg(long = "deny-when-locked")]
+    deny_when_locked: bool,
+
+    /// Refuse to sign while running on battery below this charge, e.g. 20 (Linux)
+    #[arg(long = "deny-on-battery", value_name = "PERCENT")]
+    deny_on_battery: Option<u8>,
+
+    /// Refuse to sign data identical to what the same key signed within this many seconds
+    #[arg(long = "replay-window", value_name = "SECONDS")]
+    replay_window: Option<u64>,
+
+    /// Cap signing with one key per UTC day or week, e.g. SHA256:...=100/day (repeatable)
+    #[arg(long = "key-quota", value_name = "FINGERPRINT=COUNT/PERIOD")]
+    key_quota: Vec<KeyQuota>,
+
+    /// Keep state that survives restarts, such as quota usage and last-used times, in this file
+    #[arg(long = "state-file", value_name = "PATH")]
+    state_file: Option<PathBuf>,
+
+    /// Save quota usage to this file so restarts do not reset it (instead of --state-file)
+    #[arg(long = "quota-state", value_name = "PATH")]
+    quota_state: Option<PathBuf>,
+
+    /// Rhai script returning "allow", "deny" or "confirm" for requests that pass the other checks
+    #[cfg(feature = "policy-script")]
+    #[arg(long = "policy-script", value_name = "PATH")]
+    policy_script: Option<PathBuf>,
+
+    /// Only sign with keys whose comment contains this substring
+    #[arg(long = "require-comment", value_name = "SUBSTR")]
+    require_comment: Option<String>,
+
+    /// Rewrite the comments of added keys through this program, which reads one on stdin and prints the new one;
+    /// it should print comments it already rewrote unchanged, as chained proxies each run their filter
+    #[arg(long = "comment-filter", value_name = "PATH")]
+    comment_filter: Option<PathBuf>,
+
+    /// Warn when a key added through the proxy stays loaded this long without a lifetime
+    #[arg(long = "warn-key-age", value_name = "SECONDS")]
+    warn_key_age: Option<u64>,
+
+    /// Warn while an askpass prompt has been left unanswered this long, repeating at that interval
+    #[arg(
+        long = "warn-pending-confirm",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    warn_pending_confirm: Option<u64>,
+
+    /// Ask for confirmations through this, falling back to the next when unavailable (repeatable; default askpass)
+    #[arg(long = "confirmer", value_enum, value_name = "CONFIRMER")]
+    confirmer: Vec<Confirmer>,
+
+    /// Warn when a key added through the proxy stays loaded this long without signing
+    #[arg(long = "warn-unused-key", value_name = "SECONDS")]
+    warn_unused_key: Option<u64>,
+
+    /// Save when each key last signed to this file so restarts keep it (instead of --state-file)
+    #[arg(long = "key-usage-state", value_name = "PATH")]
+    key_usage_state: Option<PathBuf>,
+
+    /// Probe the backend this often and fail requests fast while it is unresponsive
+    #[arg(long = "health-interval", value_name = "SECONDS")]
+    health_interval: Option<u64>,
+
+    /// Signal readiness once the socket is bound and the backend answers: none, systemd or file:<PATH>
+    #[arg(long = "notify-ready", value_name = "HOW", default_value_t = NotifyReady::None)]
+    notify_ready: NotifyReady,
+
+    /// Log more: -v for debug, -vv for trace
+    #[arg(short = 'v', long = "verbose", action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Write the effective policy's canonical JSON here at startup; its SHA-256 is the policy hash
+    #[arg(long = "attest-policy", value_name = "PATH")]
+    attest_policy: Option<PathBuf>,
+
+    /// Sign the --attest-policy file with the backend key of this fingerprint into PATH.sig
+    #[arg(long = "attest-key", value_name = "KEY", requires = "attest_policy")]
+    attest_key: Option<Fingerprint>,
+
+    /// Send an audit event per request, as one JSON datagram, to this Unix datagram socket
+    #[cfg(unix)]
+    #[arg(long = "audit-socket", value_name = "PATH")]
+    audit_socket: Option<PathBuf>,
+
+    /// Only log warnings and errors, suppressing the startup messages
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Tokio runtime to run on: one thread for light desktop use, or a worker pool
+    #[arg(long = "runtime", value_enum, default_value_t = RuntimeFlavor::MultiThread)]
+    runtime: RuntimeFlavor,
+
+    /// Worker threads for the multi-thread runtime (defaults to one per CPU)
+    #[arg(long = "worker-threads", value_name = "N")]
+    worker_threads: Option<NonZeroUsize>,
+
+    /// Also accept connections from virtual machines on this vsock address, e.g. any:2222
+    #[cfg(all(feature = "vsock", target_os = "linux"))]
+    #[arg(long = "vsock", value_name = "CID:PORT")]
+    vsock: Option<vsock::VsockTarget>,
+
+    /// Export a span per request to this OTLP/HTTP endpoint (e.g. http://localhost:4318/v1/traces)
+    #[cfg(feature = "otel")]
+    #[arg(long = "otlp-endpoint", value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
     /// Command to run with SSH_AUTH_SOCK redirected through the proxy
-    #[arg(value_name = "BIN")]
-    bin: String,
+    #[arg(value_name = "BIN", required = true)]
+    bin: Option<String>,
 
     /// Arguments for the command
     #[arg(value_name = "ARGS", trailing_var_arg = true, allow_hyphen_values = true)]
     bin_args: Vec<String>,
+
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum RuntimeFlavor {
+    /// Everything on the main thread
+    CurrentThread,
+    /// A pool of worker threads
+    MultiThread,
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Sign a file once through the policy instead of running a command (prints an SSHSIG)
+    Sign(signer::SignArgs),
 }
 
 #[derive(Clone)]
 struct Proxy {
-    backend_socket_path: PathBuf,
+    backends: Backends,
+    // Windows connections that cannot reach the backend fail on their own instead
+    #[cfg_attr(windows, allow(dead_code))]
     fatal_tx: watch::Sender<bool>,
+    policy: Arc<Policy>,
+    stats: Arc<Stats>,
+    key_ages: Arc<KeyAges>,
+    logging: Logging,
+    health: Option<Arc<Health>>,
+}
+
+/// What the proxy logs about connections beyond its policy decisions
+#[derive(Clone)]
+struct Logging {
+    client_cmdline: bool,
+    debug_proto: bool,
+    format: LogFormat,
+    levels: Arc<RequestLevels>,
+    #[cfg(unix)]
+    audit: Option<Arc<audit::AuditSocket>>,
 }
 
 impl Proxy {
-    fn new(backend_socket_path: PathBuf, fatal_tx: watch::Sender<bool>) -> Self {
+    fn new(
+        backends: Backends,
+        fatal_tx: watch::Sender<bool>,
+        policy: Policy,
+        logging: Logging,
+        health: Option<Arc<Health>>,
+        key_ages: KeyAges,
+    ) -> Self {
         Self {
-            backend_socket_path,
+            backends,
             fatal_tx,
+            policy: Arc::new(policy),
+            stats: Arc::new(Stats::new()),
+            key_ages: Arc::new(key_ages),
+            logging,
+            health,
+        }
+    }
+
+    /// Proxy in front of `agent` enforcing `policy`, logging nothing beyond its decisions
+    #[cfg(test)]
+    fn in_process(agent: InProcessAgent, policy: Policy) -> Self {
+        let logging = Logging {
+            client_cmdline: false,
+            debug_proto: false,
+            format: LogFormat::Plain,
+            levels: Arc::new(RequestLevels::new(Vec::new())),
+            #[cfg(unix)]
+            audit: None,
+        };
+        let (fatal_tx, _) = watch::channel(false);
+        let key_ages = KeyAges::new(None, None, None);
+        let backends = Backends::InProcess(agent);
+        Self::new(backends, fatal_tx, policy, logging, None, key_ages)
+    }
+
+    /// Connect to the backend for `client`, or fail over between backends when there are several
+    fn connect_backend(&self, client: &ClientInfo) -> Result<Box<dyn Session>, ProxyError> {
+        let paths = match &self.backends {
+            Backends::Sockets(paths) => paths,
+            // The in-process agent prompts by itself, so it is told whom not to prompt
+            Backends::InProcess(agent) => {
+                let bypassed = keyusers::bypassed(&self.policy.confirm_bypass, client.uid);
+                return Ok(Box::new(agent.without_confirming(bypassed)));
+            }
+        };
+        match &paths[..] {
+            // Connect eagerly so an unreachable backend is noticed when the client connects
+            [path] => {
+                #[cfg(unix)]
+                let backend = backend::connect(path)?;
+                #[cfg(windows)]
+                let backend = connect_named_pipe(path)?;
+                let candidates = vec![path.clone()];
+                Ok(Box::new(Failover::connected(candidates, 0, backend)))
+            }
+            paths => Ok(Box::new(Failover::new(paths.to_vec()))),
+        }
+    }
+
+    fn session(&self, backend: Box<dyn Session>, client: ClientInfo) -> impl Session + use<> {
+        if self.logging.client_cmdline {
+            info!("Client connected: {client}");
         }
+
+        #[cfg(feature = "otel")]
+        let backend = Box::new(otel::Traced::new(backend, "backend"));
+
+        let session = ProxySession {
+            backend,
+            policy: self.policy.clone(),
+            stats: self.stats.clone(),
+            key_ages: self.key_ages.clone(),
+            client,
+            bound_host: None,
+            forwarded: false,
+            health: self.health.clone(),
+            last_denial: None,
+            denied: false,
+            reserved: None,
+            listed_comment: None,
+            logging: self.logging.clone(),
+        };
+
+        #[cfg(feature = "otel")]
+        let session = otel::Traced::new(Box::new(session), "request");
+
+        session
     }
 }
 
 struct ProxySession {
     backend: Box<dyn Session>,
+    policy: Arc<Policy>,
+    stats: Arc<Stats>,
+    key_ages: Arc<KeyAges>,
+    client: ClientInfo,
+    /// Host key from the most recent session-bind on this connection
+    bound_host: Option<Fingerprint>,
+    /// Whether a session-bind reported this connection as forwarded
+    forwarded: bool,
+    health: Option<Arc<Health>>,
+    /// Most recent request on this connection refused by the policy
+    last_denial: Option<Denial>,
+    /// Whether the request being handled was refused by the policy
+    denied: bool,
+    /// What the sign request being handled took from its key's limits
+    reserved: Option<Reservation>,
+    /// Key and comment the backend listed for the request being handled, once looked up
+    listed_comment: Option<(KeyData, Option<String>)>,
+    logging: Logging,
 }
 
-#[ssh_agent_lib::async_trait]
-impl Session for ProxySession {
-    async fn handle(&mut self, message: Request) -> Result<Response, AgentError> {
-        match message {
-            Request::AddIdentity(add) => {
-                // Rewrite to constrained add with confirm
-                let constrained = AddIdentityConstrained {
-                    identity: add,
-                    constraints: vec![KeyConstraint::Confirm],
-                };
-                self.backend
-                    .handle(Request::AddIdConstrained(constrained))
-                    .await
-            }
-            Request::AddIdConstrained(mut add_con) => {
-                // Ensure confirm constraint is present
-                if !add_con
-                    .constraints
-                    .iter()
-                    .any(|c| matches!(c, KeyConstraint::Confirm))
-                {
-                    add_con.constraints.push(KeyConstraint::Confirm);
-                }
-                self.backend
-                    .handle(Request::AddIdConstrained(add_con))
-                    .await
-            }
-            // Forward everything else unchanged
-            msg => self.backend.handle(msg).await,
+/// A rate-limit token, quota unit and replay-guard entry taken for one sign request
+///
+/// Given back when dropped, unless kept because the backend signed; a request
+/// abandoned by a client hanging up is given back with its session.
+struct Reservation {
+    policy: Arc<Policy>,
+    key: Fingerprint,
+    replay: Option<Digest>,
+    kept: bool,
+}
+
+impl Reservation {
+    fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.kept {
+            self.policy.key_rate_limits.refund(&self.key);
+            self.policy.key_quotas.release(&self.key);
+            self.policy.replay_guard.forget(self.replay);
         }
     }
 }
 
-#[cfg(unix)]
-impl Agent<Listener> for Proxy {
-    fn new_session(&mut self, _: &tokio::net::UnixStream) -> impl Session {
-        let binding = Binding::FilePath(self.backend_socket_path.clone())
-            .try_into()
-            .unwrap_or_else(|e| {
-                let _ = self.fatal_tx.send(true);
-                panic!("Failed to create binding for ssh-agent backend: {e}");
-            });
-        let backend = connect(binding).unwrap_or_else(|e| {
-            let _ = self.fatal_tx.send(true);
-            panic!("Failed to establish connection to ssh-agent backend: {e}");
-        });
+impl ProxySession {
+    /// Whether this is an info extension request answered by the proxy itself
+    fn is_info_request(&self, ext: &Extension) -> bool {
+        self.policy.info_extension && ext.name == INFO_EXTENSION
+    }
 
-        ProxySession { backend }
+    /// Whether this is a why-denied extension request answered by the proxy itself
+    fn is_why_denied_request(&self, ext: &Extension) -> bool {
+        self.policy.why_denied_extension && ext.name == WHY_DENIED_EXTENSION
     }
-}
 
-#[cfg(windows)]
-impl Agent<Listener> for Proxy {
-    fn new_session(
-        &mut self,
-        _: &tokio::net::windows::named_pipe::NamedPipeServer,
-    ) -> impl Session {
-        let binding = Binding::NamedPipe(self.backend_socket_path.clone().into_os_string())
-            .try_into()
-            .unwrap_or_else(|e| {
-                let _ = self.fatal_tx.send(true);
-                panic!("Failed to create binding for ssh-agent backend: {e}");
-            });
-        let backend = connect(binding).unwrap_or_else(|e| {
-            let _ = self.fatal_tx.send(true);
-            panic!("Failed to establish connection to ssh-agent backend: {e}");
-        });
+    /// Whether `message` is answered by the proxy itself, without the backend
+    fn is_local_request(&self, message: &Request) -> bool {
+        matches!(message, Request::Extension(ext)
+            if self.is_info_request(ext) || self.is_why_denied_request(ext))
+    }
 
-        ProxySession { backend }
+    /// Log and remember a denial, then fail the request
+    fn deny(&mut self, denial: Denial) -> Result<Response, AgentError> {
+        if let Some(level) = self.logging.levels.denied() {
+            log!(
+                level,
+                "Denied {} from {}: {}",
+                denial.action,
+                self.client,
+                denial.reason
+            );
+        }
+        self.last_denial = Some(denial);
+        self.denied = true;
+        Ok(Response::Failure)
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    /// Why the proxy's own policy forbids forwarding `request`, if it does
+    fn sign_denial(&self, request: &SignRequest) -> Option<Denial> {
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        let action = format!("signing with {key}");
+        if self.policy.reject_foreign_sign && inspect::looks_like_foreign_protocol(&request.data) {
+            return Some(Denial::new(
+                action,
+                "data looks like a non-SSH protocol structure",
+            ));
+        }
 
-    let backend_socket = std::env::var_os("SSH_AUTH_SOCK")
-        .ok_or("Missing SSH_AUTH_SOCK for backend ssh-agent socket.")?;
-    let backend_socket_path = PathBuf::from(backend_socket);
+        if let Some(allowed) = &self.policy.allowed_keys
+            && !allowed.contains(&key)
+        {
+            return Some(Denial::new(action, "key is not on the allow-list"));
+        }
 
-    let socket = args.socket.unwrap_or_else(|| {
-        let mut path = std::env::temp_dir();
-        path.push(format!("ssh-agent-ac-{}.sock", std::process::id()));
-        path
-    });
+        if !keyusers::permitted(&self.policy.key_users, &key, self.client.uid) {
+            return Some(Denial::new(action, "key is restricted to other UIDs"));
+        }
+        let sk_users = self.policy.sk_key_users.as_deref();
+        if !keyusers::sk_permitted(sk_users, &request.pubkey, self.client.uid) {
+            return Some(Denial::new(
+                action,
+                "security keys are restricted to other UIDs",
+            ));
+        }
 
-    // Ensure the parent directory of the socket exists
-    if let Some(parent) = socket.parent()
-        && !parent.exists() {
-            fs::create_dir_all(parent)?;
-            println!("Created directory: {}", parent.display());
+        if let Some(reason) = self.host_denial(&key) {
+            return Some(Denial::new(action, reason));
         }
 
-    // Clean up old socket if present
-    if socket.exists() {
-        fs::remove_file(&socket)?;
+        if let Some(algorithm) = sigalg::disallowed(&self.policy.key_signature_algorithms, request)
+        {
+            let reason = format!("signature algorithm {algorithm} is not allowed for this key");
+            return Some(Denial::new(action, reason));
+        }
+
+        if !signprefix::permitted(&self.policy.key_sign_prefixes, &key, &request.data) {
+            let reason = "data does not start with an allowed prefix";
+            return Some(Denial::new(action, reason));
+        }
+
+        let flags = request.flags;
+        match self.policy.flag_profiles.check(&key, flags) {
+            Some((AnomalyAction::Warn, seen)) => warn!(
+                "Sign request with {key} from {} uses flags 0x{flags:02x}, unlike its previous {seen} requests",
+                self.client
+            ),
+            Some((AnomalyAction::Deny, _)) => {
+                let reason = format!("flags 0x{flags:02x} deviate from this key's baseline");
+                return Some(Denial::new(action, reason));
+            }
+            None => {}
+        }
+
+        None
     }
 
-    println!("Backend ssh-agent socket: {}", backend_socket_path.display());
-    println!("Proxy listening on: {}", socket.display());
+    /// Take what signing with `request`'s key uses up from its limits, or say why it is refused
+    ///
+    /// Taken before the request goes on, so concurrent requests cannot all pass on
+    /// the last token or quota, or all sign the same data; `handle` gives it back
+    /// if the backend does not sign.
+    fn reserve(&mut self, request: &SignRequest) -> Option<Denial> {
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        let action = format!("signing with {key}");
+        if !self.policy.key_rate_limits.allows(&key) {
+            return Some(Denial::new(action, "rate limit exceeded"));
+        }
+        if let Err(reset) = self.policy.key_quotas.check(&key) {
+            self.policy.key_rate_limits.refund(&key);
+            let mins = reset.as_secs().div_ceil(60);
+            let reason = format!("quota exhausted, resets in {}h{:02}m", mins / 60, mins % 60);
+            return Some(Denial::new(action, reason));
+        }
+        let replay = self.policy.replay_guard.digest(&key, &request.data);
+        if self.policy.replay_guard.replayed(replay) {
+            self.policy.key_rate_limits.refund(&key);
+            self.policy.key_quotas.release(&key);
+            let reason = "identical data was just signed (possible replay)";
+            return Some(Denial::new(action, reason));
+        }
+        self.reserved = Some(Reservation {
+            policy: Arc::clone(&self.policy),
+            key,
+            replay,
+            kept: false,
+        });
+        None
+    }
 
-    let mut cmd = Command::new(&args.bin);
-    cmd.args(&args.bin_args)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .env("SSH_AUTH_SOCK", &socket);
+    /// Why signing is refused in the machine's current state, if it is
+    ///
+    /// A state that cannot be determined never refuses the request.
+    async fn device_denial(&self, request: &SignRequest) -> Option<Denial> {
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        let action = || format!("signing with {key}");
 
-    let (fatal_tx, mut fatal_rx) = watch::channel(false);
+        if self.policy.deny_when_locked && device::is_screen_locked().await == Some(true) {
+            return Some(Denial::new(action(), "the screen is locked"));
+        }
+        if let Some(min) = self.policy.deny_on_battery_below
+            && let Some(battery) = device::battery_state()
+            && battery.discharging
+            && battery.percent < min
+        {
+            let reason = format!("on battery at {}%, below {min}%", battery.percent);
+            return Some(Denial::new(action(), reason));
+        }
+        None
+    }
 
-    let server_socket = socket.clone();
-    let listener = Listener::bind(&server_socket)?;
-    let server = tokio::spawn(async move {
-        listen(listener, Proxy::new(backend_socket_path, fatal_tx)).await
-    });
-    tokio::pin!(server);
+    /// Why signing on this forwarded connection is refused, if it is forwarded and refused
+    async fn forwarded_denial(&self, request: &SignRequest) -> Option<Denial> {
+        if !self.forwarded {
+            return None;
+        }
 
-    let mut child = match cmd.spawn() {
-        Ok(child) => child,
-        Err(e) => {
-            server.abort();
-            if socket.exists() {
-                let _ = fs::remove_file(&socket);
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        let action = format!("signing with {key}");
+        let client = &self.client;
+        match self.policy.on_forwarded {
+            OnForwarded::Warn => {
+                warn!("Signing with {key} on a forwarded connection from {client}");
+                None
+            }
+            OnForwarded::Confirm => {
+                let prompt = format!("Allow {action} on a forwarded connection from {client}?");
+                (!askpass::confirm(&prompt).await)
+                    .then(|| Denial::new(action, "forwarded connection not confirmed"))
             }
-            return Err(format!("Failed to spawn {}: {}", args.bin, e).into());
+            OnForwarded::Deny => Some(Denial::new(action, "connection is forwarded")),
         }
-    };
+    }
 
-    let mut fatal_rx_active = true;
-    let child_status = loop {
-        tokio::select! {
-            status = child.wait() => break status?,
-            result = &mut server => {
-                let _ = child.kill().await;
-                let _ = child.wait().await;
-                if socket.exists() {
-                    let _ = fs::remove_file(&socket);
-                }
-                return Err(format!("Proxy exited unexpectedly: {:?}", result).into());
-            },
-            result = fatal_rx.changed(), if fatal_rx_active => {
-                if result.is_ok() && *fatal_rx.borrow() {
-                    let _ = child.kill().await;
-                    let _ = child.wait().await;
-                    if socket.exists() {
-                        let _ = fs::remove_file(&socket);
-                    }
-                    return Err("Proxy aborted due to backend connection failure.".into());
-                }
-                if result.is_err() {
-                    fatal_rx_active = false;
-                }
-                continue;
-            },
-            _ = signal::ctrl_c() => {
-                let _ = child.kill().await;
-                break child.wait().await?;
+    /// Why signing with a key the proxy confirms itself is refused, if it is not confirmed
+    async fn confirm_denial(&self, request: &SignRequest) -> Option<Denial> {
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        let comment = self.policy.sign_confirmations.comment(&key)?;
+        if self.bypasses_confirmation(&key) {
+            return None;
+        }
+        // Worded like ssh-agent's own prompt, which the user would otherwise see
+        let prompt = format!("Allow use of key {comment}?\nKey fingerprint {key}.");
+        (!askpass::confirm(&prompt).await)
+            .then(|| Denial::new(format!("signing with {key}"), "not confirmed"))
+    }
+
+    /// Whether the client may sign with `key` without the confirmation it would need, logging it if so
+    fn bypasses_confirmation(&self, key: &Fingerprint) -> bool {
+        let bypassed = keyusers::bypassed(&self.policy.confirm_bypass, self.client.uid);
+        if !bypassed.contains(key) {
+            return false;
+        }
+        warn!(
+            "Signing with {key} for {} without confirmation: bypassed for this UID",
+            self.client
+        );
+        true
+    }
+
+    /// Why signing with a key that must be confirmed is refused, if it is not confirmed
+    async fn key_confirm_denial(&self, request: &SignRequest) -> Option<Denial> {
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        let otherwise = keyconfirm::required(&self.policy.key_confirms, &key)?;
+        if self.bypasses_confirmation(&key) {
+            return None;
+        }
+
+        let action = format!("signing with {key}");
+        let client = &self.client;
+        match askpass::try_confirm(&format!("Allow {action} for {client}?")).await {
+            Some(true) => None,
+            Some(false) => Some(Denial::new(action, "not confirmed")),
+            None if otherwise == Unconfirmable::Deny => {
+                Some(Denial::new(action, "no confirmer could ask the user"))
+            }
+            None => {
+                warn!("Allowing {action} for {client} unconfirmed: no confirmer could ask");
+                None
             }
         }
-    };
+    }
 
-    server.abort();
-    if socket.exists() {
-        let _ = fs::remove_file(&socket);
+    /// Why signing with a key that needs a one-time code is refused, if the code is missing or wrong
+    async fn totp_denial(&self, request: &SignRequest) -> Option<Denial> {
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        if !self.policy.key_totps.requires(&key) {
+            return None;
+        }
+
+        let action = format!("signing with {key}");
+        let client = &self.client;
+        let prompt = format!("Enter the one-time code to allow {action} for {client}:");
+        match askpass::ask(&prompt).await {
+            Some(code) if self.policy.key_totps.verify(&key, &code) => None,
+            Some(_) => Some(Denial::new(action, "one-time code is invalid or expired")),
+            None => Some(Denial::new(action, "no one-time code was entered")),
+        }
     }
 
-    if !child_status.success() {
-        return Err(format!("Command exited with status: {}", child_status).into());
+    /// Why an extension request is refused, if it is
+    fn extension_denial(&self, ext: &Extension) -> Option<Denial> {
+        // Checked first, so no payload over the limit is ever parsed
+        let len = ext.details.as_ref().len();
+        if let Some(max) = self.policy.max_extension_bytes
+            && len > max
+        {
+            let name = &ext.name;
+            let reason = format!("payload is {len} bytes, over {max}");
+            return Some(Denial::new(format!("handling extension {name}"), reason));
+        }
+
+        if ext.name == SessionBind::NAME {
+            return self.session_bind_denial(ext);
+        }
+        None
     }
 
-    Ok(())
+    /// Why binding this connection to a host is refused, if it is
+    fn session_bind_denial(&self, ext: &Extension) -> Option<Denial> {
+        if self.policy.allowed_bind_hosts.is_empty() {
+            return None;
+        }
+        let action = "binding the connection";
+        let Some(bind) = ext.parse_message::<SessionBind>().ok().flatten() else {
+            return Some(Denial::new(action, "malformed session-bind"));
+        };
+
+        let host = bind.host_key.fingerprint(HashAlg::Sha256);
+        if self.policy.allowed_bind_hosts.contains(&host) {
+            return None;
+        }
+        let reason = format!("host key {host} is not allowed");
+        Some(Denial::new(action, reason))
+    }
+
+    /// Why the host this connection is bound to may not receive signatures, if it may not
+    fn host_denial(&self, key: &Fingerprint) -> Option<String> {
+        if self.policy.allowed_hosts.is_empty() {
+            return None;
+        }
+
+        match &self.bound_host {
+            Some(host) if self.policy.allowed_hosts.contains(host) => {
+                info!("Signing with {key} for host key {host}");
+                None
+            }
+            Some(host) => Some(format!("host key {host} is not allowed")),
+            None => Some("connection is not bound to a host".to_string()),
+        }
+    }
+
+    /// Why a key of this strength and type may not be added, if it may not
+    fn key_denial(&self, credential: &Credential) -> Option<Denial> {
+        let Some(key) = keys::credential_key(credential) else {
+            return Some(Denial::new(
+                "adding a key",
+                "cannot determine its public key",
+            ));
+        };
+        let algorithm = key.algorithm();
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        let action = format!("adding {fingerprint}");
+
+        if let Some(allowed) = &self.policy.allowed_keys
+            && !allowed.contains(&fingerprint)
+        {
+            return Some(Denial::new(action, "key is not on the allow-list"));
+        }
+
+        if !self.policy.allowed_key_types.is_empty()
+            && !self.policy.allowed_key_types.contains(&algorithm)
+        {
+            return Some(Denial::new(
+                action,
+                format!("key type {algorithm} is not allowed"),
+            ));
+        }
+
+        if let Some(min_bits) = self.policy.min_rsa_bits {
+            let too_weak = match &key {
+                KeyData::Dsa(_) => true,
+                KeyData::Rsa(rsa) => keys::rsa_bits(rsa) < min_bits,
+                _ => false,
+            };
+            if too_weak {
+                return Some(Denial::new(
+                    action,
+                    format!("{algorithm} key is below the minimum strength"),
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// Why the constraint extensions a client adds a key with are refused, if they are
+    fn constraint_denial(&self, constraints: &[KeyConstraint]) -> Option<Denial> {
+        let action = "adding a constrained key";
+        for constraint in constraints {
+            let KeyConstraint::Extension(extension) = constraint else {
+                continue;
+            };
+            let name = &extension.name;
+            let allowed = &self.policy.allowed_constraint_extensions;
+            if !allowed.is_empty() && !allowed.contains(name) {
+                let reason = format!("constraint extension {name} is not allowed");
+                return Some(Denial::new(action, reason));
+            }
+
+            let len = extension.details.as_ref().len();
+            if let Some(max) = self.policy.max_constraint_extension_bytes
+                && len > max
+            {
+                let reason = format!("constraint extension {name} is {len} bytes, over {max}");
+                return Some(Denial::new(action, reason));
+            }
+        }
+        None
+    }
+
+    /// Add the constraints enforced by the proxy to those requested by the client
+    fn constrain(&self, constraints: &mut Vec<KeyConstraint>) {
+        match self.policy.enforced_constraint {
+            // Ensure confirm constraint is present
+            EnforcedConstraint::Confirm => {
+                if !constraints
+                    .iter()
+                    .any(|c| matches!(c, KeyConstraint::Confirm))
+                {
+                    constraints.push(KeyConstraint::Confirm);
+                }
+            }
+            // Ensure the key expires no later than the enforced lifetime
+            EnforcedConstraint::Lifetime(max) => {
+                let mut found = false;
+                for constraint in constraints.iter_mut() {
+                    if let KeyConstraint::Lifetime(lifetime) = constraint {
+                        *lifetime = (*lifetime).min(max);
+                        found = true;
+                    }
+                }
+                // The default lifetime still applies, but never beyond the maximum
+                if !found {
+                    let lifetime = self.policy.default_lifetime.map_or(max, |d| d.min(max));
+                    constraints.push(KeyConstraint::Lifetime(lifetime));
+                }
+            }
+            // A lifetime protects the key well enough on its own
+            EnforcedConstraint::ConfirmOrLifetime => {
+                if !constraints
+                    .iter()
+                    .any(|c| matches!(c, KeyConstraint::Confirm | KeyConstraint::Lifetime(_)))
+                {
+                    constraints.push(KeyConstraint::Confirm);
+                }
+            }
+            EnforcedConstraint::None => {}
+        }
+
+        // Only fill in a lifetime when the client did not choose one
+        if let Some(lifetime) = self.policy.default_lifetime
+            && !constraints
+                .iter()
+                .any(|c| matches!(c, KeyConstraint::Lifetime(_)))
+        {
+            constraints.push(KeyConstraint::Lifetime(lifetime));
+        }
+    }
+
+    /// Why the signing key may not be used for lack of the required comment, if it may not
+    async fn comment_denial(
+        &mut self,
+        request: &SignRequest,
+    ) -> Result<Option<Denial>, AgentError> {
+        let policy = self.policy.clone();
+        let Some(required) = &policy.required_comment else {
+            return Ok(None);
+        };
+
+        let comment = self.key_comment(&request.pubkey).await?;
+        if comment.is_some_and(|comment| comment.contains(required.as_str())) {
+            return Ok(None);
+        }
+
+        let key = request.pubkey.fingerprint(HashAlg::Sha256);
+        Ok(Some(Denial::new(
+            format!("signing with {key}"),
+            format!("key comment does not contain {required:?}"),
+        )))
+    }
+
+    /// Comment of `key` as listed by the backend, if it holds the key
+    ///
+    /// Listed at most once per request, however many checks need the comment.
+    async fn key_comment(&mut self, key: &KeyData) -> Result<Option<String>, AgentError> {
+        if let Some((listed, comment)) = &self.listed_comment
+            && listed == key
+        {
+            return Ok(comment.clone());
+        }
+        let identities = match self.backend.handle(Request::RequestIdentities).await? {
+            Response::IdentitiesAnswer(identities) => identities,
+            _ => Vec::new(),
+        };
+        let comment = identities
+            .into_iter()
+            .find(|id| &id.pubkey == key)
+            .map(|id| id.comment);
+        self.listed_comment = Some((key.clone(), comment.clone()));
+        Ok(comment)
+    }
+
+    /// Why the policy script refuses `message`, if it does
+    #[cfg(feature = "policy-script")]
+    async fn script_denial(&mut self, message: &Request) -> Result<Option<Denial>, AgentError> {
+        use script::Decision;
+
+        let policy = self.policy.clone();
+        let Some(script) = &policy.script else {
+            return Ok(None);
+        };
+
+        let comment = match message {
+            Request::SignRequest(request) => self.key_comment(&request.pubkey).await?,
+            Request::AddIdentity(add) => Some(keys::credential_comment(&add.credential).into()),
+            Request::AddIdConstrained(add) => {
+                Some(keys::credential_comment(&add.identity.credential).into())
+            }
+            _ => None,
+        };
+        let action = format!("{} request", request::request_name(message));
+        let denial = match script.decide(message, comment.as_deref(), &self.client) {
+            Ok(Decision::Allow) => None,
+            Ok(Decision::Deny) => Some(Denial::new(action, "denied by the policy script")),
+            Ok(Decision::Confirm) => {
+                let prompt = format!("Allow {action} for {}?", self.client);
+                (!askpass::confirm(&prompt).await).then(|| Denial::new(action, "not confirmed"))
+            }
+            Err(e) => Some(Denial::new(action, format!("policy script failed: {e}"))),
+        };
+        Ok(denial)
+    }
+
+    /// Add a key with the enforced constraints; `plain` if the client sent it without constraints
+    async fn add_constrained(
+        &mut self,
+        mut add: AddIdentityConstrained,
+        plain: bool,
+    ) -> Result<Response, AgentError> {
+        if let Some(filter) = &self.policy.comment_filter {
+            let credential = &mut add.identity.credential;
+            if let Some(comment) = filter.rewrite(keys::credential_comment(credential)).await {
+                keys::set_credential_comment(credential, comment);
+            }
+        }
+        self.constrain(&mut add.constraints);
+        let confirm_on_sign = plain
+            && self.policy.sign_confirmations.enabled()
+            && add.constraints == [KeyConstraint::Confirm];
+        let key = keys::credential_key(&add.identity.credential);
+        let comment = keys::credential_comment(&add.identity.credential).to_string();
+        let expires = add
+            .constraints
+            .iter()
+            .any(|c| matches!(c, KeyConstraint::Lifetime(_)));
+        let confirm = add.constraints.contains(&KeyConstraint::Confirm);
+
+        let request = if confirm_on_sign {
+            Request::AddIdentity(add.identity)
+        } else {
+            Request::AddIdConstrained(add)
+        };
+        let response = self.backend.handle(request).await?;
+        if let (Response::Success, Some(key)) = (&response, key) {
+            let fingerprint = key.fingerprint(HashAlg::Sha256);
+            self.key_ages.added(fingerprint, expires, confirm);
+            let confirmations = &self.policy.sign_confirmations;
+            confirmations.set(fingerprint, comment.clone(), confirm_on_sign);
+
+            if let Some(old) = self.policy.key_rotations.added(&comment, &key) {
+                let old_fingerprint = old.fingerprint(HashAlg::Sha256);
+                info!("Key rotated: {comment:?} changed from {old_fingerprint} to {fingerprint}");
+                if self.policy.key_rotations.remove_old() {
+                    let remove = RemoveIdentity { pubkey: old };
+                    match self.backend.handle(Request::RemoveIdentity(remove)).await {
+                        Ok(Response::Success) => {
+                            self.key_ages.removed(&old_fingerprint);
+                            info!("Removed rotated key {old_fingerprint}");
+                        }
+                        Ok(_) | Err(_) => warn!("Failed to remove rotated key {old_fingerprint}"),
+                    }
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    /// Whether the backend signed with an algorithm the key is not allowed to produce
+    fn disallowed_signature(&self, fingerprint: &Fingerprint, signature: &Signature) -> bool {
+        let limits = &self.policy.key_signature_algorithms;
+        let Some(algorithm) = sigalg::disallowed_signature(limits, fingerprint, signature) else {
+            return false;
+        };
+        error!(
+            "Backend signed with {algorithm}, which is not allowed for {fingerprint}; refusing to pass it on"
+        );
+        true
+    }
+
+    /// Record a signature the backend made, for the checks that count or remember them
+    ///
+    /// Refused and failed requests never get here, so they use up no limit.
+    fn signed(&self, fingerprint: &Fingerprint, flags: u32) {
+        self.key_ages.signed(fingerprint);
+        self.policy.flag_profiles.signed(fingerprint, flags);
+    }
+
+    async fn list_with_retries(&mut self) -> Result<Response, AgentError> {
+        let mut delay = Duration::from_millis(50);
+        let mut attempt = 0;
+        loop {
+            let result = self.backend.handle(Request::RequestIdentities).await;
+            let failed = matches!(result, Err(_) | Ok(Response::Failure));
+            if !failed || attempt >= self.policy.list_retries {
+                return result;
+            }
+            attempt += 1;
+            debug!(
+                "Identity listing failed (attempt {attempt}/{}), retrying in {delay:?}",
+                self.policy.list_retries
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+#[ssh_agent_lib::async_trait]
+impl Session for ProxySession {
+    async fn handle(&mut self, message: Request) -> Result<Response, AgentError> {
+        if self.logging.debug_proto {
+            dump::request(&message);
+        }
+        let process = openssh::process_name(&message);
+        if self.logging.format == LogFormat::OpensshDebug {
+            for line in openssh::request_lines(&message, &self.client) {
+                redact::eprintln(&line);
+            }
+        }
+
+        let level = self.logging.levels.request(&message);
+        let logged = level
+            .filter(|&level| log::log_enabled!(level))
+            .map(|level| (level, loglevel::summary(&message)));
+
+        #[cfg(unix)]
+        let audited = {
+            let audit = self.logging.audit.clone();
+            audit.map(|audit| (audit, dump::request_json(&message)))
+        };
+
+        let started = Instant::now();
+        self.denied = false;
+        self.reserved = None;
+        self.listed_comment = None;
+        let result = self.handle_request(message).await;
+        // Given back as it drops, unless the backend signed
+        if let Some(reservation) = self.reserved.take()
+            && matches!(result, Ok(Response::SignResponse(_)))
+        {
+            reservation.keep();
+        }
+        if self.denied
+            && let Some(millis) = self.policy.constant_time_denies_ms
+        {
+            denial::pad(started, Duration::from_millis(millis)).await;
+        }
+
+        if self.logging.debug_proto {
+            dump::response(&result);
+        }
+        if self.logging.format == LogFormat::OpensshDebug {
+            for line in openssh::response_lines(process, &result) {
+                redact::eprintln(&line);
+            }
+        }
+        // Denials were already logged, with the reason
+        if let Some((level, summary)) = logged.filter(|_| !self.denied) {
+            let outcome = loglevel::outcome(&result);
+            log!(level, "Handled {summary} from {}: {outcome}", self.client);
+        }
+        #[cfg(unix)]
+        if let Some((audit, request)) = audited {
+            let denial = self.last_denial.as_ref().filter(|_| self.denied);
+            audit.send(audit::event(&self.client, request, &result, denial));
+        }
+        result
+    }
+}
+
+impl ProxySession {
+    /// Apply the policy to `message`, answering it locally or through the backend
+    async fn handle_request(&mut self, message: Request) -> Result<Response, AgentError> {
+        self.stats.record(&message);
+
+        // The proxy's own extensions stay answered, so a frozen proxy can still be inspected
+        if let Some(kill_switch) = &self.policy.kill_switch
+            && kill_switch.blocks(&message)
+            && !self.is_local_request(&message)
+        {
+            let reason = format!("kill switch {} is engaged", kill_switch.path().display());
+            return self.deny(Denial::new("request", reason));
+        }
+
+        if let Some(health) = &self.health
+            && !health.is_healthy()
+            && !self.is_local_request(&message)
+        {
+            return self.deny(Denial::new("request", "backend ssh-agent is unhealthy"));
+        }
+
+        if let Request::SignRequest(request) = &message {
+            let mut denial = self.sign_denial(request);
+            if denial.is_none() {
+                denial = self.reserve(request);
+            }
+            if denial.is_none() {
+                denial = self.comment_denial(request).await?;
+            }
+            if denial.is_none() {
+                denial = self.device_denial(request).await;
+            }
+            // Last, so the user is only asked about requests that would otherwise go through
+            if denial.is_none() {
+                denial = self.forwarded_denial(request).await;
+            }
+            if denial.is_none() {
+                denial = self.totp_denial(request).await;
+            }
+            if denial.is_none() {
+                denial = self.key_confirm_denial(request).await;
+            }
+            if denial.is_none() {
+                denial = self.confirm_denial(request).await;
+            }
+            if let Some(denial) = denial {
+                return self.deny(denial);
+            }
+            self.key_ages
+                .check(&request.pubkey.fingerprint(HashAlg::Sha256));
+        }
+
+        let lock_action = match &message {
+            Request::Lock(_) => Some("locking the agent"),
+            Request::Unlock(_) => Some("unlocking the agent"),
+            _ => None,
+        };
+        if let Some(action) = lock_action {
+            if self.policy.block_unlock && matches!(message, Request::Unlock(_)) {
+                return self.deny(Denial::new(action, "unlocking is blocked"));
+            }
+            if self.policy.confirm_lock_unlock
+                && !askpass::confirm(&format!("Allow {action} for {}?", self.client)).await
+            {
+                return self.deny(Denial::new(action, "not confirmed"));
+            }
+        }
+
+        let removal = match &message {
+            Request::RemoveIdentity(remove) => Some(format!(
+                "removing key {}",
+                remove.pubkey.fingerprint(HashAlg::Sha256)
+            )),
+            Request::RemoveAllIdentities => Some("removing all keys".to_string()),
+            _ => None,
+        };
+        if let Some(action) = removal.filter(|_| self.policy.confirm_removals)
+            && !askpass::confirm(&format!("Allow {action} for {}?", self.client)).await
+        {
+            return self.deny(Denial::new(action, "not confirmed"));
+        }
+
+        let adding = matches!(
+            message,
+            Request::AddIdentity(_)
+                | Request::AddIdConstrained(_)
+                | Request::AddSmartcardKey(_)
+                | Request::AddSmartcardKeyConstrained(_)
+        );
+        if adding
+            && let Some(window) = self.policy.add_window
+            && self.stats.uptime() >= Duration::from_secs(window)
+        {
+            let reason = format!("the {window}s window for adding keys after startup has closed");
+            return self.deny(Denial::new("adding a key", reason));
+        }
+
+        let added = match &message {
+            Request::AddIdentity(add) => Some(&add.credential),
+            Request::AddIdConstrained(add) => Some(&add.identity.credential),
+            _ => None,
+        };
+        if let Some(denial) = added.and_then(|credential| self.key_denial(credential)) {
+            return self.deny(denial);
+        }
+
+        let constraints = match &message {
+            Request::AddIdConstrained(add) => &add.constraints[..],
+            Request::AddSmartcardKeyConstrained(add) => &add.constraints[..],
+            _ => &[],
+        };
+        if let Some(denial) = self.constraint_denial(constraints) {
+            return self.deny(denial);
+        }
+
+        if let Request::Extension(ext) = &message
+            && let Some(denial) = self.extension_denial(ext)
+        {
+            return self.deny(denial);
+        }
+
+        #[cfg(feature = "policy-script")]
+        if !self.is_local_request(&message)
+            && let Some(denial) = self.script_denial(&message).await?
+        {
+            return self.deny(denial);
+        }
+
+        match message {
+            // Listing is idempotent, so transient backend failures are safe to retry
+            Request::RequestIdentities => {
+                let mut response = self.list_with_retries().await?;
+                if let Response::IdentitiesAnswer(identities) = &mut response {
+                    let listed: Vec<_> = identities
+                        .iter()
+                        .map(|id| id.pubkey.fingerprint(HashAlg::Sha256))
+                        .collect();
+                    self.key_ages.retain_listed(&listed);
+                    for fingerprint in &listed {
+                        self.key_ages.check(fingerprint);
+                    }
+
+                    // Hide keys this client could not sign with anyway
+                    let mut listed = listed.iter();
+                    let (uid, sk_users) = (self.client.uid, self.policy.sk_key_users.as_deref());
+                    identities.retain(|id| {
+                        let fingerprint = listed.next().expect("one fingerprint per identity");
+                        keyusers::permitted(&self.policy.key_users, fingerprint, uid)
+                            && keyusers::sk_permitted(sk_users, &id.pubkey, uid)
+                    });
+                    self.policy.fake_identities.extend(identities);
+                    self.policy.identity_order.apply(identities);
+                }
+                Ok(response)
+            }
+            Request::AddIdentity(add) => {
+                // Rewrite to constrained add with confirm
+                let add = AddIdentityConstrained {
+                    identity: add,
+                    constraints: vec![],
+                };
+                self.add_constrained(add, true).await
+            }
+            Request::AddIdConstrained(add_con) => self.add_constrained(add_con, false).await,
+            Request::RemoveIdentity(remove) => {
+                let fingerprint = remove.pubkey.fingerprint(HashAlg::Sha256);
+                let response = self.backend.handle(Request::RemoveIdentity(remove)).await?;
+                if let Response::Success = response {
+                    self.key_ages.removed(&fingerprint);
+                    self.policy.sign_confirmations.removed(&fingerprint);
+                }
+                Ok(response)
+            }
+            Request::RemoveAllIdentities => {
+                let response = self.backend.handle(Request::RemoveAllIdentities).await?;
+                if let Response::Success = response {
+                    self.key_ages.removed_all();
+                    self.policy.sign_confirmations.removed_all();
+                }
+                Ok(response)
+            }
+            Request::SignRequest(request)
+                if self.policy.fake_identities.contains(&request.pubkey) =>
+            {
+                let fingerprint = request.pubkey.fingerprint(HashAlg::Sha256);
+                debug!("Answering sign request for synthetic identity {fingerprint}");
+                Ok(self.policy.fake_identities.sign_response())
+            }
+            Request::SignRequest(request) if self.policy.verify_signatures => {
+                let fingerprint = request.pubkey.fingerprint(HashAlg::Sha256);
+                let response = self
+                    .backend
+                    .handle(Request::SignRequest(request.clone()))
+                    .await?;
+                if let Response::SignResponse(signature) = &response {
+                    match verify_signature(&request, signature) {
+                        Verification::Valid => {}
+                        Verification::Invalid => {
+                            error!(
+                                "Backend returned an invalid {} signature; refusing to pass it on",
+                                signature.algorithm()
+                            );
+                            return Ok(Response::Failure);
+                        }
+                        Verification::Unsupported => warn!(
+                            "Cannot verify {} signature, passing it on unchecked",
+                            signature.algorithm()
+                        ),
+                    }
+                    if self.disallowed_signature(&fingerprint, signature) {
+                        return Ok(Response::Failure);
+                    }
+                    self.signed(&fingerprint, request.flags);
+                }
+                Ok(response)
+            }
+            Request::SignRequest(request) => {
+                let fingerprint = request.pubkey.fingerprint(HashAlg::Sha256);
+                let flags = request.flags;
+                let response = self.backend.handle(Request::SignRequest(request)).await?;
+                if let Response::SignResponse(signature) = &response {
+                    if self.disallowed_signature(&fingerprint, signature) {
+                        return Ok(Response::Failure);
+                    }
+                    self.signed(&fingerprint, flags);
+                }
+                Ok(response)
+            }
+            // Answer introspection locally, without touching the backend
+            Request::Extension(ext) if self.is_info_request(&ext) => {
+                Ok(Response::ExtensionResponse(info_response(
+                    &self.policy,
+                    &self.stats,
+                    &self.key_ages,
+                    self.health.as_deref(),
+                )))
+            }
+            Request::Extension(ext) if self.is_why_denied_request(&ext) => Ok(
+                Response::ExtensionResponse(why_denied_response(self.last_denial.as_ref())),
+            ),
+            // Spare the round-trip for an extension the backend said it does not support
+            Request::Extension(ext) if self.policy.backend_extensions.unsupported(&ext.name) => {
+                debug!("Backend does not support extension {:?}", ext.name);
+                Ok(Response::Failure)
+            }
+            // Remember where this connection authenticates to, then let the backend verify the binding
+            Request::Extension(ext) if ext.name == SessionBind::NAME => {
+                let bind = ext.parse_message::<SessionBind>().ok().flatten();
+                let response = self.backend.handle(Request::Extension(ext)).await?;
+                if let (Response::Success, Some(bind)) = (&response, bind) {
+                    let host = bind.host_key.fingerprint(HashAlg::Sha256);
+                    debug!("Connection from {} bound to host key {host}", self.client);
+                    self.bound_host = Some(host);
+                    self.forwarded |= bind.is_forwarding;
+                }
+                Ok(response)
+            }
+            // Forward everything else unchanged
+            msg => self.backend.handle(msg).await,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Proxy {
+    /// Connect to the backend, aborting the proxy if it is gone and no health monitor runs
+    ///
+    /// Whatever the failure, this connection is served by a backend that fails
+    /// every request; only a backend that is gone shuts the proxy down.
+    fn backend_or_abort(&self, client: &ClientInfo) -> Box<dyn Session> {
+        self.connect_backend(client).unwrap_or_else(|e| {
+            error!("Failed to establish connection to ssh-agent backend: {e}");
+            // With a health monitor, an outage fails requests instead of taking the proxy down
+            if e.backend_gone() && self.health.is_none() {
+                let _ = self.fatal_tx.send(true);
+            }
+            Box::new(UnreachableBackend)
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Agent<Listener> for Proxy {
+    fn new_session(&mut self, socket: &tokio::net::UnixStream) -> impl Session {
+        let client = ClientInfo::from_stream(socket, self.logging.client_cmdline);
+        let backend = self.backend_or_abort(&client);
+        self.session(backend, client)
+    }
+}
+
+#[cfg(unix)]
+impl Agent<heal::HealingListener> for Proxy {
+    fn new_session(&mut self, socket: &tokio::net::UnixStream) -> impl Session {
+        Agent::<Listener>::new_session(self, socket)
+    }
+}
+
+#[cfg(all(feature = "vsock", target_os = "linux"))]
+impl Agent<vsock::Listener> for Proxy {
+    fn new_session(&mut self, socket: &tokio_vsock::VsockStream) -> impl Session {
+        if let Ok(peer) = socket.peer_addr() {
+            debug!("Vsock connection from CID {}", peer.cid());
+        }
+        // Peer credentials do not cross the VM boundary
+        let client = ClientInfo::default();
+        let backend = self.backend_or_abort(&client);
+        self.session(backend, client)
+    }
+}
+
+#[cfg(windows)]
+impl Agent<Listener> for Proxy {
+    fn new_session(
+        &mut self,
+        _: &tokio::net::windows::named_pipe::NamedPipeServer,
+    ) -> impl Session {
+        let client = ClientInfo::default();
+        let backend = self.connect_backend(&client).unwrap_or_else(|e| {
+            error!("Failed to establish connection to ssh-agent backend: {e}");
+            Box::new(UnreachableBackend)
+        });
+
+        self.session(backend, client)
+    }
+}
+
+/// Connect to the backend pipe, retrying while ssh-agent may still be creating it
+#[cfg(windows)]
+fn connect_named_pipe(path: &std::path::Path) -> Result<Box<dyn Session>, ProxyError> {
+    const ATTEMPTS: u32 = 5;
+    const DELAY: Duration = Duration::from_millis(100);
+
+    let mut attempt = 1;
+    loop {
+        match backend::connect(path) {
+            Err(e) if attempt < ATTEMPTS => {
+                debug!("Backend pipe not ready (attempt {attempt}/{ATTEMPTS}): {e}");
+                attempt += 1;
+                // `new_session` is synchronous, like the pipe-busy wait inside `connect`
+                std::thread::sleep(DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Bind the proxy socket, queueing `backlog` pending connections if given instead of the default
+///
+/// With a `group`, the socket is shared read/write with it.
+#[cfg(unix)]
+fn bind_listener(
+    path: &std::path::Path,
+    backlog: Option<u32>,
+    group: Option<&nix::unistd::Group>,
+) -> std::io::Result<Listener> {
+    let listener = match backlog {
+        Some(backlog) => {
+            let socket = tokio::net::UnixSocket::new_stream()?;
+            socket.bind(path)?;
+            socket.listen(backlog)?
+        }
+        None => Listener::bind(path)?,
+    };
+    if let Some(group) = group {
+        use std::os::unix::fs::PermissionsExt;
+
+        let uid = nix::unistd::getuid().as_raw();
+        std::os::unix::fs::chown(path, Some(uid), Some(group.gid.as_raw()))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+    }
+    Ok(listener)
+}
+
+/// Remove the proxy socket, on every way out once it was bound
+fn remove_socket(socket: &std::path::Path) {
+    if socket.exists() {
+        let _ = fs::remove_file(socket);
+    }
+}
+
+/// Listen for ctrl+c, or for the request to stop sent by a service manager
+///
+/// That is SIGTERM on Unix, and closing the console or shutting down on Windows.
+/// Listening starts before the returned future is first polled, so a request
+/// to stop that arrives while the proxy is still starting is not lost.
+fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    {
+        use signal::unix::{SignalKind, signal};
+
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        Ok(async move {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+        })
+    }
+    #[cfg(windows)]
+    {
+        let mut ctrl_c = signal::windows::ctrl_c()?;
+        let mut close = signal::windows::ctrl_close()?;
+        let mut shutdown = signal::windows::ctrl_shutdown()?;
+        Ok(async move {
+            tokio::select! {
+                _ = ctrl_c.recv() => {}
+                _ = close.recv() => {}
+                _ = shutdown.recv() => {}
+            }
+        })
+    }
+}
+
+/// Backend for a connection whose ssh-agent could not be reached: every request fails
+struct UnreachableBackend;
+
+#[ssh_agent_lib::async_trait]
+impl Session for UnreachableBackend {
+    async fn handle(&mut self, _: Request) -> Result<Response, AgentError> {
+        Ok(Response::Failure)
+    }
+}
+
+/// Log level of the proxy selected by -q and repeated -v; RUST_LOG directives override it
+fn log_level(verbose: u8, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let mut runtime = match args.runtime {
+        RuntimeFlavor::CurrentThread if args.worker_threads.is_some() => {
+            return Err("--worker-threads needs --runtime multi-thread".into());
+        }
+        RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+        RuntimeFlavor::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+    };
+    if let Some(threads) = args.worker_threads {
+        runtime.worker_threads(threads.get());
+    }
+    runtime.enable_all().build()?.block_on(run(args))
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    // Dependencies stay at warnings; their debug output is mostly raw protocol dumps
+    let mut logger = env_logger::Builder::new();
+    logger
+        .filter_level(LevelFilter::Warn)
+        .filter_module(module_path!(), log_level(args.verbose, args.quiet))
+        .parse_default_env();
+    if args.redact_paths {
+        logger.format(redact::format);
+        redact::enable();
+    }
+    logger.init();
+
+    if let Some(secs) = args.warn_pending_confirm {
+        askpass::warn_pending_after(Duration::from_secs(secs));
+    }
+    if !args.confirmer.is_empty() {
+        askpass::confirm_through(args.confirmer.clone());
+    }
+
+    #[cfg(feature = "otel")]
+    let _otel = args.otlp_endpoint.as_deref().map(otel::init).transpose()?;
+
+    let sign_socket = match &args.mode {
+        Some(Mode::Sign(sign)) => sign.socket.clone(),
+        None => None,
+    };
+    let backend_socket_paths = if args.in_process_backend {
+        Vec::new()
+    } else if let Some(path) = sign_socket {
+        vec![path]
+    } else if args.backend_sock.is_empty() {
+        let backend_socket = std::env::var_os("SSH_AUTH_SOCK")
+            .ok_or("Missing SSH_AUTH_SOCK for backend ssh-agent socket.")?;
+        vec![PathBuf::from(backend_socket)]
+    } else {
+        args.backend_sock
+    };
+    let backends = if args.in_process_backend {
+        Backends::InProcess(InProcessAgent::default())
+    } else {
+        Backends::Sockets(backend_socket_paths.clone())
+    };
+
+    // A per-feature file replaces the shared one for that feature and keeps its own format
+    let shared_store = args.state_file.map(JsonFile::open);
+    let quota_store = args
+        .quota_state
+        .map(|path| JsonFile::open_section(path, quota::STATE_SECTION))
+        .or_else(|| shared_store.clone());
+    let usage_store = args
+        .key_usage_state
+        .map(|path| JsonFile::open_section(path, keyage::STATE_SECTION))
+        .or_else(|| shared_store.clone());
+    let stores: Vec<Store> = [shared_store, quota_store.clone(), usage_store.clone()]
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let policy = Policy {
+        info_extension: args.enable_info_extension,
+        why_denied_extension: args.enable_why_denied_extension,
+        list_retries: args.list_retries,
+        kill_switch: args
+            .kill_switch
+            .map(|path| KillSwitch::new(path, args.kill_switch_scope)),
+        verify_signatures: args.verify_signatures,
+        identity_order: args.identity_order,
+        fake_identities: FakeIdentities::new(args.fake_identity, args.fake_sign_response),
+        sign_confirmations: SignConfirmations::new(args.no_upgrade_unconstrained),
+        enforced_constraint: if args.require_protection {
+            EnforcedConstraint::ConfirmOrLifetime
+        } else {
+            args.enforce_constraint
+        },
+        reject_foreign_sign: args.reject_foreign_sign,
+        confirm_lock_unlock: args.confirm_lock_unlock,
+        confirm_removals: args.confirm_removals,
+        block_unlock: args.block_unlock,
+        default_lifetime: args.default_lifetime,
+        add_window: args.add_window,
+        deny_when_locked: args.deny_when_locked,
+        deny_on_battery_below: args.deny_on_battery,
+        key_rotations: Rotations::new(args.remove_rotated_keys),
+        constant_time_denies_ms: args.constant_time_denies,
+        on_forwarded: args.on_forwarded,
+        allowed_hosts: args.allow_host,
+        allowed_bind_hosts: args.allow_bind_host,
+        key_users: args.key_uid,
+        sk_key_users: args.sk_key_uid,
+        key_totps: Totps::new(args.key_totp),
+        key_confirms: args.key_confirm,
+        confirm_bypass: args.confirm_bypass,
+        key_signature_algorithms: args.key_sign_alg,
+        key_sign_prefixes: args.key_sign_prefix,
+        flag_profiles: FlagProfiles::new(args.anomaly, args.anomaly_baseline),
+        replay_guard: ReplayGuard::new(args.replay_window),
+        backend_extensions: BackendExtensions::new(args.probe_extensions),
+        key_rate_limits: RateLimiter::new(args.key_rate_limit),
+        key_quotas: Quotas::new(args.key_quota, quota_store),
+        required_comment: args.require_comment,
+        comment_filter: args.comment_filter.map(CommentFilter::new),
+        #[cfg(feature = "policy-script")]
+        script: args
+            .policy_script
+            .map(script::PolicyScript::load)
+            .transpose()?,
+        min_rsa_bits: args.min_rsa_bits,
+        allowed_key_types: args.allow_key_type,
+        allowed_constraint_extensions: args.allow_constraint_extension,
+        max_constraint_extension_bytes: args.max_constraint_extension_bytes,
+        max_extension_bytes: args.max_extension_bytes,
+        allowed_keys: args
+            .allowed_keys_dir
+            .map(allowlist::AllowedKeys::load)
+            .transpose()?,
+    };
+
+    let logging = Logging {
+        client_cmdline: args.log_client_cmdline,
+        debug_proto: args.debug_proto,
+        format: args.log_format,
+        levels: Arc::new(RequestLevels::new(args.log_level_for)),
+        #[cfg(unix)]
+        audit: args
+            .audit_socket
+            .map(|path| audit::AuditSocket::new(path, policy.hash()).map(Arc::new))
+            .transpose()?,
+    };
+
+    // One-shot signing goes through a single session: no proxy socket, no command
+    if let Some(Mode::Sign(sign)) = args.mode {
+        let (fatal_tx, _) = watch::channel(false);
+        let proxy = Proxy::new(
+            backends,
+            fatal_tx,
+            policy,
+            logging,
+            None,
+            KeyAges::new(None, None, usage_store),
+        );
+        let client = ClientInfo::default();
+        let backend = proxy.connect_backend(&client)?;
+        let mut session = proxy.session(backend, client);
+        let signed = signer::sign(&mut session, &sign).await;
+        state::flush_all(&stores);
+        return signed;
+    }
+
+    let bin = args.bin.ok_or("Missing command to run")?;
+
+    let socket = args.socket.unwrap_or_else(|| {
+        let mut path = std::env::temp_dir();
+        path.push(format!("ssh-agent-ac-{}.sock", std::process::id()));
+        path
+    });
+
+    // `main` reports errors with `Debug`, so pass on the readable message
+    sockpath::prepare(&socket).map_err(|e| e.to_string())?;
+    args.notify_ready.reset()?;
+
+    if args.in_process_backend {
+        info!("Backend: in-process agent");
+    }
+    for path in &backend_socket_paths {
+        info!("Backend ssh-agent socket: {}", path.display());
+    }
+    info!("Proxy listening on: {}", socket.display());
+
+    let mut cmd = Command::new(&bin);
+    cmd.args(&args.bin_args)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .env("SSH_AUTH_SOCK", &socket);
+
+    let (fatal_tx, mut fatal_rx) = watch::channel(false);
+
+    #[cfg(unix)]
+    let socket_group = match &args.socket_group {
+        Some(name) => Some(
+            nix::unistd::Group::from_name(name)?.ok_or_else(|| format!("No such group: {name}"))?,
+        ),
+        None => None,
+    };
+
+    // Before binding, so stopping the proxy at any point afterwards removes the socket
+    let shutdown = shutdown_signal()?;
+
+    let server_socket = socket.clone();
+    #[cfg(unix)]
+    let listener = bind_listener(&server_socket, args.listen_backlog, socket_group.as_ref());
+    #[cfg(windows)]
+    let listener = Listener::bind(&server_socket);
+    let listener = listener.map_err(|e| sockpath::filesystem_error(&socket, e).to_string())?;
+    #[cfg(unix)]
+    if let Some(group) = &socket_group {
+        info!("Proxy socket shared with group {}", group.name);
+    }
+
+    if args.print_env || args.export_env.is_some() {
+        let env = sockpath::export_env(&socket).inspect_err(|_| remove_socket(&socket))?;
+        if args.print_env {
+            print!("{env}");
+        }
+        if let Some(file) = &args.export_env {
+            sockpath::write_env_file(file, &env)
+                .inspect_err(|_| remove_socket(&socket))
+                .map_err(|e| format!("Failed to write {}: {e}", file.display()))?;
+            info!("Wrote SSH_AUTH_SOCK to {}", file.display());
+        }
+    }
+    let ready_backends = backend_socket_paths.clone();
+    let health = args
+        .health_interval
+        .map(|secs| health::spawn_monitor(backend_socket_paths.clone(), Duration::from_secs(secs)));
+    let proxy = Proxy::new(
+        backends,
+        fatal_tx,
+        policy,
+        logging,
+        health,
+        KeyAges::new(
+            args.warn_key_age.map(Duration::from_secs),
+            args.warn_unused_key.map(Duration::from_secs),
+            usage_store,
+        ),
+    );
+    let idle = args.session_idle.map(Duration::from_secs);
+    let connections = args.accept_concurrency.map(|n| Arc::new(Semaphore::new(n)));
+
+    let attestation = match &args.attest_policy {
+        Some(path) => {
+            let mut backend = proxy
+                .connect_backend(&ClientInfo::default())
+                .inspect_err(|_| remove_socket(&socket))?;
+            let key = args.attest_key.as_ref();
+            let attestation = attest::write(&proxy.policy, path, key, &mut *backend)
+                .await
+                .inspect_err(|_| remove_socket(&socket))?;
+            let hash = proxy.policy.hash();
+            let file = attestation.path.display();
+            info!("Enforcing policy {hash}; attestation written to {file}");
+            if let Some(signature) = &attestation.signature {
+                info!("Signed policy attestation: {}", signature.display());
+            }
+            Some(attestation)
+        }
+        None => None,
+    };
+    #[cfg(unix)]
+    if let Some(audit) = &proxy.logging.audit {
+        audit.send(audit::startup_event(attestation.as_ref()));
+    }
+    #[cfg(windows)]
+    let _ = attestation;
+
+    // Nothing on the filesystem to clean up: the listener closes with the process
+    #[cfg(all(feature = "vsock", target_os = "linux"))]
+    if let Some(target) = args.vsock {
+        let vsock_listener =
+            vsock::Listener::bind(target).inspect_err(|_| remove_socket(&socket))?;
+        info!("Proxy listening on: {target}");
+        let vsock_proxy = proxy.clone();
+        let vsock_connections = connections.clone();
+        tokio::spawn(async move {
+            if let Err(e) = listen(vsock_listener, vsock_proxy, idle, vsock_connections).await {
+                error!("Vsock listener failed: {e}");
+            }
+        });
+    }
+
+    let seed_proxy = proxy.clone();
+    #[cfg(unix)]
+    let server = if args.self_heal_socket {
+        let (backlog, group) = (args.listen_backlog, socket_group);
+        let bind = move |path: &std::path::Path| bind_listener(path, backlog, group.as_ref());
+        let listener = heal::HealingListener::new(listener, server_socket, bind);
+        tokio::spawn(async move { listen(listener, proxy, idle, connections).await })
+    } else {
+        tokio::spawn(async move { listen(listener, proxy, idle, connections).await })
+    };
+    #[cfg(windows)]
+    let server = tokio::spawn(async move { listen(listener, proxy, idle, connections).await });
+    tokio::pin!(server);
+
+    if !args.add_key.is_empty() {
+        let client = ClientInfo::default();
+        match seed_proxy.connect_backend(&client) {
+            Ok(backend) => {
+                let mut session = seed_proxy.session(backend, client);
+                seed::add_key_files(&mut session, &args.add_key, &socket).await;
+            }
+            Err(e) => error!("Failed to connect to ssh-agent backend to add keys: {e}"),
+        }
+    }
+
+    // Probe before the command runs, so its first extension requests already benefit
+    let extensions = &seed_proxy.policy.backend_extensions;
+    if extensions.probe_enabled() {
+        match seed_proxy.connect_backend(&ClientInfo::default()) {
+            Ok(mut backend) => match extensions.probe(&mut *backend).await {
+                Some(supported) => info!("Backend supports extensions: {supported:?}"),
+                None => info!("Backend did not list its extensions; forwarding all of them"),
+            },
+            Err(e) => error!("Failed to connect to ssh-agent backend to probe extensions: {e}"),
+        }
+    }
+
+    if !stores.is_empty() {
+        let stores = stores.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(state::FLUSH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                state::flush_all(&stores);
+            }
+        });
+    }
+
+    if args.notify_ready != NotifyReady::None {
+        let ready = args.notify_ready.clone();
+        tokio::spawn(async move {
+            health::wait_for_backend(&ready_backends).await;
+            match ready.notify() {
+                Ok(()) => debug!("Signalled readiness via {ready}"),
+                Err(e) => warn!("Failed to signal readiness via {ready}: {e}"),
+            }
+        });
+    }
+
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            server.abort();
+            remove_socket(&socket);
+            return Err(format!("Failed to spawn {}: {}", bin, e).into());
+        }
+    };
+
+    tokio::pin!(shutdown);
+
+    let mut fatal_rx_active = true;
+    let child_status = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            result = &mut server => {
+                let _ = child.kill().await;
+                let _ = child.wait().await;
+                state::flush_all(&stores);
+                remove_socket(&socket);
+                return Err(format!("Proxy exited unexpectedly: {:?}", result).into());
+            },
+            result = fatal_rx.changed(), if fatal_rx_active => {
+                if result.is_ok() && *fatal_rx.borrow() {
+                    let _ = child.kill().await;
+                    let _ = child.wait().await;
+                    state::flush_all(&stores);
+                    remove_socket(&socket);
+                    return Err("Proxy aborted due to backend connection failure.".into());
+                }
+                if result.is_err() {
+                    fatal_rx_active = false;
+                }
+                continue;
+            },
+            _ = &mut shutdown => {
+                let _ = child.kill().await;
+                break child.wait().await?;
+            }
+        }
+    };
+
+    server.abort();
+    state::flush_all(&stores);
+    remove_socket(&socket);
+
+    if !child_status.success() {
+        return Err(format!("Command exited with status: {}", child_status).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signature::Signer;
+    use ssh_agent_lib::proto::AddIdentity;
+    use ssh_agent_lib::ssh_encoding::Decode;
+    use ssh_agent_lib::ssh_key::Mpint;
+    use ssh_agent_lib::ssh_key::PrivateKey;
+    use ssh_agent_lib::ssh_key::private::{
+        DsaKeypair, DsaPrivateKey, KeypairData, RsaKeypair, RsaPrivateKey,
+    };
+    use ssh_agent_lib::ssh_key::public::{DsaPublicKey, RsaPublicKey};
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Backend answering through an in-process agent and recording every request that reaches it
+    #[derive(Clone, Default)]
+    struct Recorder {
+        agent: InProcessAgent,
+        requests: Arc<Mutex<Vec<Request>>>,
+        /// Requests left to fail before the agent answers again
+        failing: Arc<AtomicUsize>,
+        /// How long the agent takes to answer a sign request
+        sign_delay: Arc<Mutex<Duration>>,
+    }
+
+    impl Recorder {
+        fn requests(&self) -> Vec<Request> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// Fail the next `count` requests as a broken backend would
+        fn fail_next(&self, count: usize) {
+            self.failing.store(count, Ordering::SeqCst);
+        }
+
+        /// Answer sign requests only after `delay`, as a slow or prompting agent would
+        fn delay_signing(&self, delay: Duration) {
+            *self.sign_delay.lock().unwrap() = delay;
+        }
+    }
+
+    #[ssh_agent_lib::async_trait]
+    impl Session for Recorder {
+        async fn handle(&mut self, message: Request) -> Result<Response, AgentError> {
+            self.requests.lock().unwrap().push(message.clone());
+            let failing = self.failing.load(Ordering::SeqCst);
+            if failing > 0 {
+                self.failing.store(failing - 1, Ordering::SeqCst);
+                return Ok(Response::Failure);
+            }
+            if let Request::SignRequest(_) = message {
+                let delay = *self.sign_delay.lock().unwrap();
+                tokio::time::sleep(delay).await;
+            }
+            self.agent.clone().handle(message).await
+        }
+    }
+
+    /// A proxy enforcing `policy` in front of a recording backend
+    fn proxy(policy: Policy) -> (Proxy, Recorder) {
+        let backend = Recorder::default();
+        (Proxy::in_process(backend.agent.clone(), policy), backend)
+    }
+
+    /// A connection to `proxy` from a client running as `uid`
+    fn connect(proxy: &Proxy, backend: &Recorder, uid: u32) -> impl Session + use<> {
+        let client = ClientInfo {
+            pid: None,
+            uid: Some(uid),
+            cmdline: None,
+        };
+        proxy.session(Box::new(backend.clone()), client)
+    }
+
+    /// A connection from UID 1000 to a proxy enforcing `policy`
+    fn session(policy: Policy) -> (impl Session, Recorder) {
+        let (proxy, backend) = proxy(policy);
+        (connect(&proxy, &backend, 1000), backend)
+    }
+
+    fn extension(name: &str) -> Request {
+        Request::Extension(Extension {
+            name: name.to_string(),
+            details: Vec::new().into(),
+        })
+    }
+
+    fn key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    fn fingerprint(key: &PrivateKey) -> Fingerprint {
+        key.fingerprint(HashAlg::Sha256)
+    }
+
+    fn add(key: &PrivateKey) -> Request {
+        Request::AddIdentity(AddIdentity {
+            credential: Credential::Key {
+                privkey: key.key_data().clone(),
+                comment: key.comment().to_string(),
+            },
+        })
+    }
+
+    fn sign(key: &PrivateKey, data: &[u8]) -> Request {
+        Request::SignRequest(SignRequest {
+            pubkey: key.public_key().key_data().clone(),
+            data: data.to_vec(),
+            flags: 0,
+        })
+    }
+
+    /// Load `key` straight into the backend, bypassing the proxy's add-time policy
+    async fn load(backend: &Recorder, key: &PrivateKey) {
+        let response = backend.agent.clone().handle(add(key)).await.unwrap();
+        assert_eq!(response, Response::Success);
+    }
+
+    fn signed(response: &Response) -> bool {
+        matches!(response, Response::SignResponse(_))
+    }
+
+    /// Backend answering every sign request with a well-formed but wrong Ed25519 signature
+    struct Forger;
+
+    #[ssh_agent_lib::async_trait]
+    impl Session for Forger {
+        async fn handle(&mut self, _: Request) -> Result<Response, AgentError> {
+            let signature = Signature::new(Algorithm::Ed25519, vec![7; 64]).unwrap();
+            Ok(Response::SignResponse(signature))
+        }
+    }
+
+    fn add_constrained(key: &PrivateKey, constraints: Vec<KeyConstraint>) -> Request {
+        let Request::AddIdentity(identity) = add(key) else {
+            unreachable!("add builds an AddIdentity");
+        };
+        Request::AddIdConstrained(AddIdentityConstrained {
+            identity,
+            constraints,
+        })
+    }
+
+    /// Constraints of the keys the backend was asked to add
+    fn forwarded_constraints(backend: &Recorder) -> Vec<Vec<KeyConstraint>> {
+        backend
+            .requests()
+            .into_iter()
+            .filter_map(|request| match request {
+                Request::AddIdentity(_) => Some(Vec::new()),
+                Request::AddIdConstrained(add) => Some(add.constraints),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A session-bind to the host holding `host_key`, as ssh sends before authenticating
+    fn bind(host_key: &PrivateKey, is_forwarding: bool) -> Request {
+        let session_id = b"session id".to_vec();
+        let bind = SessionBind {
+            host_key: host_key.public_key().key_data().clone(),
+            signature: host_key.try_sign(&session_id).unwrap(),
+            session_id,
+            is_forwarding,
+        };
+        Request::Extension(Extension::new_message(bind).unwrap())
+    }
+
+    fn key_with_comment(comment: &str) -> PrivateKey {
+        let mut key = key();
+        key.set_comment(comment);
+        key
+    }
+
+    /// An add of a well-formed but unusable key; only its public half matters to the add-time checks
+    fn add_keypair(privkey: KeypairData) -> Request {
+        Request::AddIdentity(AddIdentity {
+            credential: Credential::Key {
+                privkey,
+                comment: String::new(),
+            },
+        })
+    }
+
+    fn mpint(bytes: &[u8]) -> Mpint {
+        Mpint::from_positive_bytes(bytes).unwrap()
+    }
+
+    /// An RSA keypair whose modulus is `bits` long
+    fn rsa_keypair(bits: usize) -> KeypairData {
+        let mut n = vec![0; bits / 8];
+        n[0] = 0x80;
+        KeypairData::Rsa(RsaKeypair {
+            public: RsaPublicKey {
+                e: mpint(&[1, 0, 1]),
+                n: mpint(&n),
+            },
+            private: RsaPrivateKey {
+                d: mpint(&[1]),
+                iqmp: mpint(&[1]),
+                p: mpint(&[1]),
+                q: mpint(&[1]),
+            },
+        })
+    }
+
+    fn dsa_keypair() -> KeypairData {
+        KeypairData::Dsa(DsaKeypair {
+            public: DsaPublicKey {
+                p: mpint(&[7]),
+                q: mpint(&[3]),
+                g: mpint(&[2]),
+                y: mpint(&[4]),
+            },
+            private: DsaPrivateKey::decode(&mut &[0, 0, 0, 1, 1][..]).unwrap(),
+        })
+    }
+
+    /// Sign requests that reached the backend
+    fn forwarded_signs(backend: &Recorder) -> usize {
+        let requests = backend.requests().into_iter();
+        requests
+            .filter(|request| matches!(request, Request::SignRequest(_)))
+            .count()
+    }
+
+    /// A policy allowing `key` one signature a minute
+    fn rate_limited(key: &PrivateKey) -> Policy {
+        let limit = format!("{}=1/60", fingerprint(key)).parse().unwrap();
+        Policy {
+            key_rate_limits: RateLimiter::new(vec![limit]),
+            ..Policy::default()
+        }
+    }
+
+    /// Fingerprints of the keys listed in `response`
+    fn listed(response: Response) -> Vec<Fingerprint> {
+        let Response::IdentitiesAnswer(identities) = response else {
+            panic!("expected identities, got {response:?}");
+        };
+        let identities = identities.into_iter();
+        identities
+            .map(|id| id.pubkey.fingerprint(HashAlg::Sha256))
+            .collect()
+    }
+
+    /// A proxy enforcing `outer` in front of one enforcing `inner`, as when two instances are chained
+    fn chained(outer: Policy, inner: Policy) -> (impl Session, Recorder) {
+        let (inner, backend) = session(inner);
+        let (outer, _) = proxy(outer);
+        (
+            outer.session(Box::new(inner), ClientInfo::default()),
+            backend,
+        )
+    }
+
+    /// Comments of the keys the backend was asked to add
+    #[cfg(unix)]
+    fn forwarded_comments(backend: &Recorder) -> Vec<String> {
+        let requests = backend.requests().into_iter();
+        requests
+            .filter_map(|request| match request {
+                Request::AddIdentity(add) => Some(keys::credential_comment(&add.credential).into()),
+                Request::AddIdConstrained(add) => {
+                    Some(keys::credential_comment(&add.identity.credential).into())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// An executable shell script running `script`
+    #[cfg(unix)]
+    fn program(name: &str, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}", std::process::id()));
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// The proxy's answer to the info extension
+    async fn queried_info(session: &mut impl Session) -> serde_json::Value {
+        let response = session.handle(extension(INFO_EXTENSION)).await.unwrap();
+        let Response::ExtensionResponse(ext) = response else {
+            panic!("expected an extension response, got {response:?}");
+        };
+        serde_json::from_str(&ext.details.parse::<String>().unwrap()).unwrap()
+    }
+
+    /// A FIDO security key, as far as the agent protocol shows it
+    fn sk_keypair() -> KeypairData {
+        use ssh_agent_lib::ssh_key::{private, public};
+
+        let public = public::SkEd25519::new(public::Ed25519PublicKey([9; 32]), "ssh:");
+        KeypairData::SkEd25519(private::SkEd25519::new(public, 0x01, vec![1; 16]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_the_info_extension_with_json() {
+        let policy = Policy {
+            info_extension: true,
+            ..Policy::default()
+        };
+        let hash = policy.hash();
+        let (mut session, backend) = session(policy);
+        session.handle(Request::RequestIdentities).await.unwrap();
+
+        let response = session.handle(extension(INFO_EXTENSION)).await.unwrap();
+        let Response::ExtensionResponse(ext) = response else {
+            panic!("expected an extension response, got {response:?}");
+        };
+        let info: serde_json::Value =
+            serde_json::from_str(&ext.details.parse::<String>().unwrap()).unwrap();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["policy_hash"], hash);
+        assert_eq!(info["requests"], 2);
+        // Answered by the proxy, so only the listing reached the backend
+        assert_eq!(backend.requests(), [Request::RequestIdentities]);
+    }
+
+    #[tokio::test]
+    async fn forwards_the_info_extension_when_disabled() {
+        let (mut session, backend) = session(Policy::default());
+        session.handle(extension(INFO_EXTENSION)).await.unwrap();
+        assert_eq!(backend.requests(), [extension(INFO_EXTENSION)]);
+    }
+
+    #[tokio::test]
+    async fn retries_a_failed_listing() {
+        let policy = Policy {
+            list_retries: 2,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        backend.fail_next(1);
+        let response = session.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(response, Response::IdentitiesAnswer(Vec::new()));
+        assert_eq!(backend.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_listing_after_the_retries() {
+        let policy = Policy {
+            list_retries: 1,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        backend.fail_next(2);
+        let response = session.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert_eq!(backend.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn never_retries_a_failed_sign() {
+        let policy = Policy {
+            list_retries: 2,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        load(&backend, &key).await;
+        backend.fail_next(1);
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert!(!signed(&response));
+        assert_eq!(backend.requests(), [sign(&key, b"data")]);
+    }
+
+    #[tokio::test]
+    async fn kill_switch_file_blocks_signing_until_removed() {
+        let path = std::env::temp_dir().join(format!("ssh-agent-ac-{}-kill", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let policy = Policy {
+            info_extension: true,
+            kill_switch: Some(KillSwitch::new(path.clone(), KillSwitchScope::All)),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        load(&backend, &key).await;
+
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        let info = session.handle(extension(INFO_EXTENSION)).await.unwrap();
+        assert!(matches!(info, Response::ExtensionResponse(_)));
+        assert!(backend.requests().is_empty());
+
+        fs::remove_file(&path).unwrap();
+        // Past the cached existence check
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert!(signed(&response));
+    }
+
+    #[tokio::test]
+    async fn refuses_a_garbage_signature_from_the_backend() {
+        let policy = Policy {
+            verify_signatures: true,
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        let key = key();
+        load(&backend, &key).await;
+
+        let mut session = connect(&proxy, &backend, 1000);
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert!(signed(&response));
+
+        let client = ClientInfo::default();
+        let mut session = proxy.session(Box::new(Forger), client);
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+    }
+
+    #[tokio::test]
+    async fn passes_backend_signatures_through_without_verification() {
+        let (proxy, _) = proxy(Policy::default());
+        let mut session = proxy.session(Box::new(Forger), ClientInfo::default());
+        let response = session.handle(sign(&key(), b"data")).await.unwrap();
+        assert!(signed(&response));
+    }
+
+    /// Lifetimes of keys added with each of `lifetimes`, under a default and a maximum
+    async fn lifetimes_after(
+        default: u32,
+        max: Option<u32>,
+        lifetimes: &[Option<u32>],
+    ) -> Vec<Vec<KeyConstraint>> {
+        let policy = Policy {
+            default_lifetime: Some(default),
+            enforced_constraint: max.map_or(EnforcedConstraint::None, EnforcedConstraint::Lifetime),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        for lifetime in lifetimes {
+            let constraints = lifetime.map(KeyConstraint::Lifetime).into_iter().collect();
+            let response = session.handle(add_constrained(&key(), constraints)).await;
+            assert_eq!(response.unwrap(), Response::Success);
+        }
+        forwarded_constraints(&backend)
+    }
+
+    #[tokio::test]
+    async fn default_lifetime_applies_only_without_a_lifetime() {
+        let forwarded = lifetimes_after(600, None, &[None, Some(60), Some(7200)]).await;
+        assert_eq!(
+            forwarded,
+            [
+                vec![KeyConstraint::Lifetime(600)],
+                vec![KeyConstraint::Lifetime(60)],
+                vec![KeyConstraint::Lifetime(7200)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lifetimes_are_clamped_to_the_maximum() {
+        let forwarded = lifetimes_after(600, Some(3600), &[None, Some(60), Some(7200)]).await;
+        assert_eq!(
+            forwarded,
+            [
+                vec![KeyConstraint::Lifetime(600)],
+                vec![KeyConstraint::Lifetime(60)],
+                vec![KeyConstraint::Lifetime(3600)],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn default_lifetime_never_exceeds_the_maximum() {
+        let forwarded = lifetimes_after(7200, Some(3600), &[None]).await;
+        assert_eq!(forwarded, [vec![KeyConstraint::Lifetime(3600)]]);
+    }
+
+    #[tokio::test]
+    async fn signs_only_for_allowed_hosts() {
+        let (allowed, other) = (key(), key());
+        let policy = Policy {
+            allowed_hosts: vec![fingerprint(&allowed)],
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        let key = key();
+        load(&backend, &key).await;
+
+        let mut unbound = connect(&proxy, &backend, 1000);
+        let response = unbound.handle(sign(&key, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+
+        let mut session = connect(&proxy, &backend, 1000);
+        let response = session.handle(bind(&other, false)).await.unwrap();
+        assert_eq!(response, Response::Success);
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+
+        let mut session = connect(&proxy, &backend, 1000);
+        session.handle(bind(&allowed, false)).await.unwrap();
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert!(signed(&response));
+    }
+
+    #[cfg(windows)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connects_to_a_backend_pipe_created_after_a_delay() {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let path = format!(r"\\.\pipe\ssh-agent-ac-{}-late", std::process::id());
+        let pipe = path.clone();
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            let server = ServerOptions::new().create(&pipe).unwrap();
+            server.connect().await.unwrap();
+            server
+        });
+
+        let path = PathBuf::from(path);
+        let connected = tokio::task::spawn_blocking(move || connect_named_pipe(&path).is_ok());
+        assert!(connected.await.unwrap());
+        drop(server.await.unwrap());
+    }
+
+    #[cfg(windows)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn gives_up_on_a_backend_pipe_that_never_appears() {
+        let path = PathBuf::from(format!(
+            r"\\.\pipe\ssh-agent-ac-{}-missing",
+            std::process::id()
+        ));
+        let connected = tokio::task::spawn_blocking(move || connect_named_pipe(&path).is_ok());
+        assert!(!connected.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn fails_requests_fast_while_the_backend_is_unhealthy() {
+        let policy = Policy {
+            info_extension: true,
+            ..Policy::default()
+        };
+        let (mut proxy, backend) = proxy(policy);
+        let missing = std::env::temp_dir().join(format!(
+            "ssh-agent-ac-{}-unhealthy.sock",
+            std::process::id()
+        ));
+        let health = health::spawn_monitor(vec![missing], Duration::from_millis(10));
+        while health.is_healthy() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        proxy.health = Some(health);
+
+        let mut session = connect(&proxy, &backend, 1000);
+        let response = session.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        let info = session.handle(extension(INFO_EXTENSION)).await.unwrap();
+        assert!(matches!(info, Response::ExtensionResponse(_)));
+        assert!(backend.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn signs_only_with_keys_carrying_the_required_comment() {
+        let policy = Policy {
+            required_comment: Some("env=prod".into()),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let (tagged, untagged) = (
+            key_with_comment("me@host env=prod"),
+            key_with_comment("me@host"),
+        );
+        load(&backend, &tagged).await;
+        load(&backend, &untagged).await;
+
+        let response = session.handle(sign(&tagged, b"data")).await.unwrap();
+        assert!(signed(&response));
+        let response = session.handle(sign(&untagged, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        // A key the backend does not hold has no comment to match either
+        let response = session.handle(sign(&key(), b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+    }
+
+    #[test]
+    fn verbosity_flags_select_the_log_level() {
+        assert_eq!(log_level(0, false), LevelFilter::Info);
+        assert_eq!(log_level(1, false), LevelFilter::Debug);
+        assert_eq!(log_level(2, false), LevelFilter::Trace);
+        assert_eq!(log_level(3, false), LevelFilter::Trace);
+        assert_eq!(log_level(0, true), LevelFilter::Warn);
+        assert_eq!(log_level(2, true), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn parses_repeated_verbosity_flags() {
+        let args = Args::try_parse_from(["ssh-agent-ac", "-vv", "ssh", "host"]).unwrap();
+        assert_eq!(log_level(args.verbose, args.quiet), LevelFilter::Trace);
+        let args = Args::try_parse_from(["ssh-agent-ac", "--quiet", "ssh"]).unwrap();
+        assert_eq!(log_level(args.verbose, args.quiet), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn verbose_and_quiet_conflict() {
+        assert!(Args::try_parse_from(["ssh-agent-ac", "-v", "-q", "ssh"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_weak_rsa_and_dsa_keys() {
+        let policy = Policy {
+            min_rsa_bits: Some(2048),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let response = session.handle(add_keypair(rsa_keypair(1024))).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        let response = session.handle(add_keypair(dsa_keypair())).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert!(backend.requests().is_empty());
+
+        let response = session.handle(add_keypair(rsa_keypair(3072))).await;
+        assert_eq!(response.unwrap(), Response::Success);
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn each_enforced_constraint_reaches_the_backend() {
+        let modes = [
+            (EnforcedConstraint::Confirm, vec![KeyConstraint::Confirm]),
+            (
+                EnforcedConstraint::Lifetime(300),
+                vec![KeyConstraint::Lifetime(300)],
+            ),
+            (EnforcedConstraint::None, vec![]),
+        ];
+        for (enforced_constraint, expected) in modes {
+            let policy = Policy {
+                enforced_constraint,
+                ..Policy::default()
+            };
+            let (mut session, backend) = session(policy);
+            let response = session.handle(add(&key())).await;
+            assert_eq!(response.unwrap(), Response::Success);
+            let response = session.handle(add_constrained(&key(), Vec::new())).await;
+            assert_eq!(response.unwrap(), Response::Success);
+            assert_eq!(
+                forwarded_constraints(&backend),
+                [expected.clone(), expected],
+                "{enforced_constraint}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shares_the_socket_with_the_group() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-group.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let gid = nix::unistd::getgid();
+        let group = nix::unistd::Group::from_gid(gid).unwrap().unwrap();
+        let listener = bind_listener(&path, Some(8), Some(&group)).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        remove_socket(&path);
+        drop(listener);
+        assert_eq!(metadata.uid(), nix::unistd::getuid().as_raw());
+        assert_eq!(metadata.gid(), gid.as_raw());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+    }
+
+    #[tokio::test]
+    async fn why_denied_reports_the_last_denial_of_the_session() {
+        async fn why_denied(session: &mut impl Session) -> String {
+            let response = session.handle(extension(WHY_DENIED_EXTENSION)).await;
+            let Response::ExtensionResponse(ext) = response.unwrap() else {
+                panic!("why-denied is answered with an extension response");
+            };
+            String::decode(&mut ext.details.as_ref()).unwrap()
+        }
+
+        let policy = Policy {
+            why_denied_extension: true,
+            min_rsa_bits: Some(2048),
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        let mut session = connect(&proxy, &backend, 1000);
+        assert_eq!(why_denied(&mut session).await, "");
+        let response = session.handle(add_keypair(rsa_keypair(1024))).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        let reason = why_denied(&mut session).await;
+        assert!(
+            reason.ends_with("ssh-rsa key is below the minimum strength"),
+            "{reason}"
+        );
+
+        let mut other = connect(&proxy, &backend, 1000);
+        assert_eq!(why_denied(&mut other).await, "");
+        assert!(backend.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_signs_cannot_share_the_last_token() {
+        let key = key();
+        let (proxy, backend) = proxy(rate_limited(&key));
+        load(&backend, &key).await;
+        backend.delay_signing(Duration::from_millis(100));
+
+        let mut first = connect(&proxy, &backend, 1000);
+        let mut second = connect(&proxy, &backend, 1000);
+        let (first, second) = tokio::join!(
+            first.handle(sign(&key, b"first")),
+            second.handle(sign(&key, b"second"))
+        );
+        let signatures = [first.unwrap(), second.unwrap()];
+        assert_eq!(signatures.iter().filter(|r| signed(r)).count(), 1);
+        assert_eq!(forwarded_signs(&backend), 1);
+    }
+
+    #[tokio::test]
+    async fn unsigned_requests_give_their_token_back() {
+        let key = key();
+        let (mut session, backend) = session(rate_limited(&key));
+        load(&backend, &key).await;
+
+        backend.fail_next(1);
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert!(signed(&session.handle(sign(&key, b"data")).await.unwrap()));
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert_eq!(forwarded_signs(&backend), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_signs_cannot_share_the_last_quota() {
+        let key = key();
+        let quota = format!("{}=1/day", fingerprint(&key)).parse().unwrap();
+        let policy = Policy {
+            key_quotas: Quotas::new(vec![quota], None),
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        load(&backend, &key).await;
+        backend.delay_signing(Duration::from_millis(100));
+
+        let mut first = connect(&proxy, &backend, 1000);
+        let mut second = connect(&proxy, &backend, 1000);
+        backend.fail_next(1);
+        let response = first.handle(sign(&key, b"failed")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        let (first, second) = tokio::join!(
+            first.handle(sign(&key, b"first")),
+            second.handle(sign(&key, b"second"))
+        );
+        let signatures = [first.unwrap(), second.unwrap()];
+        assert_eq!(signatures.iter().filter(|r| signed(r)).count(), 1);
+        assert_eq!(forwarded_signs(&backend), 2);
+    }
+
+    #[cfg(feature = "policy-script")]
+    #[tokio::test]
+    async fn checks_needing_the_comment_list_keys_once() {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-comment.rhai", std::process::id()));
+        fs::write(
+            &path,
+            r#"if request.comment == "work" { "allow" } else { "deny" }"#,
+        )
+        .unwrap();
+        let script = script::PolicyScript::load(path.clone()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let policy = Policy {
+            required_comment: Some("work".into()),
+            script: Some(script),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key_with_comment("work");
+        load(&backend, &key).await;
+
+        assert!(signed(&session.handle(sign(&key, b"data")).await.unwrap()));
+        let listings = backend.requests().into_iter();
+        let listings = listings.filter(|request| *request == Request::RequestIdentities);
+        assert_eq!(listings.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn unlocking_is_blocked_or_confirmed() {
+        let unlock = || Request::Unlock("passphrase".into());
+        let policy = Policy {
+            block_unlock: true,
+            confirm_lock_unlock: true,
+            ..Policy::default()
+        };
+        let (mut blocked, backend) = session(policy);
+        let (response, prompts) = askpass::answering(Some(true), blocked.handle(unlock())).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert!(prompts.is_empty());
+        assert!(backend.requests().is_empty());
+
+        let policy = Policy {
+            confirm_lock_unlock: true,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let (response, prompts) = askpass::answering(Some(false), session.handle(unlock())).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0].starts_with("Allow unlocking the agent for "));
+        assert!(backend.requests().is_empty());
+        let (_, prompts) = askpass::answering(Some(true), session.handle(unlock())).await;
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(backend.requests(), [unlock()]);
+    }
+
+    #[tokio::test]
+    async fn refuses_signatures_in_an_algorithm_the_key_may_not_produce() {
+        let pubkey = KeyData::try_from(&rsa_keypair(2048)).unwrap();
+        let fingerprint = pubkey.fingerprint(HashAlg::Sha256);
+        let limit = format!("{fingerprint}=rsa-sha2-512").parse().unwrap();
+        let policy = Policy {
+            key_signature_algorithms: vec![limit],
+            ..Policy::default()
+        };
+        let (proxy, _) = proxy(policy);
+        let mut session = proxy.session(Box::new(Forger), ClientInfo::default());
+        let request = Request::SignRequest(SignRequest {
+            pubkey,
+            data: b"data".to_vec(),
+            flags: sigalg::RSA_SHA2_512,
+        });
+        assert_eq!(session.handle(request).await.unwrap(), Response::Failure);
+    }
+
+    #[tokio::test]
+    async fn info_reports_the_enforced_policy() {
+        let limit = format!("{}=5/60", fingerprint(&key())).parse().unwrap();
+        let policy = Policy {
+            info_extension: true,
+            enforced_constraint: EnforcedConstraint::Lifetime(300),
+            min_rsa_bits: Some(3072),
+            key_rate_limits: RateLimiter::new(vec![limit]),
+            ..Policy::default()
+        };
+        let (expected, hash) = (serde_json::to_value(&policy).unwrap(), policy.hash());
+        let (mut session, backend) = session(policy);
+
+        let response = session.handle(extension(INFO_EXTENSION)).await.unwrap();
+        let Response::ExtensionResponse(ext) = response else {
+            panic!("expected an extension response, got {response:?}");
+        };
+        let info: serde_json::Value =
+            serde_json::from_str(&ext.details.parse::<String>().unwrap()).unwrap();
+        assert_eq!(info["policy"], expected);
+        assert_eq!(info["policy_hash"], hash);
+        assert_eq!(info["policy"]["enforced_constraint"], "lifetime=300");
+        assert_eq!(info["policy"]["min_rsa_bits"], 3072);
+        assert!(backend.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn trusted_uids_bypass_the_proxys_confirmation() {
+        let key = key();
+        let (confirm, bypass) = (
+            format!("{}=deny", fingerprint(&key)),
+            format!("{}=1000", fingerprint(&key)),
+        );
+        let policy = Policy {
+            key_confirms: vec![confirm.parse().unwrap()],
+            confirm_bypass: vec![bypass.parse().unwrap()],
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        load(&backend, &key).await;
+
+        let mut trusted = connect(&proxy, &backend, 1000);
+        let (response, prompts) =
+            askpass::answering(Some(false), trusted.handle(sign(&key, b"data"))).await;
+        assert!(signed(&response.unwrap()));
+        assert!(prompts.is_empty());
+
+        let mut other = connect(&proxy, &backend, 1001);
+        let (response, prompts) =
+            askpass::answering(Some(false), other.handle(sign(&key, b"data"))).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert_eq!(prompts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn trusted_uids_bypass_the_in_process_agents_confirmation() {
+        let key = key();
+        let policy = Policy {
+            confirm_bypass: vec![format!("{}=1000", fingerprint(&key)).parse().unwrap()],
+            ..Policy::default()
+        };
+        let (proxy, _) = proxy(policy);
+        let client = |uid| ClientInfo {
+            pid: None,
+            uid: Some(uid),
+            cmdline: None,
+        };
+        let connect =
+            |uid| proxy.session(proxy.connect_backend(&client(uid)).unwrap(), client(uid));
+
+        let mut trusted = connect(1000);
+        assert_eq!(trusted.handle(add(&key)).await.unwrap(), Response::Success);
+        let (response, prompts) =
+            askpass::answering(Some(false), trusted.handle(sign(&key, b"data"))).await;
+        assert!(signed(&response.unwrap()));
+        assert!(prompts.is_empty());
+
+        let mut other = connect(1001);
+        let (response, prompts) =
+            askpass::answering(Some(false), other.handle(sign(&key, b"data"))).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert_eq!(prompts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn lists_only_the_keys_a_client_may_use() {
+        let (restricted, open) = (key(), key());
+        let policy = Policy {
+            key_users: vec![
+                format!("{}=1001", fingerprint(&restricted))
+                    .parse()
+                    .unwrap(),
+            ],
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        load(&backend, &restricted).await;
+        load(&backend, &open).await;
+
+        let mut other = connect(&proxy, &backend, 1000);
+        let response = other.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(listed(response), [fingerprint(&open)]);
+        let mut allowed = connect(&proxy, &backend, 1001);
+        let response = allowed.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(
+            listed(response),
+            [fingerprint(&restricted), fingerprint(&open)]
+        );
+    }
+
+    /// Whether a sign on a connection bound with `is_forwarding` went through, and the prompts shown
+    async fn forwarded_sign(
+        on_forwarded: OnForwarded,
+        is_forwarding: bool,
+        answer: bool,
+    ) -> (bool, usize) {
+        let policy = Policy {
+            on_forwarded,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        load(&backend, &key).await;
+        let response = session
+            .handle(bind(&self::key(), is_forwarding))
+            .await
+            .unwrap();
+        assert_eq!(response, Response::Success);
+        let (response, prompts) =
+            askpass::answering(Some(answer), session.handle(sign(&key, b"data"))).await;
+        (signed(&response.unwrap()), prompts.len())
+    }
+
+    #[tokio::test]
+    async fn forwarded_connections_get_the_configured_treatment() {
+        assert_eq!(
+            forwarded_sign(OnForwarded::Warn, true, false).await,
+            (true, 0)
+        );
+        assert_eq!(
+            forwarded_sign(OnForwarded::Confirm, true, false).await,
+            (false, 1)
+        );
+        assert_eq!(
+            forwarded_sign(OnForwarded::Confirm, true, true).await,
+            (true, 1)
+        );
+        assert_eq!(
+            forwarded_sign(OnForwarded::Deny, true, true).await,
+            (false, 0)
+        );
+        assert_eq!(
+            forwarded_sign(OnForwarded::Deny, false, true).await,
+            (true, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn removes_the_key_a_rotation_replaces() {
+        let policy = Policy {
+            key_rotations: Rotations::new(true),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let (old, new) = (key_with_comment("work"), key_with_comment("work"));
+        assert_eq!(session.handle(add(&old)).await.unwrap(), Response::Success);
+        assert_eq!(session.handle(add(&new)).await.unwrap(), Response::Success);
+
+        let removal = Request::RemoveIdentity(RemoveIdentity {
+            pubkey: old.public_key().key_data().clone(),
+        });
+        assert_eq!(backend.requests().last(), Some(&removal));
+        let response = session.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(listed(response), [fingerprint(&new)]);
+    }
+
+    #[tokio::test]
+    async fn chained_proxies_apply_their_constraints_once() {
+        let lifetime = || Policy {
+            enforced_constraint: EnforcedConstraint::Lifetime(300),
+            ..Policy::default()
+        };
+        let (mut session, backend) = chained(lifetime(), lifetime());
+        let clamped = add_constrained(&key(), vec![KeyConstraint::Lifetime(7200)]);
+        assert_eq!(session.handle(clamped).await.unwrap(), Response::Success);
+        assert_eq!(
+            forwarded_constraints(&backend),
+            [vec![KeyConstraint::Lifetime(300)]]
+        );
+
+        let (mut session, backend) = chained(Policy::default(), Policy::default());
+        assert_eq!(
+            session.handle(add(&key())).await.unwrap(),
+            Response::Success
+        );
+        assert_eq!(
+            forwarded_constraints(&backend),
+            [vec![KeyConstraint::Confirm]]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn chained_proxies_prefix_comments_once_with_an_idempotent_filter() {
+        let filter = program(
+            "prefix-filter",
+            r#"read -r comment; case "$comment" in work:*) echo "$comment" ;; *) echo "work:$comment" ;; esac"#,
+        );
+        let policy = || Policy {
+            comment_filter: Some(CommentFilter::new(filter.clone())),
+            ..Policy::default()
+        };
+        let (mut session, backend) = chained(policy(), policy());
+        let response = session.handle(add(&key_with_comment("laptop"))).await;
+        fs::remove_file(&filter).unwrap();
+        assert_eq!(response.unwrap(), Response::Success);
+        assert_eq!(forwarded_comments(&backend), ["work:laptop"]);
+    }
+
+    #[tokio::test]
+    async fn only_keys_with_a_public_key_file_may_be_added_and_used() {
+        let (listed, unlisted) = (key(), key());
+        let dir =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-allowed-dir", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("listed.pub");
+        listed.public_key().write_openssh_file(&path).unwrap();
+        let allowed = allowlist::AllowedKeys::load(dir.clone()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let policy = Policy {
+            allowed_keys: Some(allowed),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+
+        assert_eq!(
+            session.handle(add(&listed)).await.unwrap(),
+            Response::Success
+        );
+        assert_eq!(
+            session.handle(add(&unlisted)).await.unwrap(),
+            Response::Failure
+        );
+        load(&backend, &unlisted).await;
+        let response = session.handle(sign(&unlisted, b"data")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert_eq!(forwarded_signs(&backend), 0);
+    }
+
+    #[tokio::test]
+    async fn denials_take_at_least_the_minimum_time() {
+        let policy = Policy {
+            constant_time_denies_ms: Some(200),
+            min_rsa_bits: Some(2048),
+            ..Policy::default()
+        };
+        let (mut session, _) = session(policy);
+
+        let started = Instant::now();
+        let response = session.handle(add_keypair(rsa_keypair(1024))).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // Only denials are padded
+        let started = Instant::now();
+        let response = session.handle(add(&key())).await;
+        assert_eq!(response.unwrap(), Response::Success);
+        assert!(started.elapsed() < Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn session_binds_only_to_allowed_hosts() {
+        let (known, unknown) = (key(), key());
+        let policy = Policy {
+            allowed_bind_hosts: vec![fingerprint(&known)],
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+
+        let response = session.handle(bind(&unknown, false)).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert!(backend.requests().is_empty());
+
+        let response = session.handle(bind(&known, false)).await.unwrap();
+        assert_eq!(response, Response::Success);
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn one_shot_signatures_verify() {
+        let (mut session, backend) = session(Policy::default());
+        let key = key();
+        load(&backend, &key).await;
+
+        let data = b"release tarball";
+        let sshsig = signer::sshsig(&mut session, &fingerprint(&key), "file", data)
+            .await
+            .unwrap();
+        key.public_key().verify("file", data, &sshsig).unwrap();
+        assert!(
+            key.public_key()
+                .verify("file", b"tampered", &sshsig)
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn one_shot_signing_fails_when_the_policy_refuses() {
+        let policy = Policy {
+            allowed_hosts: vec![fingerprint(&key())],
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        load(&backend, &key).await;
+
+        let signed = signer::sshsig(&mut session, &fingerprint(&key), "file", b"data").await;
+        assert!(signed.unwrap_err().to_string().ends_with("was refused"));
+    }
+
+    #[tokio::test]
+    async fn info_flags_keys_the_proxy_made_confirm() {
+        let (confirmed, lifetime) = (key(), key());
+        for (key, enforced_constraint) in [
+            (&confirmed, EnforcedConstraint::Confirm),
+            (&lifetime, EnforcedConstraint::Lifetime(300)),
+        ] {
+            let policy = Policy {
+                info_extension: true,
+                enforced_constraint,
+                ..Policy::default()
+            };
+            let (mut session, _) = session(policy);
+            let response = session.handle(add(key)).await.unwrap();
+            assert_eq!(response, Response::Success);
+
+            let info = queried_info(&mut session).await;
+            let keys = info["keys"].as_array().unwrap();
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys[0]["fingerprint"], fingerprint(key).to_string());
+            let gated = enforced_constraint == EnforcedConstraint::Confirm;
+            assert_eq!(keys[0]["confirm_required"], gated);
+        }
+    }
+
+    #[tokio::test]
+    async fn signing_updates_the_last_used_time() {
+        let policy = Policy {
+            info_extension: true,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        load(&backend, &key).await;
+        let used = fingerprint(&key).to_string();
+
+        let info = queried_info(&mut session).await;
+        assert!(info["last_used"].get(&used).is_none());
+
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let response = session.handle(sign(&key, b"data")).await.unwrap();
+        assert!(signed(&response));
+        let info = queried_info(&mut session).await;
+        assert!(info["last_used"][&used].as_u64().unwrap() >= before);
+    }
+
+    #[tokio::test]
+    async fn refuses_adds_with_an_oversized_constraint_extension() {
+        let policy = Policy {
+            max_constraint_extension_bytes: Some(64),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let constraint = |len: usize| {
+            KeyConstraint::Extension(Extension {
+                name: "restrict-destination-v00@openssh.com".into(),
+                details: vec![0; len].into(),
+            })
+        };
+
+        let oversized = add_constrained(&key(), vec![constraint(65)]);
+        assert_eq!(session.handle(oversized).await.unwrap(), Response::Failure);
+        assert!(backend.requests().is_empty());
+
+        let fitting = add_constrained(&key(), vec![constraint(64)]);
+        session.handle(fitting).await.unwrap();
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn listens_with_the_configured_backlog() {
+        // Connections past the backlog of a socket nobody accepts on are refused at once
+        async fn queued(backlog: u32) -> usize {
+            let path = std::env::temp_dir().join(format!(
+                "ssh-agent-ac-{}-backlog-{backlog}.sock",
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            let _listener = bind_listener(&path, Some(backlog), None).unwrap();
+            let mut clients = Vec::new();
+            while let Ok(client) = tokio::net::UnixStream::connect(&path).await {
+                clients.push(client);
+                assert!(clients.len() < 100, "connections never queued up");
+            }
+            fs::remove_file(&path).unwrap();
+            clients.len()
+        }
+
+        let (short, long) = (queued(2).await, queued(16).await);
+        assert!(short <= 3, "{short} connections queued");
+        assert!(long >= 16, "{long} connections queued");
+    }
+
+    #[tokio::test]
+    async fn protection_confirms_only_keys_without_a_lifetime() {
+        let policy = Policy {
+            enforced_constraint: EnforcedConstraint::ConfirmOrLifetime,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+
+        session.handle(add(&key())).await.unwrap();
+        let lifetime = vec![KeyConstraint::Lifetime(60)];
+        session
+            .handle(add_constrained(&key(), lifetime.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            forwarded_constraints(&backend),
+            [vec![KeyConstraint::Confirm], lifetime]
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_extensions_the_backend_does_not_support_locally() {
+        let backend_extensions = BackendExtensions::new(true);
+        let supported = backend_extensions
+            .probe(&mut InProcessAgent::default())
+            .await
+            .unwrap();
+        assert!(supported.contains(SessionBind::NAME));
+        let policy = Policy {
+            backend_extensions,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+
+        let response = session.handle(extension("unknown@example.com")).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert!(backend.requests().is_empty());
+
+        let response = session.handle(bind(&key(), false)).await.unwrap();
+        assert_eq!(response, Response::Success);
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn adds_are_refused_once_the_window_after_startup_closes() {
+        let policy = Policy {
+            add_window: Some(1),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+
+        let response = session.handle(add(&key())).await.unwrap();
+        assert_eq!(response, Response::Success);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let response = session.handle(add(&key())).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert_eq!(forwarded_constraints(&backend).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_oversized_extension_payloads() {
+        let policy = Policy {
+            max_extension_bytes: Some(512),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+
+        // A host key blob claiming 4 GiB, padded past the limit
+        let mut details = vec![0xff; 4];
+        details.extend([0; 1024]);
+        let crafted = Request::Extension(Extension {
+            name: SessionBind::NAME.into(),
+            details: details.into(),
+        });
+        let response = session.handle(crafted).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert!(backend.requests().is_empty());
+
+        let response = session.handle(bind(&key(), false)).await.unwrap();
+        assert_eq!(response, Response::Success);
+        assert_eq!(backend.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_sign_while_locked_or_low_on_battery() {
+        let policy = Policy {
+            deny_when_locked: true,
+            deny_on_battery_below: Some(20),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        load(&backend, &key).await;
+
+        let battery = |discharging, percent| {
+            Some(device::Battery {
+                discharging,
+                percent,
+            })
+        };
+        for (locked, battery, allowed) in [
+            (Some(true), None, false),
+            (Some(false), battery(true, 10), false),
+            (Some(false), battery(false, 10), true),
+            (Some(false), battery(true, 50), true),
+            // Unknown states never refuse
+            (None, None, true),
+        ] {
+            let signing = session.handle(sign(&key, b"data"));
+            let response = device::simulating(locked, battery, signing).await;
+            assert_eq!(
+                signed(&response.unwrap()),
+                allowed,
+                "{locked:?} {battery:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_signs_are_refused_as_replays() {
+        let policy = Policy {
+            replay_guard: ReplayGuard::new(Some(60)),
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        let key = key();
+        load(&backend, &key).await;
+        backend.delay_signing(Duration::from_millis(100));
+
+        let mut first = connect(&proxy, &backend, 1000);
+        let mut second = connect(&proxy, &backend, 1000);
+        let (first, second) = tokio::join!(
+            first.handle(sign(&key, b"data")),
+            second.handle(sign(&key, b"data"))
+        );
+        let signatures = [first.unwrap(), second.unwrap()];
+        assert_eq!(signatures.iter().filter(|r| signed(r)).count(), 1);
+        assert_eq!(forwarded_signs(&backend), 1);
+    }
+
+    #[tokio::test]
+    async fn unsigned_data_is_not_refused_as_a_replay() {
+        let policy = Policy {
+            replay_guard: ReplayGuard::new(Some(60)),
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        let key = key();
+        load(&backend, &key).await;
+
+        let mut session = connect(&proxy, &backend, 1000);
+        backend.fail_next(1);
+        let response = session.handle(sign(&key, b"failed")).await.unwrap();
+        assert_eq!(response, Response::Failure);
+        assert!(signed(
+            &session.handle(sign(&key, b"failed")).await.unwrap()
+        ));
+
+        // Abandoned mid-sign, as by a client hanging up
+        backend.delay_signing(Duration::from_secs(10));
+        let mut abandoned = connect(&proxy, &backend, 1000);
+        let signing = abandoned.handle(sign(&key, b"abandoned"));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), signing)
+                .await
+                .is_err()
+        );
+        drop(abandoned);
+        backend.delay_signing(Duration::ZERO);
+        assert!(signed(
+            &session.handle(sign(&key, b"abandoned")).await.unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn plain_adds_are_upgraded_unless_confirmed_at_sign_time() {
+        let (mut upgrading, backend) = session(Policy::default());
+        upgrading.handle(add(&key())).await.unwrap();
+        let [Request::AddIdConstrained(upgraded)] = &backend.requests()[..] else {
+            panic!("expected a constrained add, got {:?}", backend.requests());
+        };
+        assert_eq!(upgraded.constraints, [KeyConstraint::Confirm]);
+
+        let policy = Policy {
+            sign_confirmations: SignConfirmations::new(true),
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        session.handle(add(&key)).await.unwrap();
+        assert!(matches!(backend.requests()[..], [Request::AddIdentity(_)]));
+
+        let (response, prompts) =
+            askpass::answering(Some(false), session.handle(sign(&key, b"data"))).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert_eq!(prompts.len(), 1);
+        let (response, _) =
+            askpass::answering(Some(true), session.handle(sign(&key, b"data"))).await;
+        assert!(signed(&response.unwrap()));
+        assert_eq!(forwarded_signs(&backend), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn audits_each_request_to_the_collector() {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-audit.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let collector = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        let (mut proxy, backend) = proxy(Policy::default());
+        let audit = audit::AuditSocket::new(path.clone(), proxy.policy.hash()).unwrap();
+        proxy.logging.audit = Some(Arc::new(audit));
+        let key = key();
+        load(&backend, &key).await;
+
+        let mut session = connect(&proxy, &backend, 1000);
+        session.handle(sign(&key, b"data")).await.unwrap();
+        let mut datagram = [0; 4096];
+        let len = collector.recv(&mut datagram).unwrap();
+        let event: serde_json::Value = serde_json::from_slice(&datagram[..len]).unwrap();
+        assert_eq!(event["client"]["uid"], 1000);
+        assert_eq!(event["policy"], proxy.policy.hash());
+        assert_eq!(event["request"]["type"], "sign");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removals_prompt_and_a_denial_keeps_the_key() {
+        let policy = Policy {
+            confirm_removals: true,
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        let key = key();
+        load(&backend, &key).await;
+        let removal = || {
+            Request::RemoveIdentity(RemoveIdentity {
+                pubkey: key.public_key().key_data().clone(),
+            })
+        };
+
+        let (response, prompts) = askpass::answering(Some(false), session.handle(removal())).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0].contains(&fingerprint(&key).to_string()));
+        let (response, _) =
+            askpass::answering(Some(false), session.handle(Request::RemoveAllIdentities)).await;
+        assert_eq!(response.unwrap(), Response::Failure);
+        assert!(backend.requests().is_empty());
+        assert!(signed(&session.handle(sign(&key, b"data")).await.unwrap()));
+
+        let (response, _) = askpass::answering(Some(true), session.handle(removal())).await;
+        assert_eq!(response.unwrap(), Response::Success);
+        assert_eq!(
+            session.handle(sign(&key, b"data")).await.unwrap(),
+            Response::Failure
+        );
+    }
+
+    #[tokio::test]
+    async fn confirmed_keys_fall_back_to_their_own_policy_without_a_confirmer() {
+        let (high, low) = (key(), key());
+        let policy = Policy {
+            key_confirms: vec![
+                format!("{}=deny", fingerprint(&high)).parse().unwrap(),
+                format!("{}=allow", fingerprint(&low)).parse().unwrap(),
+            ],
+            ..Policy::default()
+        };
+        let (mut session, backend) = session(policy);
+        load(&backend, &high).await;
+        load(&backend, &low).await;
+
+        for (answer, high_signs, low_signs) in [
+            (None, false, true),
+            (Some(false), false, false),
+            (Some(true), true, true),
+        ] {
+            let (response, _) =
+                askpass::answering(answer, session.handle(sign(&high, b"data"))).await;
+            assert_eq!(signed(&response.unwrap()), high_signs, "{answer:?}");
+            let (response, _) =
+                askpass::answering(answer, session.handle(sign(&low, b"data"))).await;
+            assert_eq!(signed(&response.unwrap()), low_signs, "{answer:?}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn forwards_comments_as_the_filter_rewrites_them() {
+        let upper = program("upper-filter", "tr a-z A-Z");
+        let failing = program("failing-filter", "echo ignored; exit 1");
+        let missing = std::env::temp_dir().join("ssh-agent-ac-no-such-filter");
+        let mut comments = Vec::new();
+        for filter in [&upper, &failing, &missing] {
+            let policy = Policy {
+                comment_filter: Some(CommentFilter::new(filter.clone())),
+                ..Policy::default()
+            };
+            let (mut session, backend) = session(policy);
+            let response = session.handle(add(&key_with_comment("me@laptop"))).await;
+            assert_eq!(response.unwrap(), Response::Success);
+            comments.extend(forwarded_comments(&backend));
+        }
+        fs::remove_file(&upper).unwrap();
+        fs::remove_file(&failing).unwrap();
+        assert_eq!(comments, ["ME@LAPTOP", "me@laptop", "me@laptop"]);
+    }
+
+    #[tokio::test]
+    async fn hides_security_keys_from_other_uids() {
+        let policy = Policy {
+            sk_key_users: Some(vec![1000]),
+            ..Policy::default()
+        };
+        let (proxy, backend) = proxy(policy);
+        let (key, sk) = (key(), sk_keypair());
+        load(&backend, &key).await;
+        let response = backend.agent.clone().handle(add_keypair(sk.clone())).await;
+        assert_eq!(response.unwrap(), Response::Success);
+        let sk = KeyData::try_from(&sk).unwrap().fingerprint(HashAlg::Sha256);
+
+        let mut trusted = connect(&proxy, &backend, 1000);
+        let response = trusted.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(listed(response), [fingerprint(&key), sk]);
+
+        let mut restricted = connect(&proxy, &backend, 1001);
+        let response = restricted.handle(Request::RequestIdentities).await.unwrap();
+        assert_eq!(listed(response), [fingerprint(&key)]);
+    }
+
+    #[tokio::test]
+    async fn lists_fake_identities_and_refuses_to_sign_with_them() {
+        for (mode, refusal) in [
+            (FakeSignResponse::Failure, Response::Failure),
+            (
+                FakeSignResponse::ExtensionFailure,
+                Response::ExtensionFailure,
+            ),
+        ] {
+            let fake = key();
+            let policy = Policy {
+                fake_identities: FakeIdentities::new(vec![fake.public_key().clone()], mode),
+                ..Policy::default()
+            };
+            let (mut session, backend) = session(policy);
+            let real = key();
+            load(&backend, &real).await;
+
+            let response = session.handle(Request::RequestIdentities).await.unwrap();
+            assert_eq!(listed(response), [fingerprint(&real), fingerprint(&fake)]);
+            let response = session.handle(sign(&fake, b"data")).await.unwrap();
+            assert_eq!(response, refusal);
+            assert_eq!(forwarded_signs(&backend), 0);
+            assert!(signed(&session.handle(sign(&real, b"data")).await.unwrap()));
+        }
+    }
 }
@@ -0,0 +1,410 @@
+use log::{debug, warn};
+use rsa::BigUint;
+use rsa::pkcs1v15::SigningKey;
+use sha2::{Digest, Sha256, Sha512};
+use signature::{SignatureEncoding, Signer};
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::extension::{MessageExtension, QueryResponse, SessionBind};
+use ssh_agent_lib::proto::message::KeyConstraint;
+use ssh_agent_lib::proto::{Credential, Extension, Identity, Request, Response, SignRequest};
+use ssh_agent_lib::ssh_key::private::{KeypairData, RsaKeypair};
+use ssh_agent_lib::ssh_key::public::KeyData;
+use ssh_agent_lib::ssh_key::{Algorithm, Fingerprint, HashAlg, Mpint, Signature};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::askpass;
+use crate::sigalg::{RSA_SHA2_256, RSA_SHA2_512};
+
+/// Software agent holding keys in the proxy's memory, used instead of a backend ssh-agent
+///
+/// All connections share one key store, which is lost when the proxy exits.
+/// Lifetime and confirm constraints are honoured like ssh-agent does;
+/// certificates, extension constraints and smartcards are refused.
+#[derive(Clone, Default)]
+pub struct InProcessAgent {
+    state: Arc<Mutex<State>>,
+    /// Keys this connection signs with without confirming, despite a confirm constraint
+    unconfirmed: Vec<Fingerprint>,
+}
+
+#[derive(Default)]
+struct State {
+    keys: Vec<StoredKey>,
+    /// SHA-256 of the passphrase the agent is locked with
+    lock: Option<[u8; 32]>,
+}
+
+struct StoredKey {
+    privkey: KeypairData,
+    pubkey: KeyData,
+    comment: String,
+    expires: Option<Instant>,
+    confirm: bool,
+}
+
+impl InProcessAgent {
+    /// A connection to the same key store that signs with `keys` without confirming
+    pub fn without_confirming(&self, keys: Vec<Fingerprint>) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+            unconfirmed: keys,
+        }
+    }
+
+    /// The key store, without keys whose lifetime has run out
+    fn state(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        state.keys.retain(|key| key.expires.is_none_or(|t| t > now));
+        state
+    }
+
+    fn identities(&self) -> Vec<Identity> {
+        let state = self.state();
+        if state.lock.is_some() {
+            return Vec::new();
+        }
+        state
+            .keys
+            .iter()
+            .map(|key| Identity {
+                pubkey: key.pubkey.clone(),
+                comment: key.comment.clone(),
+            })
+            .collect()
+    }
+
+    fn add_key(&self, credential: Credential, constraints: &[KeyConstraint]) -> Response {
+        let Credential::Key { privkey, comment } = credential else {
+            warn!("The in-process agent does not hold certificates");
+            return Response::Failure;
+        };
+        let Ok(pubkey) = KeyData::try_from(&privkey) else {
+            return Response::Failure;
+        };
+
+        let mut expires = None;
+        let mut confirm = false;
+        for constraint in constraints {
+            match constraint {
+                KeyConstraint::Lifetime(secs) => {
+                    expires = Some(Instant::now() + Duration::from_secs(u64::from(*secs)));
+                }
+                KeyConstraint::Confirm => confirm = true,
+                KeyConstraint::Extension(extension) => {
+                    let name = &extension.name;
+                    warn!("The in-process agent does not support constraint {name}");
+                    return Response::Failure;
+                }
+            }
+        }
+
+        let mut state = self.state();
+        if state.lock.is_some() {
+            return Response::Failure;
+        }
+        // Adding a key again replaces it, constraints included
+        state.keys.retain(|key| key.pubkey != pubkey);
+        state.keys.push(StoredKey {
+            privkey,
+            pubkey,
+            comment,
+            expires,
+            confirm,
+        });
+        Response::Success
+    }
+
+    /// Whether signing with `pubkey` needs confirming, if the key is held
+    #[cfg(test)]
+    pub fn confirms(&self, pubkey: &KeyData) -> Option<bool> {
+        let state = self.state();
+        let key = state.keys.iter().find(|key| key.pubkey == *pubkey);
+        key.map(|key| key.confirm)
+    }
+
+    fn remove_key(&self, pubkey: &KeyData) -> Response {
+        let mut state = self.state();
+        if state.lock.is_some() {
+            return Response::Failure;
+        }
+        let before = state.keys.len();
+        state.keys.retain(|key| key.pubkey != *pubkey);
+        if state.keys.len() == before {
+            return Response::Failure;
+        }
+        Response::Success
+    }
+
+    async fn sign_request(&self, request: SignRequest) -> Response {
+        let found = {
+            let state = self.state();
+            if state.lock.is_some() {
+                return Response::Failure;
+            }
+            let key = state.keys.iter().find(|key| key.pubkey == request.pubkey);
+            key.map(|key| (key.privkey.clone(), key.comment.clone(), key.confirm))
+        };
+        let Some((privkey, comment, confirm)) = found else {
+            return Response::Failure;
+        };
+
+        let fingerprint = request.pubkey.fingerprint(HashAlg::Sha256);
+        let prompt = format!("Allow use of key {comment}?\nKey fingerprint {fingerprint}.");
+        let bypassed = confirm && self.unconfirmed.contains(&fingerprint);
+        if bypassed {
+            warn!("Signing with {fingerprint} without confirmation: bypassed for this UID");
+        }
+        if confirm && !bypassed && !askpass::confirm(&prompt).await {
+            debug!("Use of key {fingerprint} not confirmed");
+            return Response::Failure;
+        }
+
+        let signature = match &privkey {
+            // SHA-256 first when both are asked for, as in OpenSSH's ssh-agent
+            KeypairData::Rsa(keypair) if request.flags & RSA_SHA2_256 != 0 => {
+                rsa_sign(keypair, HashAlg::Sha256, &request.data)
+            }
+            KeypairData::Rsa(keypair) if request.flags & RSA_SHA2_512 != 0 => {
+                rsa_sign(keypair, HashAlg::Sha512, &request.data)
+            }
+            KeypairData::Rsa(_) => {
+                warn!("The in-process agent does not sign with ssh-rsa (SHA-1)");
+                return Response::Failure;
+            }
+            key => key.try_sign(&request.data).map_err(Into::into),
+        };
+        match signature {
+            Ok(signature) => Response::SignResponse(signature),
+            Err(e) => {
+                warn!("Failed to sign with {fingerprint}: {e}");
+                Response::Failure
+            }
+        }
+    }
+
+    fn lock_with(&self, passphrase: &str) -> Response {
+        let mut state = self.state();
+        if state.lock.is_some() {
+            return Response::Failure;
+        }
+        state.lock = Some(Sha256::digest(passphrase).into());
+        Response::Success
+    }
+
+    fn unlock_with(&self, passphrase: &str) -> Response {
+        let mut state = self.state();
+        let digest: [u8; 32] = Sha256::digest(passphrase).into();
+        if state.lock != Some(digest) {
+            return Response::Failure;
+        }
+        state.lock = None;
+        Response::Success
+    }
+}
+
+/// Sign `data` as rsa-sha2-256 or rsa-sha2-512
+///
+/// ssh-key 0.6 builds the RSA key from the first prime twice, so its own
+/// signer fails on every real key; the key is assembled here instead.
+fn rsa_sign(
+    keypair: &RsaKeypair,
+    hash: HashAlg,
+    data: &[u8],
+) -> Result<Signature, Box<dyn std::error::Error>> {
+    let uint = |mpint: &Mpint| {
+        let bytes = mpint
+            .as_positive_bytes()
+            .ok_or("negative RSA key component")?;
+        Ok::<_, &str>(BigUint::from_bytes_be(bytes))
+    };
+    let key = rsa::RsaPrivateKey::from_components(
+        uint(&keypair.public.n)?,
+        uint(&keypair.public.e)?,
+        uint(&keypair.private.d)?,
+        vec![uint(&keypair.private.p)?, uint(&keypair.private.q)?],
+    )?;
+
+    let data = match hash {
+        HashAlg::Sha256 => SigningKey::<Sha256>::new(key).try_sign(data)?.to_vec(),
+        _ => SigningKey::<Sha512>::new(key).try_sign(data)?.to_vec(),
+    };
+    let algorithm = Algorithm::Rsa { hash: Some(hash) };
+    Ok(Signature::new(algorithm, data)?)
+}
+
+#[ssh_agent_lib::async_trait]
+impl Session for InProcessAgent {
+    async fn handle(&mut self, message: Request) -> Result<Response, AgentError> {
+        let response = match message {
+            Request::RequestIdentities => Response::IdentitiesAnswer(self.identities()),
+            Request::SignRequest(request) => self.sign_request(request).await,
+            Request::AddIdentity(add) => self.add_key(add.credential, &[]),
+            Request::AddIdConstrained(add) => {
+                self.add_key(add.identity.credential, &add.constraints)
+            }
+            Request::RemoveIdentity(remove) => self.remove_key(&remove.pubkey),
+            Request::RemoveAllIdentities => {
+                let mut state = self.state();
+                if state.lock.is_some() {
+                    Response::Failure
+                } else {
+                    state.keys.clear();
+                    Response::Success
+                }
+            }
+            Request::Lock(passphrase) => self.lock_with(&passphrase),
+            Request::Unlock(passphrase) => self.unlock_with(&passphrase),
+            // The proxy tracks bindings itself; acknowledge them as ssh-agent does
+            Request::Extension(extension) if extension.name == SessionBind::NAME => {
+                Response::Success
+            }
+            Request::Extension(extension) if extension.name == QueryResponse::NAME => {
+                let supported = QueryResponse {
+                    extensions: vec![QueryResponse::NAME.into(), SessionBind::NAME.into()],
+                };
+                match Extension::new_message(supported) {
+                    Ok(answer) => Response::ExtensionResponse(answer),
+                    Err(_) => Response::Failure,
+                }
+            }
+            _ => Response::Failure,
+        };
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs1v15::VerifyingKey;
+    use rsa::traits::{PrivateKeyParts, PublicKeyParts};
+    use signature::Verifier;
+    use ssh_agent_lib::proto::{AddIdentity, RemoveIdentity};
+    use ssh_agent_lib::ssh_key::PrivateKey;
+    use ssh_agent_lib::ssh_key::private::RsaPrivateKey;
+    use ssh_agent_lib::ssh_key::public::RsaPublicKey;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+
+    async fn with_key() -> (InProcessAgent, KeyData) {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut agent = InProcessAgent::default();
+        add(&mut agent, key.key_data().clone()).await;
+        (agent, key.public_key().key_data().clone())
+    }
+
+    async fn add(agent: &mut InProcessAgent, privkey: KeypairData) {
+        let add = AddIdentity {
+            credential: Credential::Key {
+                privkey,
+                comment: "test".to_string(),
+            },
+        };
+        let response = agent.handle(Request::AddIdentity(add)).await.unwrap();
+        assert!(matches!(response, Response::Success));
+    }
+
+    async fn sign(agent: &mut InProcessAgent, pubkey: &KeyData, flags: u32) -> Response {
+        let request = SignRequest {
+            pubkey: pubkey.clone(),
+            data: b"data".to_vec(),
+            flags,
+        };
+        agent.handle(Request::SignRequest(request)).await.unwrap()
+    }
+
+    /// A freshly generated RSA key, small so that debug builds generate it quickly
+    fn rsa_key() -> (rsa::RsaPrivateKey, KeypairData) {
+        let key = rsa::RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let mpint = |uint: &BigUint| Mpint::from_positive_bytes(&uint.to_bytes_be()).unwrap();
+        let keypair = RsaKeypair {
+            public: RsaPublicKey {
+                e: mpint(key.e()),
+                n: mpint(key.n()),
+            },
+            private: RsaPrivateKey {
+                d: mpint(key.d()),
+                iqmp: mpint(&key.crt_coefficient().unwrap()),
+                p: mpint(&key.primes()[0]),
+                q: mpint(&key.primes()[1]),
+            },
+        };
+        (key, KeypairData::Rsa(keypair))
+    }
+
+    async fn listed(agent: &mut InProcessAgent) -> usize {
+        match agent.handle(Request::RequestIdentities).await.unwrap() {
+            Response::IdentitiesAnswer(identities) => identities.len(),
+            response => panic!("unexpected {response:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn removing_from_a_locked_agent_keeps_the_key() {
+        let (mut agent, pubkey) = with_key().await;
+        let lock = agent.handle(Request::Lock("pass".into())).await.unwrap();
+        assert!(matches!(lock, Response::Success));
+
+        let remove = Request::RemoveIdentity(RemoveIdentity {
+            pubkey: pubkey.clone(),
+        });
+        let response = agent.handle(remove).await.unwrap();
+        assert!(matches!(response, Response::Failure));
+        let removed = agent.handle(Request::RemoveAllIdentities).await.unwrap();
+        assert!(matches!(removed, Response::Failure));
+
+        agent.handle(Request::Unlock("pass".into())).await.unwrap();
+        assert_eq!(listed(&mut agent).await, 1);
+    }
+
+    #[tokio::test]
+    async fn removing_from_an_unlocked_agent_drops_the_key() {
+        let (mut agent, pubkey) = with_key().await;
+        let remove = Request::RemoveIdentity(RemoveIdentity { pubkey });
+        let response = agent.handle(remove).await.unwrap();
+        assert!(matches!(response, Response::Success));
+        assert_eq!(listed(&mut agent).await, 0);
+    }
+
+    #[tokio::test]
+    async fn ed25519_signatures_verify() {
+        let (mut agent, pubkey) = with_key().await;
+        let Response::SignResponse(signature) = sign(&mut agent, &pubkey, 0).await else {
+            panic!("not signed");
+        };
+        assert_eq!(signature.algorithm(), Algorithm::Ed25519);
+        pubkey.verify(b"data", &signature).unwrap();
+        assert!(pubkey.verify(b"other", &signature).is_err());
+    }
+
+    #[tokio::test]
+    async fn rsa_signatures_use_the_requested_hash_and_verify() {
+        let (key, privkey) = rsa_key();
+        let mut agent = InProcessAgent::default();
+        add(&mut agent, privkey.clone()).await;
+        let pubkey = KeyData::try_from(&privkey).unwrap();
+        let verifier = rsa::RsaPublicKey::from(&key);
+
+        let signed = |response| match response {
+            Response::SignResponse(signature) => signature,
+            response => panic!("unexpected {response:?}"),
+        };
+        let sha256 = signed(sign(&mut agent, &pubkey, RSA_SHA2_256).await);
+        assert_eq!(sha256.algorithm().as_str(), "rsa-sha2-256");
+        let sha256 = rsa::pkcs1v15::Signature::try_from(sha256.as_bytes()).unwrap();
+        let check = VerifyingKey::<Sha256>::new(verifier.clone());
+        check.verify(b"data", &sha256).unwrap();
+
+        let sha512 = signed(sign(&mut agent, &pubkey, RSA_SHA2_512).await);
+        assert_eq!(sha512.algorithm().as_str(), "rsa-sha2-512");
+        let sha512 = rsa::pkcs1v15::Signature::try_from(sha512.as_bytes()).unwrap();
+        let check = VerifyingKey::<Sha512>::new(verifier);
+        check.verify(b"data", &sha512).unwrap();
+
+        // Never ssh-rsa (SHA-1)
+        let response = sign(&mut agent, &pubkey, 0).await;
+        assert!(matches!(response, Response::Failure));
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// How to tell a supervisor that the proxy is ready to serve
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotifyReady {
+    None,
+    /// Send `READY=1` to the socket in NOTIFY_SOCKET, like `sd_notify(3)`
+    Systemd,
+    /// Write the proxy's PID to this file
+    File(PathBuf),
+}
+
+impl FromStr for NotifyReady {
+    type Err = String;
+
+    /// Parse `none`, `systemd` or `file:<PATH>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "systemd" => Ok(Self::Systemd),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Self::File(path.into())),
+                _ => Err("expected none, systemd or file:<PATH>".into()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for NotifyReady {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Systemd => write!(f, "systemd"),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+impl NotifyReady {
+    /// Remove a readiness file left by an earlier run, so it does not announce this one too early
+    pub fn reset(&self) -> io::Result<()> {
+        match self {
+            Self::File(path) if path.exists() => std::fs::remove_file(path),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn notify(&self) -> io::Result<()> {
+        match self {
+            Self::None => Ok(()),
+            Self::Systemd => sd_notify("READY=1"),
+            Self::File(path) => std::fs::write(path, format!("{}\n", std::process::id())),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn sd_notify(state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = std::env::var_os("NOTIFY_SOCKET")
+        .ok_or_else(|| io::Error::other("NOTIFY_SOCKET is not set"))?;
+    let socket = UnixDatagram::unbound()?;
+
+    // A leading '@' names a socket in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        return Ok(());
+    }
+
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn sd_notify(_state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "systemd notification is only available on Unix",
+    ))
+}
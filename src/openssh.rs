@@ -0,0 +1,297 @@
+use clap::ValueEnum;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::extension::{MessageExtension, SessionBind};
+use ssh_agent_lib::proto::message::KeyConstraint;
+use ssh_agent_lib::proto::{Credential, Request, Response};
+use ssh_agent_lib::ssh_key::HashAlg;
+use ssh_agent_lib::ssh_key::public::KeyData;
+
+use crate::client::ClientInfo;
+use crate::keys::{credential_comment, credential_key};
+
+/// Destination constraint extension, as recorded by `ssh-add -h`
+const RESTRICT_DESTINATION: &str = "restrict-destination-v00@openssh.com";
+
+/// How requests and responses are logged
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Only the proxy's own log lines
+    #[default]
+    Plain,
+    /// Also trace every request and response to stderr like `ssh-agent -d`
+    OpensshDebug,
+}
+
+/// Name of the ssh-agent.c function handling `request`, used as the line prefix
+pub fn process_name(request: &Request) -> &'static str {
+    match request {
+        Request::RequestIdentities => "process_request_identities",
+        Request::SignRequest(_) => "process_sign_request2",
+        Request::AddIdentity(_) | Request::AddIdConstrained(_) => "process_add_identity",
+        Request::RemoveIdentity(_) => "process_remove_identity",
+        Request::RemoveAllIdentities => "process_remove_all_identities",
+        Request::AddSmartcardKey(_) | Request::AddSmartcardKeyConstrained(_) => {
+            "process_add_smartcard_key"
+        }
+        Request::RemoveSmartcardKey(_) => "process_remove_smartcard_key",
+        Request::Lock(_) | Request::Unlock(_) => "process_lock_agent",
+        Request::Extension(_) => "process_extension",
+    }
+}
+
+/// SSH_AGENTC_* message number of `request`
+fn message_type(request: &Request) -> u8 {
+    match request {
+        Request::RequestIdentities => 11,
+        Request::SignRequest(_) => 13,
+        Request::AddIdentity(_) => 17,
+        Request::RemoveIdentity(_) => 18,
+        Request::RemoveAllIdentities => 19,
+        Request::AddSmartcardKey(_) => 20,
+        Request::RemoveSmartcardKey(_) => 21,
+        Request::Lock(_) => 22,
+        Request::Unlock(_) => 23,
+        Request::AddIdConstrained(_) => 25,
+        Request::AddSmartcardKeyConstrained(_) => 26,
+        Request::Extension(_) => 27,
+    }
+}
+
+/// Lines `ssh-agent -d` would print on receiving `request` from `client`
+///
+/// Secrets never appear: private keys, PINs and passphrases are not part of
+/// any line.
+pub fn request_lines(request: &Request, client: &ClientInfo) -> Vec<String> {
+    let process = process_name(request);
+    let mut lines = vec![
+        format!(
+            "debug1: process_message: client {client} type {}",
+            message_type(request)
+        ),
+        format!("debug2: {process}: entering"),
+    ];
+
+    match request {
+        Request::SignRequest(sign) => {
+            let key = key(&sign.pubkey);
+            let flags = sign.flags;
+            lines.push(format!("debug2: {process}: key {key} flags 0x{flags:02x}"));
+        }
+        Request::AddIdentity(add) => lines.push(add_line(&add.credential, &[])),
+        Request::AddIdConstrained(add) => {
+            lines.push(add_line(&add.identity.credential, &add.constraints));
+        }
+        Request::RemoveIdentity(remove) => {
+            lines.push(format!("debug1: {process}: key {}", key(&remove.pubkey)));
+        }
+        Request::AddSmartcardKey(key) => lines.push(format!("debug1: {process}: add {}", key.id)),
+        Request::AddSmartcardKeyConstrained(add) => {
+            lines.push(format!("debug1: {process}: add {}", add.key.id));
+        }
+        Request::Extension(extension) if extension.name == SessionBind::NAME => {
+            lines.push("debug2: process_ext_session_bind: entering".to_string());
+            if let Ok(Some(bind)) = extension.parse_message::<SessionBind>() {
+                let host = key(&bind.host_key);
+                let forwarding = if bind.is_forwarding { "yes" } else { "no" };
+                lines.push(format!(
+                    "debug1: process_ext_session_bind: host key {host} (forwarding: {forwarding})"
+                ));
+            }
+        }
+        Request::Extension(extension) => {
+            let name = &extension.name;
+            lines.push(format!("debug1: {process}: extension {name:?}"));
+        }
+        _ => {}
+    }
+    lines
+}
+
+/// Lines describing the reply to a request handled by `process`
+pub fn response_lines(process: &str, result: &Result<Response, AgentError>) -> Vec<String> {
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => return vec![format!("error: {process}: {e}")],
+    };
+
+    match response {
+        Response::IdentitiesAnswer(identities) => {
+            let total = identities.len();
+            let mut lines: Vec<_> = identities
+                .iter()
+                .enumerate()
+                .map(|(i, id)| format!("debug1: {process}: key {i} / {total}: {}", key(&id.pubkey)))
+                .collect();
+            lines.push(format!("debug2: {process}: replying with {total} keys"));
+            lines
+        }
+        Response::SignResponse(_) => vec![format!("debug1: {process}: good signature")],
+        Response::Success => vec![format!("debug2: {process}: success")],
+        Response::Failure => vec![format!("debug1: {process}: failure")],
+        Response::ExtensionFailure => vec![format!("debug1: {process}: extension failure")],
+        Response::ExtensionResponse(extension) => vec![format!(
+            "debug2: {process}: replying to extension \"{}\"",
+            extension.name
+        )],
+    }
+}
+
+/// `<algorithm> <fingerprint>`, as ssh-agent prints keys
+fn key(key: &KeyData) -> String {
+    format!("{} {}", key.algorithm(), key.fingerprint(HashAlg::Sha256))
+}
+
+fn add_line(credential: &Credential, constraints: &[KeyConstraint]) -> String {
+    let key = credential_key(credential).map_or_else(|| "unknown key".to_string(), |k| key(&k));
+    let comment = credential_comment(credential);
+
+    let mut life = 0;
+    let mut confirm = 0;
+    let mut destinations = 0;
+    for constraint in constraints {
+        match constraint {
+            KeyConstraint::Lifetime(secs) => life = *secs,
+            KeyConstraint::Confirm => confirm = 1,
+            KeyConstraint::Extension(extension) if extension.name == RESTRICT_DESTINATION => {
+                destinations += 1;
+            }
+            KeyConstraint::Extension(_) => {}
+        }
+    }
+
+    format!(
+        "debug1: process_add_identity: add {key} {comment:?} (life: {life}) (confirm: {confirm}) (provider: none) (destination constraints: {destinations})"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::proto::{
+        AddIdentity, AddIdentityConstrained, Identity, SignRequest, SmartcardKey,
+    };
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey};
+
+    fn client() -> ClientInfo {
+        ClientInfo {
+            pid: Some(42),
+            uid: Some(1000),
+            cmdline: None,
+        }
+    }
+
+    fn private_key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    #[test]
+    fn names_the_ssh_agent_handler() {
+        assert_eq!(
+            process_name(&Request::RequestIdentities),
+            "process_request_identities"
+        );
+        assert_eq!(
+            process_name(&Request::RemoveAllIdentities),
+            "process_remove_all_identities"
+        );
+        assert_eq!(
+            process_name(&Request::Lock("pass".into())),
+            "process_lock_agent"
+        );
+        assert_eq!(
+            process_name(&Request::Unlock("pass".into())),
+            "process_lock_agent"
+        );
+    }
+
+    #[test]
+    fn traces_sign_requests() {
+        let key = private_key();
+        let request = Request::SignRequest(SignRequest {
+            pubkey: key.public_key().key_data().clone(),
+            data: b"data".to_vec(),
+            flags: 4,
+        });
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        assert_eq!(
+            request_lines(&request, &client()),
+            [
+                "debug1: process_message: client pid=42 uid=1000 type 13".to_string(),
+                "debug2: process_sign_request2: entering".to_string(),
+                format!("debug2: process_sign_request2: key ssh-ed25519 {fingerprint} flags 0x04"),
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_constraints_of_added_keys() {
+        let key = private_key();
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        let request = Request::AddIdConstrained(AddIdentityConstrained {
+            identity: AddIdentity {
+                credential: Credential::Key {
+                    privkey: key.key_data().clone(),
+                    comment: "work".to_string(),
+                },
+            },
+            constraints: vec![KeyConstraint::Lifetime(60), KeyConstraint::Confirm],
+        });
+        let lines = request_lines(&request, &client());
+        assert_eq!(
+            lines[2],
+            format!(
+                "debug1: process_add_identity: add ssh-ed25519 {fingerprint} \"work\" (life: 60) (confirm: 1) (provider: none) (destination constraints: 0)"
+            )
+        );
+    }
+
+    #[test]
+    fn never_traces_smartcard_pins() {
+        let request = Request::AddSmartcardKey(SmartcardKey {
+            id: "/usr/lib/opensc-pkcs11.so".to_string(),
+            pin: "123456".to_string().into(),
+        });
+        let lines = request_lines(&request, &client());
+        assert_eq!(
+            lines[2],
+            "debug1: process_add_smartcard_key: add /usr/lib/opensc-pkcs11.so"
+        );
+        assert!(lines.iter().all(|line| !line.contains("123456")));
+    }
+
+    #[test]
+    fn traces_listed_keys() {
+        let key = private_key();
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        let identities = vec![Identity {
+            pubkey: key.public_key().key_data().clone(),
+            comment: "work".to_string(),
+        }];
+        let process = "process_request_identities";
+        assert_eq!(
+            response_lines(process, &Ok(Response::IdentitiesAnswer(identities))),
+            [
+                format!("debug1: {process}: key 0 / 1: ssh-ed25519 {fingerprint}"),
+                format!("debug2: {process}: replying with 1 keys"),
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_failures_and_errors() {
+        let process = "process_sign_request2";
+        assert_eq!(
+            response_lines(process, &Ok(Response::Failure)),
+            ["debug1: process_sign_request2: failure"]
+        );
+        let error = Err(AgentError::Failure);
+        assert_eq!(
+            response_lines(process, &error),
+            [format!(
+                "error: process_sign_request2: {}",
+                AgentError::Failure
+            )]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use ssh_agent_lib::proto::Identity;
+use ssh_agent_lib::ssh_key::{Fingerprint, HashAlg};
+use std::fmt;
+use std::str::FromStr;
+
+/// Order in which listed identities are returned, which clients often try keys in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum IdentityOrder {
+    /// As the backend lists them
+    #[default]
+    Backend,
+    /// Sorted by comment
+    Comment,
+    /// These keys first, in this order, then the rest as the backend lists them
+    Pinned(Vec<Fingerprint>),
+}
+
+impl FromStr for IdentityOrder {
+    type Err = String;
+
+    /// Parse `backend`, `comment` or `pinned=<FINGERPRINT>[,<FINGERPRINT>...]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "backend" => Ok(Self::Backend),
+            "comment" => Ok(Self::Comment),
+            _ => {
+                let pinned = s
+                    .strip_prefix("pinned=")
+                    .ok_or("expected backend, comment or pinned=<FINGERPRINT>[,...]")?;
+                pinned
+                    .split(',')
+                    .map(|fingerprint| fingerprint.parse().map_err(|e| format!("{e}")))
+                    .collect::<Result<_, _>>()
+                    .map(Self::Pinned)
+            }
+        }
+    }
+}
+
+impl fmt::Display for IdentityOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Backend => write!(f, "backend"),
+            Self::Comment => write!(f, "comment"),
+            Self::Pinned(pinned) => {
+                let pinned: Vec<_> = pinned.iter().map(ToString::to_string).collect();
+                write!(f, "pinned={}", pinned.join(","))
+            }
+        }
+    }
+}
+
+impl IdentityOrder {
+    /// Put `identities` in this order; sorting is stable, so ties keep the backend's order
+    pub fn apply(&self, identities: &mut [Identity]) {
+        match self {
+            Self::Backend => {}
+            Self::Comment => identities.sort_by(|a, b| a.comment.cmp(&b.comment)),
+            Self::Pinned(pinned) => identities.sort_by_key(|id| {
+                let fingerprint = id.pubkey.fingerprint(HashAlg::Sha256);
+                pinned
+                    .iter()
+                    .position(|pin| *pin == fingerprint)
+                    .unwrap_or(pinned.len())
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey};
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+    const OTHER: &str = "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU";
+
+    fn identity(comment: &str) -> Identity {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        Identity {
+            pubkey: key.public_key().key_data().clone(),
+            comment: comment.to_string(),
+        }
+    }
+
+    fn comments(identities: &[Identity]) -> Vec<&str> {
+        identities.iter().map(|id| id.comment.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_and_displays_orders() {
+        for order in ["backend", "comment", &format!("pinned={KEY},{OTHER}")] {
+            assert_eq!(order.parse::<IdentityOrder>().unwrap().to_string(), order);
+        }
+        assert_eq!(
+            format!("pinned={KEY}").parse(),
+            Ok(IdentityOrder::Pinned(vec![KEY.parse().unwrap()]))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_orders() {
+        assert!("random".parse::<IdentityOrder>().is_err());
+        assert!("pinned=".parse::<IdentityOrder>().is_err());
+        assert!(
+            format!("pinned={KEY},nope")
+                .parse::<IdentityOrder>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn backend_order_is_unchanged() {
+        let mut identities = vec![identity("b"), identity("a")];
+        IdentityOrder::Backend.apply(&mut identities);
+        assert_eq!(comments(&identities), ["b", "a"]);
+    }
+
+    #[test]
+    fn sorts_by_comment_keeping_ties_in_backend_order() {
+        let mut identities = vec![identity("b"), identity("a"), identity("b"), identity("a")];
+        let keys: Vec<_> = identities.iter().map(|id| id.pubkey.clone()).collect();
+        IdentityOrder::Comment.apply(&mut identities);
+        assert_eq!(comments(&identities), ["a", "a", "b", "b"]);
+        assert_eq!(identities[0].pubkey, keys[1]);
+        assert_eq!(identities[1].pubkey, keys[3]);
+        assert_eq!(identities[2].pubkey, keys[0]);
+    }
+
+    #[test]
+    fn pinned_keys_come_first_in_pinned_order() {
+        let mut identities = vec![identity("a"), identity("b"), identity("c"), identity("d")];
+        let pinned = [&identities[2], &identities[1]]
+            .map(|id| id.pubkey.fingerprint(HashAlg::Sha256))
+            .to_vec();
+        IdentityOrder::Pinned(pinned).apply(&mut identities);
+        assert_eq!(comments(&identities), ["c", "b", "a", "d"]);
+    }
+}
@@ -0,0 +1,149 @@
+use log::warn;
+use opentelemetry::trace::{FutureExt, Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue, global};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::{Request, Response};
+use ssh_agent_lib::ssh_key::HashAlg;
+use std::time::Instant;
+
+use crate::request::{request_key, request_name};
+
+/// Exports spans until dropped, flushing whatever is still buffered
+pub struct OtelGuard(SdkTracerProvider);
+
+impl Drop for OtelGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            warn!("Failed to flush OpenTelemetry spans: {e}");
+        }
+    }
+}
+
+/// Install a global tracer exporting over OTLP/HTTP to `endpoint` (e.g. `http://localhost:4318/v1/traces`)
+pub fn init(endpoint: &str) -> Result<OtelGuard, Box<dyn std::error::Error>> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Ok(OtelGuard(provider))
+}
+
+/// Session wrapper recording every handled request as a span
+///
+/// The proxy session is wrapped as `request` and its backend as `backend`, so
+/// backend calls (including any confirmation the backend waits for) become
+/// child spans of the request they serve.
+pub struct Traced {
+    inner: Box<dyn Session>,
+    name: &'static str,
+}
+
+impl Traced {
+    pub fn new(inner: Box<dyn Session>, name: &'static str) -> Self {
+        Self { inner, name }
+    }
+}
+
+#[ssh_agent_lib::async_trait]
+impl Session for Traced {
+    async fn handle(&mut self, message: Request) -> Result<Response, AgentError> {
+        let mut span = global::tracer(env!("CARGO_PKG_NAME")).start(self.name);
+        span.set_attribute(KeyValue::new("ssh_agent.request", request_name(&message)));
+        if let Some(key) = request_key(&message) {
+            span.set_attribute(KeyValue::new(
+                "ssh_agent.fingerprint",
+                key.fingerprint(HashAlg::Sha256).to_string(),
+            ));
+        }
+
+        let cx = Context::current_with_span(span);
+        let started = Instant::now();
+        let result = self.inner.handle(message).with_context(cx.clone()).await;
+
+        let span = cx.span();
+        span.set_attribute(KeyValue::new(
+            "ssh_agent.latency_ms",
+            started.elapsed().as_secs_f64() * 1000.0,
+        ));
+        let decision = match &result {
+            Ok(Response::Failure | Response::ExtensionFailure) => "failure",
+            Ok(_) => "success",
+            Err(e) => {
+                span.set_status(Status::error(e.to_string()));
+                "error"
+            }
+        };
+        span.set_attribute(KeyValue::new("ssh_agent.decision", decision));
+        span.end();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+
+    /// Backend failing every request
+    struct Failing;
+
+    #[ssh_agent_lib::async_trait]
+    impl Session for Failing {
+        async fn handle(&mut self, _: Request) -> Result<Response, AgentError> {
+            Ok(Response::Failure)
+        }
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<String> {
+        let kv = span.attributes.iter().find(|kv| kv.key.as_str() == key)?;
+        Some(kv.value.to_string())
+    }
+
+    #[tokio::test]
+    async fn records_a_span_per_request_with_a_backend_child() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        global::set_tracer_provider(provider.clone());
+
+        let backend = Box::new(Traced::new(Box::new(Failing), "backend"));
+        let mut session = Traced::new(backend, "request");
+        session.handle(Request::RequestIdentities).await.unwrap();
+        session.handle(Request::RemoveAllIdentities).await.unwrap();
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["backend", "request", "backend", "request"]);
+        for pair in spans.chunks(2) {
+            let (backend, request) = (&pair[0], &pair[1]);
+            assert_eq!(backend.parent_span_id, request.span_context.span_id());
+            let decision = attribute(request, "ssh_agent.decision");
+            assert_eq!(decision.as_deref(), Some("failure"));
+        }
+        let requests: Vec<_> = [&spans[1], &spans[3]]
+            .map(|span| attribute(span, "ssh_agent.request"))
+            .to_vec();
+        assert_eq!(
+            requests,
+            [
+                Some("request-identities".into()),
+                Some("remove-all-identities".into())
+            ]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use ssh_agent_lib::ssh_key::{Algorithm, Fingerprint};
+use std::fmt::Display;
+
+use crate::allowlist::AllowedKeys;
+use crate::anomaly::FlagProfiles;
+use crate::comment::CommentFilter;
+use crate::constraint::{EnforcedConstraint, SignConfirmations};
+use crate::extensions::BackendExtensions;
+use crate::fake::{FakeIdentities, FakeSignResponse};
+use crate::forwarding::OnForwarded;
+use crate::keyconfirm::KeyConfirm;
+use crate::keyusers::KeyUsers;
+use crate::killswitch::KillSwitch;
+use crate::order::IdentityOrder;
+use crate::quota::Quotas;
+use crate::ratelimit::RateLimiter;
+use crate::replay::ReplayGuard;
+use crate::rotation::Rotations;
+#[cfg(feature = "policy-script")]
+use crate::script::PolicyScript;
+use crate::sigalg::KeySignatureAlgorithms;
+use crate::signprefix::KeySignPrefix;
+use crate::totp::Totps;
+use std::time::Duration;
+
+/// Effective policy enforced by the proxy, shared by all sessions.
+#[derive(Debug, Serialize)]
+pub struct Policy {
+    /// Answer the info extension instead of forwarding it
+    pub info_extension: bool,
+    /// Answer the why-denied extension instead of forwarding it
+    pub why_denied_extension: bool,
+    /// Extra attempts for a failed identity listing
+    pub list_retries: u32,
+    /// Kill-switch file freezing the agent while present
+    pub kill_switch: Option<KillSwitch>,
+    /// Check backend signatures before returning them
+    pub verify_signatures: bool,
+    /// Order of listed identities
+    #[serde(serialize_with = "display")]
+    pub identity_order: IdentityOrder,
+    /// Public-only identities listed besides the backend's
+    pub fake_identities: FakeIdentities,
+    /// Keys added without constraints whose signatures the proxy confirms itself
+    pub sign_confirmations: SignConfirmations,
+    /// Constraint added to every key
+    #[serde(serialize_with = "display")]
+    pub enforced_constraint: EnforcedConstraint,
+    /// Deny signing data that looks like another protocol's structure
+    pub reject_foreign_sign: bool,
+    /// Confirm lock and unlock requests through SSH_ASKPASS
+    pub confirm_lock_unlock: bool,
+    /// Confirm key removals through SSH_ASKPASS
+    pub confirm_removals: bool,
+    /// Deny every unlock request
+    pub block_unlock: bool,
+    /// Lifetime in seconds for keys added without one
+    pub default_lifetime: Option<u32>,
+    /// Seconds after startup during which keys may be added
+    pub add_window: Option<u64>,
+    /// Refuse signing while the screen is locked
+    pub deny_when_locked: bool,
+    /// Refuse signing on battery below this percentage
+    pub deny_on_battery_below: Option<u8>,
+    /// Keys replaced by a new key with the same comment
+    pub key_rotations: Rotations,
+    /// Minimum time in milliseconds before a denial is answered, if set
+    pub constant_time_denies_ms: Option<u64>,
+    /// Handling of signatures on forwarded connections
+    pub on_forwarded: OnForwarded,
+    /// Host keys a connection must be bound to before it may sign, if non-empty
+    #[serde(serialize_with = "display_all")]
+    pub allowed_hosts: Vec<Fingerprint>,
+    /// Host keys a connection may be bound to, if non-empty
+    #[serde(serialize_with = "display_all")]
+    pub allowed_bind_hosts: Vec<Fingerprint>,
+    /// Client UIDs each listed key is visible and usable for
+    pub key_users: Vec<KeyUsers>,
+    /// Client UIDs FIDO security keys are visible and usable for, if restricted
+    pub sk_key_users: Option<Vec<u32>>,
+    /// Keys that need a one-time code for every signature
+    pub key_totps: Totps,
+    /// Keys that need confirming for every signature, and what to do when that cannot be asked
+    pub key_confirms: Vec<KeyConfirm>,
+    /// Client UIDs each listed key signs for without the proxy's own confirmation
+    pub confirm_bypass: Vec<KeyUsers>,
+    /// Signature algorithms each listed key may produce
+    pub key_signature_algorithms: Vec<KeySignatureAlgorithms>,
+    /// Prefixes the data each listed key signs must start with
+    pub key_sign_prefixes: Vec<KeySignPrefix>,
+    /// Per-key baselines of sign flags, and what to do on a deviation
+    pub flag_profiles: FlagProfiles,
+    /// Extensions the backend supports, if probed
+    pub backend_extensions: BackendExtensions,
+    /// Recently signed payloads, refused again within the window
+    pub replay_guard: ReplayGuard,
+    /// Per-key signing rate limits
+    pub key_rate_limits: RateLimiter,
+    /// Per-key signing quotas per day or week
+    pub key_quotas: Quotas,
+    /// Substring a key's comment must contain for it to sign
+    pub required_comment: Option<String>,
+    /// Program rewriting the comments of added keys
+    pub comment_filter: Option<CommentFilter>,
+    /// Script deciding requests that passed the built-in checks
+    #[cfg(feature = "policy-script")]
+    pub script: Option<PolicyScript>,
+    /// Minimum RSA modulus size on add; DSA keys are refused when set
+    pub min_rsa_bits: Option<u32>,
+    /// Key types accepted on add, if non-empty
+    #[serde(serialize_with = "display_all")]
+    pub allowed_key_types: Vec<Algorithm>,
+    /// Constraint extensions a client may add keys with, if non-empty
+    pub allowed_constraint_extensions: Vec<String>,
+    /// Largest constraint extension payload accepted on add
+    pub max_constraint_extension_bytes: Option<usize>,
+    /// Largest extension request payload the proxy accepts
+    pub max_extension_bytes: Option<usize>,
+    /// Keys that may be added or used at all, if set
+    pub allowed_keys: Option<AllowedKeys>,
+}
+
+/// Policy of a proxy started without options: keys are added with confirm, nothing else is checked
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            info_extension: false,
+            why_denied_extension: false,
+            list_retries: 0,
+            kill_switch: None,
+            verify_signatures: false,
+            identity_order: IdentityOrder::default(),
+            fake_identities: FakeIdentities::new(Vec::new(), FakeSignResponse::default()),
+            sign_confirmations: SignConfirmations::new(false),
+            enforced_constraint: EnforcedConstraint::default(),
+            reject_foreign_sign: false,
+            confirm_lock_unlock: false,
+            confirm_removals: false,
+            block_unlock: false,
+            default_lifetime: None,
+            add_window: None,
+            deny_when_locked: false,
+            deny_on_battery_below: None,
+            key_rotations: Rotations::new(false),
+            constant_time_denies_ms: None,
+            on_forwarded: OnForwarded::Warn,
+            allowed_hosts: Vec::new(),
+            allowed_bind_hosts: Vec::new(),
+            key_users: Vec::new(),
+            sk_key_users: None,
+            key_totps: Totps::new(Vec::new()),
+            key_confirms: Vec::new(),
+            confirm_bypass: Vec::new(),
+            key_signature_algorithms: Vec::new(),
+            key_sign_prefixes: Vec::new(),
+            flag_profiles: FlagProfiles::new(None, 10),
+            backend_extensions: BackendExtensions::new(false),
+            replay_guard: ReplayGuard::new(None),
+            key_rate_limits: RateLimiter::new(Vec::new()),
+            key_quotas: Quotas::new(Vec::new(), None),
+            required_comment: None,
+            comment_filter: None,
+            #[cfg(feature = "policy-script")]
+            script: None,
+            min_rsa_bits: None,
+            allowed_key_types: Vec::new(),
+            allowed_constraint_extensions: Vec::new(),
+            max_constraint_extension_bytes: None,
+            max_extension_bytes: None,
+            allowed_keys: None,
+        }
+    }
+}
+
+impl Policy {
+    /// The policy's JSON serialization, the same for the same effective policy
+    pub fn canonical_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("policy serializes to JSON")
+    }
+
+    /// Hex-encoded SHA-256 of the policy's JSON serialization
+    pub fn hash(&self) -> String {
+        hex_sha256(&self.canonical_json())
+    }
+}
+
+/// Hex-encoded SHA-256 of `data`
+pub fn hex_sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Serialize a value through its `Display` form
+pub fn display<T: Display, S: Serializer>(item: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(item)
+}
+
+/// Serialize a list of values through their `Display` form
+pub fn display_all<T: Display, S: Serializer>(
+    items: &[T],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(items.iter().map(ToString::to_string))
+}
+
+/// Serialize a duration as whole seconds
+pub fn secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guarded(min_rsa_bits: Option<u32>) -> Policy {
+        Policy {
+            min_rsa_bits,
+            // Its HMAC key is drawn at random, but is not part of the policy
+            replay_guard: ReplayGuard::new(Some(60)),
+            ..Policy::default()
+        }
+    }
+
+    #[test]
+    fn hash_is_stable_for_the_same_policy() {
+        let hash = guarded(Some(3072)).hash();
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(guarded(Some(3072)).hash(), hash);
+        assert_eq!(Policy::default().hash(), Policy::default().hash());
+    }
+
+    #[test]
+    fn hash_changes_with_the_policy() {
+        let hash = guarded(Some(3072)).hash();
+        assert_ne!(guarded(Some(4096)).hash(), hash);
+        assert_ne!(guarded(None).hash(), hash);
+        assert_ne!(Policy::default().hash(), guarded(None).hash());
+    }
+}
@@ -0,0 +1,277 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::state::Store;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Section of the state store holding the counters
+pub const STATE_SECTION: &str = "quotas";
+
+/// Calendar window after which a quota starts over, in UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotaPeriod {
+    /// Resets at midnight
+    Day,
+    /// Resets at midnight between Sunday and Monday
+    Week,
+}
+
+impl QuotaPeriod {
+    /// Index of the window containing `secs` since the epoch, and the seconds left in it
+    fn window(self, secs: u64) -> (u64, u64) {
+        let (length, offset) = match self {
+            Self::Day => (DAY_SECS, 0),
+            // The epoch fell on a Thursday
+            Self::Week => (7 * DAY_SECS, 3 * DAY_SECS),
+        };
+        let shifted = secs + offset;
+        (shifted / length, length - shifted % length)
+    }
+}
+
+/// At most `count` signatures per `period` for one key
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyQuota {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    count: u32,
+    period: QuotaPeriod,
+}
+
+impl FromStr for KeyQuota {
+    type Err = String;
+
+    /// Parse `<FINGERPRINT>=<COUNT>/day` or `<FINGERPRINT>=<COUNT>/week`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fingerprint, quota) = s
+            .rsplit_once('=')
+            .ok_or("expected <FINGERPRINT>=<COUNT>/<day|week>")?;
+        let (count, period) = quota
+            .split_once('/')
+            .ok_or("expected <COUNT>/<day|week> after '='")?;
+
+        let fingerprint = fingerprint.parse().map_err(|e| format!("{e}"))?;
+        let count: u32 = count.parse().map_err(|e| format!("count: {e}"))?;
+        let period = match period {
+            "day" => QuotaPeriod::Day,
+            "week" => QuotaPeriod::Week,
+            _ => return Err("period must be day or week".into()),
+        };
+        if count == 0 {
+            return Err("count must be positive".into());
+        }
+
+        Ok(Self {
+            fingerprint,
+            count,
+            period,
+        })
+    }
+}
+
+/// Signatures made with one key in its current window
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct Usage {
+    window: u64,
+    used: u32,
+}
+
+/// Usage counters for keys with a quota, optionally persisted across restarts
+#[derive(Debug, Serialize)]
+pub struct Quotas {
+    limits: Vec<KeyQuota>,
+    #[serde(rename = "state_file", serialize_with = "crate::state::location")]
+    store: Option<Store>,
+    #[serde(skip)]
+    usage: Mutex<BTreeMap<Fingerprint, Usage>>,
+}
+
+impl Quotas {
+    /// Set up the quotas, resuming the counters saved in `store`
+    pub fn new(limits: Vec<KeyQuota>, store: Option<Store>) -> Self {
+        let usage = store
+            .as_ref()
+            .and_then(|store| store.get(STATE_SECTION))
+            .map(load)
+            .unwrap_or_default();
+
+        Self {
+            limits,
+            store,
+            usage: Mutex::new(usage),
+        }
+    }
+
+    /// Count a signature with `fingerprint`, or say how long until its exhausted quota resets
+    ///
+    /// The signature is counted under the counters' lock, so concurrent requests
+    /// cannot both use the last one.
+    pub fn check(&self, fingerprint: &Fingerprint) -> Result<(), Duration> {
+        self.check_at(fingerprint, now())
+    }
+
+    /// Give back the signature counted for a request the backend did not sign
+    pub fn release(&self, fingerprint: &Fingerprint) {
+        self.release_at(fingerprint, now());
+    }
+
+    fn check_at(&self, fingerprint: &Fingerprint, now: u64) -> Result<(), Duration> {
+        let Some(limit) = self.limit(fingerprint) else {
+            return Ok(());
+        };
+        let (window, remaining) = limit.period.window(now);
+
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = usage
+            .entry(*fingerprint)
+            .or_insert(Usage { window, used: 0 });
+        if entry.window != window {
+            *entry = Usage { window, used: 0 };
+        }
+        if entry.used >= limit.count {
+            return Err(Duration::from_secs(remaining));
+        }
+        entry.used += 1;
+        self.save(&usage);
+        Ok(())
+    }
+
+    fn release_at(&self, fingerprint: &Fingerprint, now: u64) {
+        let Some(limit) = self.limit(fingerprint) else {
+            return;
+        };
+        let (window, _) = limit.period.window(now);
+
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        // A signature counted in an earlier window was already forgotten
+        if let Some(entry) = usage.get_mut(fingerprint)
+            && entry.window == window
+        {
+            entry.used = entry.used.saturating_sub(1);
+            self.save(&usage);
+        }
+    }
+
+    /// Persist the counters, if there is a store
+    fn save(&self, usage: &BTreeMap<Fingerprint, Usage>) {
+        if let Some(store) = &self.store {
+            let state: BTreeMap<String, Usage> = usage
+                .iter()
+                .map(|(fingerprint, usage)| (fingerprint.to_string(), *usage))
+                .collect();
+            let json = serde_json::to_value(state).expect("quota state serializes to JSON");
+            store.set(STATE_SECTION, json);
+        }
+    }
+
+    fn limit(&self, fingerprint: &Fingerprint) -> Option<&KeyQuota> {
+        self.limits.iter().find(|l| &l.fingerprint == fingerprint)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Parse saved counters, skipping entries whose fingerprint does not parse
+fn load(saved: serde_json::Value) -> BTreeMap<Fingerprint, Usage> {
+    let state: BTreeMap<String, Usage> = serde_json::from_value(saved).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable quota state: {e}");
+        BTreeMap::new()
+    });
+    state
+        .into_iter()
+        .filter_map(|(fingerprint, usage)| Some((fingerprint.parse().ok()?, usage)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+    /// Monday 2024-01-01 00:00:00 UTC
+    const MONDAY: u64 = 1_704_067_200;
+
+    fn quotas(quota: &str) -> (Quotas, Fingerprint) {
+        let quota = format!("{KEY}={quota}").parse().unwrap();
+        (Quotas::new(vec![quota], None), KEY.parse().unwrap())
+    }
+
+    #[test]
+    fn exhausts_after_count_signatures() {
+        let (quotas, key) = quotas("2/day");
+        let noon = MONDAY + DAY_SECS / 2;
+        assert_eq!(quotas.check_at(&key, noon), Ok(()));
+        assert_eq!(quotas.check_at(&key, noon), Ok(()));
+        let reset = Duration::from_secs(DAY_SECS / 2);
+        assert_eq!(quotas.check_at(&key, noon), Err(reset));
+    }
+
+    #[test]
+    fn released_signatures_can_be_used_again() {
+        let (quotas, key) = quotas("1/day");
+        assert_eq!(quotas.check_at(&key, MONDAY), Ok(()));
+        quotas.release_at(&key, MONDAY);
+        assert_eq!(quotas.check_at(&key, MONDAY), Ok(()));
+        assert!(quotas.check_at(&key, MONDAY).is_err());
+    }
+
+    #[test]
+    fn releases_leave_the_next_window_alone() {
+        let (quotas, key) = quotas("1/day");
+        assert_eq!(quotas.check_at(&key, MONDAY + DAY_SECS - 1), Ok(()));
+        assert_eq!(quotas.check_at(&key, MONDAY + DAY_SECS), Ok(()));
+        quotas.release_at(&key, MONDAY + DAY_SECS);
+        quotas.release_at(&key, MONDAY + DAY_SECS);
+        assert_eq!(quotas.check_at(&key, MONDAY + DAY_SECS), Ok(()));
+        assert!(quotas.check_at(&key, MONDAY + DAY_SECS).is_err());
+    }
+
+    #[test]
+    fn daily_quota_resets_at_midnight() {
+        let (quotas, key) = quotas("1/day");
+        let last_second = MONDAY + DAY_SECS - 1;
+        assert_eq!(quotas.check_at(&key, last_second), Ok(()));
+        assert_eq!(
+            quotas.check_at(&key, last_second),
+            Err(Duration::from_secs(1))
+        );
+        assert_eq!(quotas.check_at(&key, MONDAY + DAY_SECS), Ok(()));
+    }
+
+    #[test]
+    fn weekly_quota_resets_on_monday() {
+        let (quotas, key) = quotas("1/week");
+        let sunday_night = MONDAY + 7 * DAY_SECS - 1;
+        assert_eq!(quotas.check_at(&key, MONDAY), Ok(()));
+        assert!(quotas.check_at(&key, sunday_night).is_err());
+        assert_eq!(quotas.check_at(&key, sunday_night + 1), Ok(()));
+    }
+
+    #[test]
+    fn windows_start_at_utc_midnight_and_monday() {
+        assert_eq!(QuotaPeriod::Day.window(MONDAY).1, DAY_SECS);
+        assert_eq!(QuotaPeriod::Week.window(MONDAY).1, 7 * DAY_SECS);
+        let (week, _) = QuotaPeriod::Week.window(MONDAY - 1);
+        assert_eq!(QuotaPeriod::Week.window(MONDAY).0, week + 1);
+    }
+
+    #[test]
+    fn parses_quotas() {
+        assert!(format!("{KEY}=0/day").parse::<KeyQuota>().is_err());
+        assert!(format!("{KEY}=1/month").parse::<KeyQuota>().is_err());
+        let quota: KeyQuota = format!("{KEY}=5/week").parse().unwrap();
+        assert_eq!((quota.count, quota.period), (5, QuotaPeriod::Week));
+    }
+}
@@ -0,0 +1,180 @@
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// At most `count` signatures per `period` for one key
+#[derive(Clone, Debug, Serialize)]
+pub struct KeyRateLimit {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    count: u32,
+    #[serde(rename = "period_secs", serialize_with = "crate::policy::secs")]
+    period: Duration,
+}
+
+impl FromStr for KeyRateLimit {
+    type Err = String;
+
+    /// Parse `<FINGERPRINT>=<COUNT>/<SECONDS>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fingerprint, rate) = s
+            .rsplit_once('=')
+            .ok_or("expected <FINGERPRINT>=<COUNT>/<SECONDS>")?;
+        let (count, period) = rate
+            .split_once('/')
+            .ok_or("expected <COUNT>/<SECONDS> after '='")?;
+
+        let fingerprint = fingerprint.parse().map_err(|e| format!("{e}"))?;
+        let count: u32 = count.parse().map_err(|e| format!("count: {e}"))?;
+        let period: u64 = period.parse().map_err(|e| format!("seconds: {e}"))?;
+        if count == 0 || period == 0 {
+            return Err("count and seconds must be positive".into());
+        }
+
+        Ok(Self {
+            fingerprint,
+            count,
+            period: Duration::from_secs(period),
+        })
+    }
+}
+
+/// Token buckets for keys with their own rate limit
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct RateLimiter {
+    limits: Vec<KeyRateLimit>,
+    #[serde(skip)]
+    buckets: Mutex<BTreeMap<Fingerprint, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Vec<KeyRateLimit>) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Take a token for a signature with `fingerprint`, or return false if none is left
+    ///
+    /// The token is taken under the bucket's lock, so concurrent requests cannot
+    /// both get the last one. Keys without a limit are never throttled.
+    pub fn allows(&self, fingerprint: &Fingerprint) -> bool {
+        self.allows_at(fingerprint, Instant::now())
+    }
+
+    /// Give back the token taken for a request the backend did not sign
+    pub fn refund(&self, fingerprint: &Fingerprint) {
+        self.refund_at(fingerprint, Instant::now());
+    }
+
+    fn allows_at(&self, fingerprint: &Fingerprint, now: Instant) -> bool {
+        let mut taken = false;
+        let limited = self.refill(fingerprint, now, |tokens| {
+            if *tokens >= 1.0 {
+                *tokens -= 1.0;
+                taken = true;
+            }
+        });
+        limited.is_none() || taken
+    }
+
+    fn refund_at(&self, fingerprint: &Fingerprint, now: Instant) {
+        self.refill(fingerprint, now, |tokens| *tokens += 1.0);
+    }
+
+    /// Refill the bucket of `fingerprint` up to `now` and apply `update` to its tokens,
+    /// returning what is left, or `None` if the key has no limit
+    fn refill(
+        &self,
+        fingerprint: &Fingerprint,
+        now: Instant,
+        update: impl FnOnce(&mut f64),
+    ) -> Option<f64> {
+        let limit = self.limits.iter().find(|l| &l.fingerprint == fingerprint)?;
+        let capacity = f64::from(limit.count);
+        let per_sec = capacity / limit.period.as_secs_f64();
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let (tokens, last) = buckets.entry(*fingerprint).or_insert((capacity, now));
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        *tokens = (*tokens + elapsed * per_sec).min(capacity);
+        *last = now;
+        update(tokens);
+        *tokens = tokens.min(capacity);
+        Some(*tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+
+    fn limiter(limit: &str) -> (RateLimiter, Fingerprint) {
+        let limit = format!("{KEY}={limit}").parse().unwrap();
+        (RateLimiter::new(vec![limit]), KEY.parse().unwrap())
+    }
+
+    #[test]
+    fn throttles_after_count_signatures() {
+        let (limiter, key) = limiter("2/60");
+        let now = Instant::now();
+        assert!(limiter.allows_at(&key, now));
+        assert!(limiter.allows_at(&key, now));
+        assert!(!limiter.allows_at(&key, now));
+    }
+
+    #[test]
+    fn refills_over_the_period() {
+        let (limiter, key) = limiter("2/60");
+        let now = Instant::now();
+        limiter.allows_at(&key, now);
+        limiter.allows_at(&key, now);
+        assert!(!limiter.allows_at(&key, now + Duration::from_secs(29)));
+        assert!(limiter.allows_at(&key, now + Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn refunded_tokens_can_be_taken_again() {
+        let (limiter, key) = limiter("1/60");
+        let now = Instant::now();
+        assert!(limiter.allows_at(&key, now));
+        limiter.refund_at(&key, now);
+        assert!(limiter.allows_at(&key, now));
+        assert!(!limiter.allows_at(&key, now));
+    }
+
+    #[test]
+    fn refunds_never_exceed_the_capacity() {
+        let (limiter, key) = limiter("1/60");
+        let now = Instant::now();
+        limiter.refund_at(&key, now);
+        limiter.refund_at(&key, now);
+        assert!(limiter.allows_at(&key, now));
+        assert!(!limiter.allows_at(&key, now));
+    }
+
+    #[test]
+    fn keys_without_a_limit_are_not_throttled() {
+        let (limiter, _) = limiter("1/60");
+        let other = "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU"
+            .parse()
+            .unwrap();
+        assert!(limiter.allows_at(&other, Instant::now()));
+        assert!(limiter.allows_at(&other, Instant::now()));
+    }
+
+    #[test]
+    fn parses_limits() {
+        assert!(format!("{KEY}=0/60").parse::<KeyRateLimit>().is_err());
+        assert!(format!("{KEY}=1").parse::<KeyRateLimit>().is_err());
+        let limit: KeyRateLimit = format!("{KEY}=3/10").parse().unwrap();
+        assert_eq!((limit.count, limit.period), (3, Duration::from_secs(10)));
+    }
+}
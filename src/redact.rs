@@ -0,0 +1,119 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Directories whose next component names a user, and what replaces that component
+const USER_DIRS: &[(&str, &str)] = &[
+    ("/home/", "<user>"),
+    ("/Users/", "<user>"),
+    ("\\Users\\", "<user>"),
+    ("/run/user/", "<uid>"),
+];
+
+/// Whether `--redact-paths` is on, for output written outside the logger
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Redact paths in the lines printed with [`eprintln`] from now on
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Print `line` to stderr, with its paths redacted if [`enable`] was called
+///
+/// For the protocol traces, which bypass the logger to keep their own format.
+pub fn eprintln(line: &str) {
+    eprintln!("{}", stderr_line(line));
+}
+
+fn stderr_line(line: &str) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        paths(line)
+    } else {
+        line.to_string()
+    }
+}
+
+/// Replace user names and UIDs in the paths within `text` by placeholders
+///
+/// `/home/alice/.ssh/id_ed25519` becomes `/home/<user>/.ssh/id_ed25519`, and a
+/// home directory outside the usual places (e.g. `/root`) becomes `~`, so paths
+/// stay recognizable without telling whose they are.
+pub fn paths(text: &str) -> String {
+    let mut text = text.to_string();
+    if let Some(home) = std::env::var_os("HOME")
+        && let Some(home) = home.to_str()
+        && home.len() > 1
+        && !USER_DIRS.iter().any(|(dir, _)| home.starts_with(dir))
+    {
+        text = text.replace(&format!("{home}/"), "~/");
+    }
+
+    for (dir, placeholder) in USER_DIRS {
+        let mut redacted = String::with_capacity(text.len());
+        let mut rest = &text[..];
+        while let Some(at) = rest.find(dir) {
+            let (before, after) = rest.split_at(at + dir.len());
+            redacted.push_str(before);
+            let end = after
+                .find(|c: char| matches!(c, '/' | '\\' | '"' | '\'' | ':') || c.is_whitespace())
+                .unwrap_or(after.len());
+            if end > 0 {
+                redacted.push_str(placeholder);
+            }
+            rest = &after[end..];
+        }
+        redacted.push_str(rest);
+        text = redacted;
+    }
+    text
+}
+
+/// Log line format for env_logger that redacts paths in each message
+pub fn format(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+    let style = buf.default_level_style(record.level());
+    writeln!(
+        buf,
+        "[{} {style}{:<5}{style:#} {}] {}",
+        buf.timestamp(),
+        record.level(),
+        record.target(),
+        paths(&record.args().to_string())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_user_names_in_paths() {
+        assert_eq!(
+            paths("key /home/alice/.ssh/id_ed25519 added"),
+            "key /home/<user>/.ssh/id_ed25519 added"
+        );
+        assert_eq!(paths("/Users/bob"), "/Users/<user>");
+        assert_eq!(paths(r"C:\Users\carol\.ssh"), r"C:\Users\<user>\.ssh");
+        assert_eq!(
+            paths("socket \"/run/user/1000/agent.sock\""),
+            "socket \"/run/user/<uid>/agent.sock\""
+        );
+        assert_eq!(paths("/home/ is empty"), "/home/ is empty");
+    }
+
+    #[test]
+    fn replaces_every_occurrence() {
+        assert_eq!(
+            paths("/home/a/x -> /home/b/y"),
+            "/home/<user>/x -> /home/<user>/y"
+        );
+    }
+
+    #[test]
+    fn stderr_lines_are_redacted_once_enabled() {
+        let line = r#"{"request":{"path":"/home/alice/.ssh/id_ed25519"}}"#;
+        enable();
+        assert_eq!(
+            stderr_line(line),
+            r#"{"request":{"path":"/home/<user>/.ssh/id_ed25519"}}"#
+        );
+    }
+}
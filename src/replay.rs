@@ -0,0 +1,155 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use ssh_agent_lib::ssh_key::rand_core::{OsRng, RngCore};
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Most payloads remembered at once; the oldest are forgotten first
+const CAPACITY: usize = 4096;
+
+/// Identifies one (key, payload) pair without keeping the payload
+pub type Digest = [u8; 32];
+
+/// Payloads signed within the last window, for refusing identical sign requests as replays
+///
+/// Payloads are remembered by an HMAC under a key drawn at startup, so the
+/// proxy's memory never holds what was signed.
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct ReplayGuard {
+    window_secs: Option<u64>,
+    #[serde(skip)]
+    key: [u8; 32],
+    #[serde(skip)]
+    recent: Mutex<VecDeque<(Digest, Instant)>>,
+}
+
+impl ReplayGuard {
+    pub fn new(window_secs: Option<u64>) -> Self {
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            window_secs,
+            key,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Digest of signing `data` with `fingerprint`, or `None` if the guard is off
+    pub fn digest(&self, fingerprint: &Fingerprint, data: &[u8]) -> Option<Digest> {
+        self.window_secs?;
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(fingerprint.as_bytes());
+        mac.update(data);
+        Some(mac.finalize().into_bytes().into())
+    }
+
+    /// Whether the payload was signed within the window; if not, it is remembered as signed now
+    ///
+    /// Checked and remembered under one lock, so of identical requests sent at
+    /// once only the first passes. [`forget`](Self::forget) it if it does not get signed.
+    pub fn replayed(&self, digest: Option<Digest>) -> bool {
+        let Some(digest) = digest else {
+            return false;
+        };
+        let mut recent = self.recent();
+        if recent.iter().any(|(seen, _)| *seen == digest) {
+            return true;
+        }
+        if recent.len() == CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back((digest, Instant::now()));
+        false
+    }
+
+    /// Forget a payload `replayed` remembered, because it was not signed after all
+    pub fn forget(&self, digest: Option<Digest>) {
+        let Some(digest) = digest else {
+            return;
+        };
+        let mut recent = self.recent();
+        if let Some(i) = recent.iter().rposition(|(seen, _)| *seen == digest) {
+            recent.remove(i);
+        }
+    }
+
+    /// The remembered payloads, without those signed before the window
+    fn recent(&self) -> MutexGuard<'_, VecDeque<(Digest, Instant)>> {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        let window = Duration::from_secs(self.window_secs.unwrap_or_default());
+        while recent.front().is_some_and(|(_, at)| at.elapsed() >= window) {
+            recent.pop_front();
+        }
+        recent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+    const OTHER: &str = "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU";
+
+    #[test]
+    fn refuses_data_signed_within_the_window() {
+        let guard = ReplayGuard::new(Some(60));
+        let key = KEY.parse().unwrap();
+        assert!(!guard.replayed(guard.digest(&key, b"data")));
+        assert!(guard.replayed(guard.digest(&key, b"data")));
+        assert!(!guard.replayed(guard.digest(&key, b"other data")));
+        assert!(!guard.replayed(guard.digest(&OTHER.parse().unwrap(), b"data")));
+    }
+
+    #[test]
+    fn forgotten_payloads_pass_again() {
+        let guard = ReplayGuard::new(Some(60));
+        let key = KEY.parse().unwrap();
+        let digest = guard.digest(&key, b"data");
+        assert!(!guard.replayed(digest));
+        guard.forget(digest);
+        assert!(!guard.replayed(digest));
+        assert!(guard.replayed(digest));
+    }
+
+    #[test]
+    fn forgets_payloads_after_the_window() {
+        let guard = ReplayGuard::new(Some(0));
+        let key = KEY.parse().unwrap();
+        assert!(!guard.replayed(guard.digest(&key, b"data")));
+        assert!(!guard.replayed(guard.digest(&key, b"data")));
+    }
+
+    #[test]
+    fn forgets_the_oldest_payload_beyond_capacity() {
+        let guard = ReplayGuard::new(Some(60));
+        let key = KEY.parse().unwrap();
+        for i in 0..=CAPACITY {
+            assert!(!guard.replayed(guard.digest(&key, &i.to_be_bytes())));
+        }
+        assert!(guard.replayed(guard.digest(&key, &CAPACITY.to_be_bytes())));
+        assert!(!guard.replayed(guard.digest(&key, &0usize.to_be_bytes())));
+    }
+
+    #[test]
+    fn is_off_without_a_window() {
+        let guard = ReplayGuard::new(None);
+        let key = KEY.parse().unwrap();
+        assert_eq!(guard.digest(&key, b"data"), None);
+        assert!(!guard.replayed(guard.digest(&key, b"data")));
+        assert!(!guard.replayed(guard.digest(&key, b"data")));
+    }
+
+    #[test]
+    fn digests_differ_between_guards() {
+        let key = KEY.parse().unwrap();
+        let first = ReplayGuard::new(Some(60)).digest(&key, b"data");
+        let second = ReplayGuard::new(Some(60)).digest(&key, b"data");
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,49 @@
+use ssh_agent_lib::proto::Request;
+use ssh_agent_lib::ssh_key::public::KeyData;
+
+use crate::keys::credential_key;
+
+/// Every name [`request_name`] returns
+pub const REQUEST_NAMES: [&str; 12] = [
+    "request-identities",
+    "sign",
+    "add-identity",
+    "remove-identity",
+    "remove-all-identities",
+    "add-smartcard-key",
+    "remove-smartcard-key",
+    "lock",
+    "unlock",
+    "add-identity-constrained",
+    "add-smartcard-key-constrained",
+    "extension",
+];
+
+/// Short kebab-case name of a request type
+pub fn request_name(request: &Request) -> &'static str {
+    match request {
+        Request::RequestIdentities => "request-identities",
+        Request::SignRequest(_) => "sign",
+        Request::AddIdentity(_) => "add-identity",
+        Request::RemoveIdentity(_) => "remove-identity",
+        Request::RemoveAllIdentities => "remove-all-identities",
+        Request::AddSmartcardKey(_) => "add-smartcard-key",
+        Request::RemoveSmartcardKey(_) => "remove-smartcard-key",
+        Request::Lock(_) => "lock",
+        Request::Unlock(_) => "unlock",
+        Request::AddIdConstrained(_) => "add-identity-constrained",
+        Request::AddSmartcardKeyConstrained(_) => "add-smartcard-key-constrained",
+        Request::Extension(_) => "extension",
+    }
+}
+
+/// Public key a request operates on, if any
+pub fn request_key(request: &Request) -> Option<KeyData> {
+    match request {
+        Request::SignRequest(sign) => Some(sign.pubkey.clone()),
+        Request::RemoveIdentity(remove) => Some(remove.pubkey.clone()),
+        Request::AddIdentity(add) => credential_key(&add.credential),
+        Request::AddIdConstrained(add) => credential_key(&add.identity.credential),
+        _ => None,
+    }
+}
@@ -0,0 +1,76 @@
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::HashAlg;
+use ssh_agent_lib::ssh_key::public::KeyData;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Detects keys replaced by a new key under the same comment
+#[derive(Debug, Serialize)]
+pub struct Rotations {
+    /// Remove the replaced key from the backend
+    remove_old: bool,
+    #[serde(skip)]
+    by_comment: Mutex<BTreeMap<String, KeyData>>,
+}
+
+impl Rotations {
+    pub fn new(remove_old: bool) -> Self {
+        Self {
+            remove_old,
+            by_comment: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn remove_old(&self) -> bool {
+        self.remove_old
+    }
+
+    /// Record `key` as added with `comment`, returning the key it replaces, if any
+    ///
+    /// Keys without a comment are not tracked, as they cannot be matched up.
+    pub fn added(&self, comment: &str, key: &KeyData) -> Option<KeyData> {
+        if comment.is_empty() {
+            return None;
+        }
+        let mut by_comment = self.by_comment.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = by_comment.insert(comment.to_string(), key.clone())?;
+        (previous.fingerprint(HashAlg::Sha256) != key.fingerprint(HashAlg::Sha256))
+            .then_some(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey};
+
+    fn key() -> KeyData {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        key.public_key().key_data().clone()
+    }
+
+    #[test]
+    fn a_new_key_under_the_same_comment_is_a_rotation() {
+        let rotations = Rotations::new(false);
+        let (old, new) = (key(), key());
+        assert_eq!(rotations.added("work", &old), None);
+        assert_eq!(rotations.added("work", &new), Some(old));
+        assert_eq!(rotations.added("home", &key()), None);
+    }
+
+    #[test]
+    fn re_adding_the_same_key_is_not_a_rotation() {
+        let rotations = Rotations::new(false);
+        let key = key();
+        rotations.added("work", &key);
+        assert_eq!(rotations.added("work", &key), None);
+    }
+
+    #[test]
+    fn keys_without_a_comment_are_not_tracked() {
+        let rotations = Rotations::new(false);
+        rotations.added("", &key());
+        assert_eq!(rotations.added("", &key()), None);
+    }
+}
@@ -0,0 +1,227 @@
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+use serde::Serialize;
+use ssh_agent_lib::proto::Request;
+use ssh_agent_lib::ssh_key::HashAlg;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::client::ClientInfo;
+use crate::policy::hex_sha256;
+use crate::request::{request_key, request_name};
+
+/// Operations a single evaluation may perform before it is aborted
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// What a policy script decided for a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    /// Allow only after the user approves through SSH_ASKPASS
+    Confirm,
+}
+
+/// Rhai script deciding requests that passed the built-in checks
+///
+/// The script sees a `request` map with `type`, `fingerprint`, `comment`,
+/// `uid`, `pid`, `time` (Unix seconds), `hour` and `weekday` (UTC, Monday is
+/// 0); fields that do not apply are `()`. It must evaluate to `"allow"`,
+/// `"deny"` or `"confirm"`. Scripts cannot import modules or touch the
+/// filesystem, and are aborted after a fixed number of operations.
+#[derive(Serialize)]
+pub struct PolicyScript {
+    path: PathBuf,
+    sha256: String,
+    #[serde(skip)]
+    engine: Engine,
+    #[serde(skip)]
+    ast: AST,
+}
+
+impl fmt::Debug for PolicyScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyScript")
+            .field("path", &self.path)
+            .field("sha256", &self.sha256)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PolicyScript {
+    pub fn load(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let source = std::fs::read_to_string(&path)?;
+
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(64 * 1024);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(10_000);
+        engine.on_print(|text| log::info!("Policy script: {text}"));
+        engine.on_debug(|text, _, _| log::debug!("Policy script: {text}"));
+
+        let ast = engine
+            .compile(&source)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+
+        Ok(Self {
+            path,
+            sha256: hex_sha256(source.as_bytes()),
+            engine,
+            ast,
+        })
+    }
+
+    /// Evaluate the script for `request`; `comment` is that of the key involved, if known
+    pub fn decide(
+        &self,
+        request: &Request,
+        comment: Option<&str>,
+        client: &ClientInfo,
+    ) -> Result<Decision, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.decide_at(request, comment, client, now)
+    }
+
+    /// Evaluate the script as of `now`, in seconds since the epoch
+    fn decide_at(
+        &self,
+        request: &Request,
+        comment: Option<&str>,
+        client: &ClientInfo,
+        now: u64,
+    ) -> Result<Decision, String> {
+        let days = now / 86_400;
+
+        let fingerprint = request_key(request).map(|key| key.fingerprint(HashAlg::Sha256));
+
+        let mut descriptor = Map::new();
+        descriptor.insert("type".into(), request_name(request).into());
+        descriptor.insert(
+            "fingerprint".into(),
+            optional(fingerprint.map(|fp| fp.to_string())),
+        );
+        descriptor.insert("comment".into(), optional(comment.map(str::to_string)));
+        descriptor.insert("uid".into(), optional(client.uid.map(i64::from)));
+        descriptor.insert("pid".into(), optional(client.pid.map(i64::from)));
+        descriptor.insert("time".into(), Dynamic::from_int(now as i64));
+        descriptor.insert(
+            "hour".into(),
+            Dynamic::from_int((now % 86_400 / 3600) as i64),
+        );
+        // The epoch fell on a Thursday
+        descriptor.insert("weekday".into(), Dynamic::from_int(((days + 3) % 7) as i64));
+
+        let mut scope = Scope::new();
+        scope.push_constant("request", descriptor);
+
+        let decision = self
+            .engine
+            .eval_ast_with_scope::<String>(&mut scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+        match decision.as_str() {
+            "allow" => Ok(Decision::Allow),
+            "deny" => Ok(Decision::Deny),
+            "confirm" => Ok(Decision::Confirm),
+            other => Err(format!("unknown decision {other:?}")),
+        }
+    }
+}
+
+fn optional<T: Into<Dynamic>>(value: Option<T>) -> Dynamic {
+    value.map_or(Dynamic::UNIT, Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::proto::SignRequest;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, PrivateKey};
+
+    /// Monday 2024-01-01 00:00:00 UTC
+    const MONDAY: u64 = 1_704_067_200;
+
+    fn script(name: &str, source: &str) -> PolicyScript {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}.rhai", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let script = PolicyScript::load(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        script
+    }
+
+    fn sign_request(key: &PrivateKey) -> Request {
+        Request::SignRequest(SignRequest {
+            pubkey: key.public_key().key_data().clone(),
+            data: b"data".to_vec(),
+            flags: 0,
+        })
+    }
+
+    #[test]
+    fn denies_a_key_after_hours() {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        let source = format!(
+            r#"if request.fingerprint == "{fingerprint}" && (request.hour < 9 || request.hour >= 17) {{
+                "deny"
+            }} else {{
+                "allow"
+            }}"#
+        );
+        let script = script("after-hours", &source);
+        let other = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let client = ClientInfo::default();
+        let decide = |key, hour: u64| {
+            script.decide_at(&sign_request(key), None, &client, MONDAY + hour * 3600)
+        };
+
+        assert_eq!(decide(&key, 12), Ok(Decision::Allow));
+        assert_eq!(decide(&key, 20), Ok(Decision::Deny));
+        assert_eq!(decide(&key, 3), Ok(Decision::Deny));
+        assert_eq!(decide(&other, 20), Ok(Decision::Allow));
+    }
+
+    #[test]
+    fn sees_the_request_descriptor() {
+        let script = script(
+            "descriptor",
+            r#"if request.type == "sign" && request.comment == "work" && request.uid == 1000 && request.weekday == 0 { "confirm" } else { "deny" }"#,
+        );
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let client = ClientInfo {
+            pid: None,
+            uid: Some(1000),
+            cmdline: None,
+        };
+        let decision = script.decide_at(&sign_request(&key), Some("work"), &client, MONDAY);
+        assert_eq!(decision, Ok(Decision::Confirm));
+        let decision = script.decide_at(&sign_request(&key), Some("home"), &client, MONDAY);
+        assert_eq!(decision, Ok(Decision::Deny));
+    }
+
+    #[test]
+    fn rejects_unknown_decisions_and_runaway_scripts() {
+        let client = ClientInfo::default();
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let maybe = script("maybe", r#""maybe""#);
+        assert!(
+            maybe
+                .decide_at(&sign_request(&key), None, &client, MONDAY)
+                .is_err()
+        );
+        let endless = script("endless", "loop {}");
+        assert!(
+            endless
+                .decide_at(&sign_request(&key), None, &client, MONDAY)
+                .is_err()
+        );
+    }
+}
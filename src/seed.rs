@@ -0,0 +1,141 @@
+use log::{info, warn};
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::proto::{AddIdentity, Credential, Request, Response};
+use ssh_agent_lib::ssh_key::PrivateKey;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Load each key file through `session`, so the usual add-time policy applies
+///
+/// Unencrypted keys are parsed and added in-process. Encrypted keys are handed
+/// to `ssh-add` against the proxy socket at `socket`, which prompts for the
+/// passphrase on the terminal or through SSH_ASKPASS. A file that fails to load
+/// is logged and skipped.
+pub async fn add_key_files(session: &mut impl Session, paths: &[PathBuf], socket: &Path) {
+    for path in paths {
+        let key = match PrivateKey::read_openssh_file(path) {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("Failed to read key {}: {e}", path.display());
+                continue;
+            }
+        };
+
+        let added = if key.is_encrypted() {
+            ssh_add(path, socket).await
+        } else {
+            let request = Request::AddIdentity(AddIdentity {
+                credential: Credential::Key {
+                    privkey: key.key_data().clone(),
+                    comment: key.comment().to_string(),
+                },
+            });
+            match session.handle(request).await {
+                Ok(Response::Success) => true,
+                Ok(_) => false,
+                Err(e) => {
+                    warn!("Failed to add key {}: {e}", path.display());
+                    continue;
+                }
+            }
+        };
+
+        if added {
+            info!("Added key {}", path.display());
+        } else {
+            warn!("Key {} was not added", path.display());
+        }
+    }
+}
+
+async fn ssh_add(path: &Path, socket: &Path) -> bool {
+    let status = Command::new("ssh-add")
+        .arg(path)
+        .env("SSH_AUTH_SOCK", socket)
+        .stdout(Stdio::null())
+        .status()
+        .await;
+
+    match status {
+        Ok(status) => status.success(),
+        Err(e) => {
+            warn!("Failed to run ssh-add for {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Proxy;
+    use crate::client::ClientInfo;
+    use crate::constraint::EnforcedConstraint;
+    use crate::memory::InProcessAgent;
+    use crate::policy::Policy;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{Algorithm, LineEnding};
+
+    fn proxy(agent: &InProcessAgent, enforced_constraint: EnforcedConstraint) -> Proxy {
+        let policy = Policy {
+            enforced_constraint,
+            ..Policy::default()
+        };
+        Proxy::in_process(agent.clone(), policy)
+    }
+
+    fn key_file(name: &str) -> (PathBuf, PrivateKey) {
+        let path = std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}", std::process::id()));
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        key.write_openssh_file(&path, LineEnding::LF).unwrap();
+        (path, key)
+    }
+
+    async fn seed(proxy: &Proxy, paths: &[PathBuf]) {
+        let client = ClientInfo::default();
+        let backend = proxy.connect_backend(&client).unwrap();
+        let mut session = proxy.session(backend, client);
+        add_key_files(&mut session, paths, Path::new("unused.sock")).await;
+    }
+
+    #[tokio::test]
+    async fn seeded_keys_get_the_enforced_confirm_constraint() {
+        let agent = InProcessAgent::default();
+        let (path, key) = key_file("seed-confirm");
+        seed(
+            &proxy(&agent, EnforcedConstraint::Confirm),
+            std::slice::from_ref(&path),
+        )
+        .await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(agent.confirms(key.public_key().key_data()), Some(true));
+    }
+
+    #[tokio::test]
+    async fn seeded_keys_are_unconstrained_without_an_enforced_constraint() {
+        let agent = InProcessAgent::default();
+        let (path, key) = key_file("seed-plain");
+        seed(
+            &proxy(&agent, EnforcedConstraint::None),
+            std::slice::from_ref(&path),
+        )
+        .await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(agent.confirms(key.public_key().key_data()), Some(false));
+    }
+
+    #[tokio::test]
+    async fn skips_files_that_fail_to_load() {
+        let agent = InProcessAgent::default();
+        let missing = std::env::temp_dir().join("ssh-agent-ac-seed-missing");
+        let (path, key) = key_file("seed-after-missing");
+        let paths = [missing, path.clone()];
+        seed(&proxy(&agent, EnforcedConstraint::Confirm), &paths).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(agent.confirms(key.public_key().key_data()), Some(true));
+    }
+}
@@ -0,0 +1,331 @@
+use futures::{SinkExt, TryStreamExt};
+use log::{debug, error, info, warn};
+use ssh_agent_lib::agent::{Agent, ListeningSocket, Session};
+use ssh_agent_lib::codec::Codec;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::{Request, Response};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio_util::codec::Framed;
+
+use crate::error::ProxyError;
+
+/// Pause after a failed accept, so running out of file descriptors does not spin
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accept connections on `socket` and serve each with a session from `agent`
+///
+/// Unlike `ssh_agent_lib::agent::listen`, the client stays watched while a
+/// request is handled: if it hangs up before the response, the handler is
+/// dropped, which cancels the pending backend call and kills any askpass
+/// prompt it is waiting on. With `idle`, a connection that sends no request
+/// for that long is closed, releasing its session and backend connection.
+///
+/// With `connections`, a connection is only accepted once it can hold a
+/// permit, which it keeps until it closes; until then, clients wait in the
+/// socket's listen backlog and no backend connection is opened for them.
+pub async fn listen<S>(
+    mut socket: S,
+    mut agent: impl Agent<S>,
+    idle: Option<Duration>,
+    connections: Option<Arc<Semaphore>>,
+) -> Result<(), ProxyError>
+where
+    S: ListeningSocket + fmt::Debug + Send,
+{
+    info!("Listening; socket = {socket:?}");
+    loop {
+        let permit = match &connections {
+            Some(connections) => connections.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        match socket.accept().await {
+            Ok(stream) => {
+                let session = agent.new_session(&stream);
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let adapter = Framed::new(stream, Codec::<Request, Response>::default());
+                    if let Err(e) = serve::<S>(session, adapter, idle).await {
+                        error!("Agent protocol error: {e:?}");
+                    }
+                });
+            }
+            // One connection failing to arrive, or too many open at once, must not stop the proxy
+            Err(e) if transient(&e) => {
+                warn!("Failed to accept a connection: {e}");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+            Err(e) => return Err(ProxyError::Accept(e)),
+        }
+    }
+}
+
+async fn serve<S>(
+    mut session: impl Session,
+    mut adapter: Framed<S::Stream, Codec<Request, Response>>,
+    idle: Option<Duration>,
+) -> Result<(), AgentError>
+where
+    S: ListeningSocket + fmt::Debug + Send,
+{
+    // Requests a client sent before the reply to an earlier one
+    let mut queued = VecDeque::new();
+    loop {
+        let request = match queued.pop_front() {
+            Some(request) => request,
+            None => {
+                let next = adapter.try_next();
+                let next = match idle {
+                    Some(idle) => match tokio::time::timeout(idle, next).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            debug!("Closing connection idle for {}s", idle.as_secs());
+                            return Ok(());
+                        }
+                    },
+                    None => next.await,
+                };
+                match next? {
+                    Some(request) => request,
+                    // The client disconnected between requests
+                    None => return Ok(()),
+                }
+            }
+        };
+        debug!("Request: {request:?}");
+
+        let result = {
+            let handling = session.handle(request);
+            tokio::pin!(handling);
+            loop {
+                tokio::select! {
+                    result = &mut handling => break result,
+                    next = adapter.try_next() => match next? {
+                        Some(request) => queued.push_back(request),
+                        None => {
+                            debug!("Client disconnected mid-request, cancelling it");
+                            return Ok(());
+                        }
+                    },
+                }
+            }
+        };
+
+        let response = match result {
+            Ok(response) => response,
+            Err(AgentError::ExtensionFailure) => {
+                error!("Extension failure handling message");
+                Response::ExtensionFailure
+            }
+            Err(e) => {
+                error!("Error handling message: {e:?}");
+                Response::Failure
+            }
+        };
+        debug!("Response: {response:?}");
+
+        adapter.send(response).await?;
+    }
+}
+
+/// Whether a failure to accept affects only the connection being accepted, not the listener
+fn transient(e: &io::Error) -> bool {
+    if matches!(
+        e.kind(),
+        ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset | ErrorKind::Interrupted
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use nix::errno::Errno;
+
+        let errno = e.raw_os_error().map(Errno::from_raw);
+        matches!(
+            errno,
+            Some(Errno::EMFILE | Errno::ENFILE | Errno::ENOBUFS | Errno::ENOMEM)
+        )
+    }
+    #[cfg(windows)]
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use nix::errno::Errno;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{UnixListener, UnixStream};
+
+    /// Listener whose accepts fail with `errors` first, then come from `listener`
+    #[derive(Debug)]
+    struct FlakySocket {
+        errors: VecDeque<io::Error>,
+        listener: UnixListener,
+    }
+
+    #[ssh_agent_lib::async_trait]
+    impl ListeningSocket for FlakySocket {
+        type Stream = UnixStream;
+
+        async fn accept(&mut self) -> io::Result<UnixStream> {
+            match self.errors.pop_front() {
+                Some(e) => Err(e),
+                None => self.listener.accept().await.map(|(stream, _)| stream),
+            }
+        }
+    }
+
+    /// Agent that lists no keys
+    #[derive(Clone)]
+    struct EmptyAgent;
+
+    #[ssh_agent_lib::async_trait]
+    impl Session for EmptyAgent {
+        async fn handle(&mut self, _: Request) -> Result<Response, AgentError> {
+            Ok(Response::IdentitiesAnswer(Vec::new()))
+        }
+    }
+
+    impl Agent<FlakySocket> for EmptyAgent {
+        fn new_session(&mut self, _: &UnixStream) -> impl Session {
+            self.clone()
+        }
+    }
+
+    /// Agent whose requests never finish, noting when one is cancelled
+    #[derive(Clone, Default)]
+    struct StalledAgent {
+        cancelled: Arc<AtomicBool>,
+    }
+
+    /// Marks the request it belongs to as cancelled when dropped before it finishes
+    struct Pending(Arc<AtomicBool>);
+
+    impl Drop for Pending {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[ssh_agent_lib::async_trait]
+    impl Session for StalledAgent {
+        async fn handle(&mut self, _: Request) -> Result<Response, AgentError> {
+            let _pending = Pending(self.cancelled.clone());
+            std::future::pending().await
+        }
+    }
+
+    impl Agent<FlakySocket> for StalledAgent {
+        fn new_session(&mut self, _: &UnixStream) -> impl Session {
+            self.clone()
+        }
+    }
+
+    fn flaky(name: &str, errors: Vec<io::Error>) -> (FlakySocket, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let errors = errors.into();
+        (FlakySocket { errors, listener }, path)
+    }
+
+    #[test]
+    fn running_out_of_descriptors_is_transient() {
+        for errno in [Errno::EMFILE, Errno::ENFILE, Errno::ENOBUFS, Errno::ENOMEM] {
+            assert!(
+                transient(&io::Error::from_raw_os_error(errno as i32)),
+                "{errno}"
+            );
+        }
+        assert!(transient(&ErrorKind::ConnectionAborted.into()));
+        assert!(!transient(&io::Error::from_raw_os_error(
+            Errno::EBADF as i32
+        )));
+    }
+
+    #[tokio::test]
+    async fn transient_accept_failures_keep_the_listener_serving() {
+        let errors = vec![
+            io::Error::from_raw_os_error(Errno::EMFILE as i32),
+            ErrorKind::ConnectionAborted.into(),
+        ];
+        let (socket, path) = flaky("transient", errors);
+        let server = tokio::spawn(listen(socket, EmptyAgent, None, None));
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        client.write_all(&[0, 0, 0, 1, 11]).await.unwrap();
+        let mut reply = [0; 9];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [0, 0, 0, 5, 12, 0, 0, 0, 0]);
+
+        server.abort();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn other_accept_failures_end_the_listener_with_an_error() {
+        let errors = vec![io::Error::from_raw_os_error(Errno::EBADF as i32)];
+        let (socket, path) = flaky("fatal", errors);
+        let result = listen(socket, EmptyAgent, None, None).await;
+        assert!(matches!(result, Err(ProxyError::Accept(_))));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn a_client_hanging_up_cancels_its_pending_request() {
+        let (socket, path) = flaky("hangup", Vec::new());
+        let agent = StalledAgent::default();
+        let cancelled = agent.cancelled.clone();
+        let server = tokio::spawn(listen(socket, agent, None, None));
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        client.write_all(&[0, 0, 0, 1, 11]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!cancelled.load(Ordering::SeqCst));
+
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !cancelled.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the pending request was never cancelled");
+
+        server.abort();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn closes_connections_idle_past_the_timeout() {
+        let (socket, path) = flaky("idle", Vec::new());
+        let idle = Duration::from_millis(200);
+        let server = tokio::spawn(listen(socket, EmptyAgent, Some(idle), None));
+
+        let mut idling = UnixStream::connect(&path).await.unwrap();
+        let mut active = UnixStream::connect(&path).await.unwrap();
+        let mut reply = [0; 9];
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            active.write_all(&[0, 0, 0, 1, 11]).await.unwrap();
+            active.read_exact(&mut reply).await.unwrap();
+        }
+
+        // Closed by the proxy, so reading sees the end of the stream
+        let closed = tokio::time::timeout(Duration::from_secs(5), idling.read(&mut reply)).await;
+        assert_eq!(closed.unwrap().unwrap(), 0);
+        active.write_all(&[0, 0, 0, 1, 11]).await.unwrap();
+        active.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [0, 0, 0, 5, 12, 0, 0, 0, 0]);
+
+        server.abort();
+        let _ = std::fs::remove_file(path);
+    }
+}
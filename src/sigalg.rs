@@ -0,0 +1,139 @@
+use serde::Serialize;
+use ssh_agent_lib::proto::SignRequest;
+use ssh_agent_lib::ssh_key::public::KeyData;
+use ssh_agent_lib::ssh_key::{Algorithm, Fingerprint, HashAlg, Signature};
+use std::str::FromStr;
+
+/// SSH_AGENT_RSA_SHA2_256 sign flag
+pub const RSA_SHA2_256: u32 = 0x02;
+/// SSH_AGENT_RSA_SHA2_512 sign flag
+pub const RSA_SHA2_512: u32 = 0x04;
+
+/// Signature algorithms one key may produce
+#[derive(Clone, Debug, Serialize)]
+pub struct KeySignatureAlgorithms {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    #[serde(serialize_with = "crate::policy::display_all")]
+    algorithms: Vec<Algorithm>,
+}
+
+impl FromStr for KeySignatureAlgorithms {
+    type Err = String;
+
+    /// Parse `<FINGERPRINT>=<ALGORITHM>[,<ALGORITHM>...]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fingerprint, algorithms) = s
+            .rsplit_once('=')
+            .ok_or("expected <FINGERPRINT>=<ALGORITHM>[,<ALGORITHM>...]")?;
+
+        let fingerprint = fingerprint.parse().map_err(|e| format!("{e}"))?;
+        let algorithms = algorithms
+            .split(',')
+            .map(|name| name.parse().map_err(|e| format!("{name}: {e}")))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            fingerprint,
+            algorithms,
+        })
+    }
+}
+
+/// Signature algorithm a sign request asks for, e.g. `rsa-sha2-512` for an RSA key
+///
+/// With both RSA flags set, SHA-256 wins, as it does in OpenSSH's ssh-agent.
+fn requested_algorithm(request: &SignRequest) -> Algorithm {
+    match &request.pubkey {
+        KeyData::Rsa(_) if request.flags & RSA_SHA2_256 != 0 => Algorithm::Rsa {
+            hash: Some(HashAlg::Sha256),
+        },
+        KeyData::Rsa(_) if request.flags & RSA_SHA2_512 != 0 => Algorithm::Rsa {
+            hash: Some(HashAlg::Sha512),
+        },
+        key => key.algorithm(),
+    }
+}
+
+/// The algorithm `request` asks for, if `limits` restrict its key to others
+pub fn disallowed(limits: &[KeySignatureAlgorithms], request: &SignRequest) -> Option<Algorithm> {
+    let fingerprint = request.pubkey.fingerprint(HashAlg::Sha256);
+    let limit = limits.iter().find(|l| l.fingerprint == fingerprint)?;
+    let algorithm = requested_algorithm(request);
+    (!limit.algorithms.contains(&algorithm)).then_some(algorithm)
+}
+
+/// The algorithm of `signature`, if `limits` restrict the key `fingerprint` to others
+///
+/// A backend may sign with another algorithm than the one asked for, e.g. with
+/// ssh-rsa when it lacks the requested SHA-2 variant.
+pub fn disallowed_signature(
+    limits: &[KeySignatureAlgorithms],
+    fingerprint: &Fingerprint,
+    signature: &Signature,
+) -> Option<Algorithm> {
+    let limit = limits.iter().find(|l| &l.fingerprint == fingerprint)?;
+    let algorithm = signature.algorithm();
+    (!limit.algorithms.contains(&algorithm)).then_some(algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_agent_lib::ssh_key::Mpint;
+    use ssh_agent_lib::ssh_key::public::RsaPublicKey;
+
+    /// An RSA public key; only its fingerprint matters here, so `n` tells keys apart
+    fn rsa_key(n: u8) -> KeyData {
+        KeyData::Rsa(RsaPublicKey {
+            e: Mpint::from_positive_bytes(&[1, 0, 1]).unwrap(),
+            n: Mpint::from_positive_bytes(&[n; 256]).unwrap(),
+        })
+    }
+
+    fn restricted(key: &KeyData, algorithms: &str) -> Vec<KeySignatureAlgorithms> {
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        vec![format!("{fingerprint}={algorithms}").parse().unwrap()]
+    }
+
+    fn request(key: &KeyData, flags: u32) -> SignRequest {
+        SignRequest {
+            pubkey: key.clone(),
+            data: b"data".to_vec(),
+            flags,
+        }
+    }
+
+    const SHA256: Algorithm = Algorithm::Rsa {
+        hash: Some(HashAlg::Sha256),
+    };
+
+    #[test]
+    fn restricted_to_sha512_rejects_a_sha256_request() {
+        let key = rsa_key(1);
+        let limits = restricted(&key, "rsa-sha2-512");
+        assert_eq!(
+            disallowed(&limits, &request(&key, RSA_SHA2_256)),
+            Some(SHA256)
+        );
+        assert_eq!(disallowed(&limits, &request(&key, RSA_SHA2_512)), None);
+        let sha1 = Algorithm::Rsa { hash: None };
+        assert_eq!(disallowed(&limits, &request(&key, 0)), Some(sha1));
+    }
+
+    #[test]
+    fn both_flags_ask_for_sha256() {
+        let key = rsa_key(1);
+        let both = request(&key, RSA_SHA2_256 | RSA_SHA2_512);
+        assert_eq!(requested_algorithm(&both), SHA256);
+        assert!(disallowed(&restricted(&key, "rsa-sha2-512"), &both).is_some());
+        assert!(disallowed(&restricted(&key, "rsa-sha2-256"), &both).is_none());
+    }
+
+    #[test]
+    fn unrestricted_keys_may_use_any_algorithm() {
+        let key = rsa_key(1);
+        let limits = restricted(&rsa_key(2), "rsa-sha2-512");
+        assert_eq!(disallowed(&limits, &request(&key, 0)), None);
+    }
+}
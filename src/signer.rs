@@ -0,0 +1,84 @@
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::proto::{Request, Response, SignRequest};
+use ssh_agent_lib::ssh_key::public::KeyData;
+use ssh_agent_lib::ssh_key::{Fingerprint, HashAlg, LineEnding, SshSig};
+use std::io::Read;
+use std::path::PathBuf;
+
+use crate::sigalg::RSA_SHA2_512;
+
+/// Sign one file through the policy and exit
+#[derive(clap::Args, Debug)]
+pub struct SignArgs {
+    /// Backend ssh-agent socket (defaults to --backend-sock, then SSH_AUTH_SOCK)
+    #[arg(long = "sock", value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    /// Fingerprint of the key to sign with
+    #[arg(long = "key", value_name = "FINGERPRINT")]
+    key: Fingerprint,
+
+    /// File to sign (`-` reads stdin)
+    #[arg(long = "data-file", value_name = "PATH")]
+    data_file: PathBuf,
+
+    /// Signature namespace, as for `ssh-keygen -Y sign -n`
+    #[arg(long = "namespace", value_name = "NAME", default_value = "file")]
+    namespace: String,
+}
+
+/// Sign the data file with one sign request through `session` and print the armored SSHSIG
+///
+/// The output verifies with `ssh-keygen -Y verify`. A confirm-constrained key
+/// prompts through the backend agent as usual.
+pub async fn sign(
+    session: &mut impl Session,
+    args: &SignArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data = if args.data_file.as_os_str() == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+        data
+    } else {
+        std::fs::read(&args.data_file)
+            .map_err(|e| format!("Failed to read {}: {e}", args.data_file.display()))?
+    };
+
+    let sshsig = sshsig(session, &args.key, &args.namespace, &data).await?;
+    print!("{}", sshsig.to_pem(LineEnding::LF)?);
+    Ok(())
+}
+
+/// Sign `data` for `namespace` with the key `fingerprint` through `session`, as an SSHSIG
+pub async fn sshsig(
+    session: &mut (impl Session + ?Sized),
+    fingerprint: &Fingerprint,
+    namespace: &str,
+    data: &[u8],
+) -> Result<SshSig, Box<dyn std::error::Error>> {
+    let Response::IdentitiesAnswer(identities) = session.handle(Request::RequestIdentities).await?
+    else {
+        return Err("Failed to list keys from the backend ssh-agent".into());
+    };
+    let pubkey = identities
+        .into_iter()
+        .map(|id| id.pubkey)
+        .find(|key| key.fingerprint(HashAlg::Sha256) == *fingerprint)
+        .ok_or_else(|| format!("No key {fingerprint} in the backend ssh-agent"))?;
+
+    // SSHSIG only allows rsa-sha2-256/512 for RSA keys, never ssh-rsa
+    let flags = match pubkey {
+        KeyData::Rsa(_) => RSA_SHA2_512,
+        _ => 0,
+    };
+    let request = Request::SignRequest(SignRequest {
+        pubkey: pubkey.clone(),
+        data: SshSig::signed_data(namespace, HashAlg::Sha512, data)?,
+        flags,
+    });
+    let Response::SignResponse(signature) = session.handle(request).await? else {
+        return Err(format!("Signing with {fingerprint} was refused").into());
+    };
+
+    Ok(SshSig::new(pubkey, namespace, HashAlg::Sha512, signature)?)
+}
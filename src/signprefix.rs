@@ -0,0 +1,119 @@
+use serde::Serialize;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::str::FromStr;
+
+/// Prefix the data one key signs must start with, for keys dedicated to one purpose
+#[derive(Clone, Debug, Serialize)]
+pub struct KeySignPrefix {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    /// The prefix as given, `hex:` and all
+    prefix: String,
+    #[serde(skip)]
+    bytes: Vec<u8>,
+}
+
+impl FromStr for KeySignPrefix {
+    type Err = String;
+
+    /// Parse `<FINGERPRINT>=<TEXT>` or `<FINGERPRINT>=hex:<HEX>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Fingerprints never contain '=', prefixes may
+        let (fingerprint, prefix) = s
+            .split_once('=')
+            .ok_or("expected <FINGERPRINT>=<TEXT> or <FINGERPRINT>=hex:<HEX>")?;
+
+        let fingerprint = fingerprint.parse().map_err(|e| format!("{e}"))?;
+        let bytes = match prefix.strip_prefix("hex:") {
+            Some(hex) => hex_decode(hex).ok_or_else(|| format!("{hex}: not hex"))?,
+            None => prefix.as_bytes().to_vec(),
+        };
+        if bytes.is_empty() {
+            return Err("prefix is empty".into());
+        }
+
+        Ok(Self {
+            fingerprint,
+            prefix: prefix.to_string(),
+            bytes,
+        })
+    }
+}
+
+/// Whether `data` may be signed with `fingerprint`
+///
+/// Keys without a prefix may sign anything; keys with several may sign data
+/// starting with any of them.
+pub fn permitted(prefixes: &[KeySignPrefix], fingerprint: &Fingerprint, data: &[u8]) -> bool {
+    let mut own = prefixes
+        .iter()
+        .filter(|p| &p.fingerprint == fingerprint)
+        .peekable();
+    own.peek().is_none() || own.any(|p| data.starts_with(&p.bytes))
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+    const OTHER: &str = "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU";
+
+    fn prefixes(specs: &[&str]) -> Vec<KeySignPrefix> {
+        specs.iter().map(|spec| spec.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_text_and_hex_prefixes() {
+        let [text, hex] = &prefixes(&[&format!("{KEY}=SSHSIG"), &format!("{KEY}=hex:00ff")])[..]
+        else {
+            unreachable!()
+        };
+        assert_eq!(text.bytes, b"SSHSIG");
+        assert_eq!(hex.bytes, [0x00, 0xff]);
+        let with_equals: KeySignPrefix = format!("{KEY}=a=b").parse().unwrap();
+        assert_eq!(with_equals.bytes, b"a=b");
+    }
+
+    #[test]
+    fn rejects_bad_prefixes() {
+        for spec in [
+            "=SSHSIG",
+            &format!("{KEY}="),
+            &format!("{KEY}=hex:0"),
+            &format!("{KEY}=hex:zz"),
+        ] {
+            assert!(spec.parse::<KeySignPrefix>().is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn keys_with_prefixes_sign_only_matching_data() {
+        let prefixes = prefixes(&[
+            &format!("{KEY}=SSHSIG\0\0\0\x03git"),
+            &format!("{KEY}=hex:0000"),
+        ]);
+        let key = KEY.parse().unwrap();
+        assert!(permitted(&prefixes, &key, b"SSHSIG\0\0\0\x03git\0\0\0\0"));
+        assert!(permitted(&prefixes, &key, &[0, 0, 0, 32]));
+        assert!(!permitted(&prefixes, &key, b"SSHSIG\0\0\0\x04file"));
+        assert!(!permitted(&prefixes, &key, b"SSH"));
+    }
+
+    #[test]
+    fn keys_without_prefixes_sign_anything() {
+        let prefixes = prefixes(&[&format!("{KEY}=SSHSIG")]);
+        assert!(permitted(&prefixes, &OTHER.parse().unwrap(), b"anything"));
+        assert!(permitted(&[], &KEY.parse().unwrap(), b"anything"));
+    }
+}
@@ -0,0 +1,203 @@
+use log::{debug, info};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Why the proxy socket could not be set up at the requested path
+#[derive(Debug)]
+pub enum SocketPathError {
+    /// The socket's directory cannot be created or written to
+    ParentNotWritable {
+        parent: PathBuf,
+        source: io::Error,
+    },
+    /// Another process accepts connections on the socket
+    InUse(PathBuf),
+    /// The path names a directory rather than a socket
+    IsDirectory(PathBuf),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for SocketPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ParentNotWritable { parent, source } => write!(
+                f,
+                "Cannot write to {} ({source}); fix its permissions or pick another path with --sock",
+                parent.display()
+            ),
+            Self::InUse(path) => write!(
+                f,
+                "Socket {} is in use by a running process; stop it or pick another path with --sock",
+                path.display()
+            ),
+            Self::IsDirectory(path) => write!(
+                f,
+                "Socket path {} is a directory; pass a file path to --sock",
+                path.display()
+            ),
+            Self::Io { path, source } => {
+                write!(f, "Cannot set up socket {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SocketPathError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ParentNotWritable { source, .. } | Self::Io { source, .. } => Some(source),
+            Self::InUse(_) | Self::IsDirectory(_) => None,
+        }
+    }
+}
+
+/// Get `path` ready for binding: create its directory and remove a socket left behind by a crash
+///
+/// A socket that still accepts connections is left alone, so a running proxy is
+/// never replaced.
+pub fn prepare(path: &Path) -> Result<(), SocketPathError> {
+    if path.is_dir() {
+        return Err(SocketPathError::IsDirectory(path.to_owned()));
+    }
+
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent).map_err(|e| filesystem_error(path, e))?;
+        info!("Created directory: {}", parent.display());
+    }
+
+    if path.exists() {
+        #[cfg(unix)]
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(SocketPathError::InUse(path.to_owned()));
+        }
+        fs::remove_file(path).map_err(|e| filesystem_error(path, e))?;
+        debug!("Removed stale socket: {}", path.display());
+    }
+    Ok(())
+}
+
+/// Classify a failure to create, remove or bind the socket at `path`
+pub fn filesystem_error(path: &Path, source: io::Error) -> SocketPathError {
+    match source.kind() {
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+            SocketPathError::ParentNotWritable {
+                parent: path.parent().unwrap_or(path).to_owned(),
+                source,
+            }
+        }
+        ErrorKind::AddrInUse => SocketPathError::InUse(path.to_owned()),
+        _ => SocketPathError::Io {
+            path: path.to_owned(),
+            source,
+        },
+    }
+}
+
+/// Shell commands pointing SSH_AUTH_SOCK at the proxy socket at `path`, for `eval` or `source`
+pub fn export_env(path: &Path) -> io::Result<String> {
+    let path = std::path::absolute(path)?;
+    let quoted = path.to_string_lossy().replace('\'', r"'\''");
+    Ok(format!("export SSH_AUTH_SOCK='{quoted}'\n"))
+}
+
+/// Write `env` to `file`, beside it first and renamed so a shell never sources half of it
+pub fn write_env_file(file: &Path, env: &str) -> io::Result<()> {
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, env)?;
+    fs::rename(&tmp, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test's files
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn refuses_a_directory() {
+        let dir = dir("sock-dir");
+        let e = prepare(&dir).unwrap_err();
+        assert!(matches!(e, SocketPathError::IsDirectory(_)));
+        assert!(e.to_string().contains("pass a file path to --sock"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn creates_missing_directories() {
+        let dir = dir("sock-parent");
+        let path = dir.join("a/b/agent.sock");
+        prepare(&path).unwrap();
+        assert!(path.parent().unwrap().is_dir());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn replaces_only_stale_sockets() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = dir("sock-stale");
+        let path = dir.join("agent.sock");
+        let live = UnixListener::bind(&path).unwrap();
+        let e = prepare(&path).unwrap_err();
+        assert!(matches!(e, SocketPathError::InUse(_)), "{e}");
+        assert!(path.exists());
+
+        // The file outlives its listener, as after a crash
+        drop(live);
+        prepare(&path).unwrap();
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn classifies_filesystem_errors() {
+        let path = Path::new("/run/ssh/agent.sock");
+        let denied = filesystem_error(path, ErrorKind::PermissionDenied.into());
+        assert!(
+            matches!(&denied, SocketPathError::ParentNotWritable { parent, .. } if parent == Path::new("/run/ssh"))
+        );
+        let in_use = filesystem_error(path, ErrorKind::AddrInUse.into());
+        assert!(matches!(in_use, SocketPathError::InUse(_)));
+        let other = filesystem_error(path, ErrorKind::InvalidInput.into());
+        assert!(matches!(other, SocketPathError::Io { .. }));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn quotes_the_socket_path_for_the_shell() {
+        let env = export_env(Path::new("/tmp/it's.sock")).unwrap();
+        assert_eq!(env, "export SSH_AUTH_SOCK='/tmp/it'\\''s.sock'\n");
+    }
+
+    #[test]
+    fn env_file_leaves_sibling_files_alone() {
+        let dir = dir("env");
+        let sibling = dir.join("agent.tmp");
+        fs::write(&sibling, "unrelated").unwrap();
+
+        let file = dir.join("agent.env");
+        write_env_file(&file, "export SSH_AUTH_SOCK='/s'\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "export SSH_AUTH_SOCK='/s'\n"
+        );
+        assert_eq!(fs::read_to_string(&sibling).unwrap(), "unrelated");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use log::warn;
+use serde::Serializer;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often changed state is written out, besides on exit
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Storage for state the proxy keeps across restarts, one JSON value per section
+///
+/// Each feature owns one section, such as `quotas`. Changes are kept in
+/// memory until [`StateStore::flush`], which the proxy calls periodically and
+/// on exit.
+pub trait StateStore: fmt::Debug + fmt::Display + Send + Sync {
+    /// The saved value of `section`, if there is one
+    fn get(&self, section: &str) -> Option<Value>;
+
+    /// Replace the value of `section`
+    fn set(&self, section: &str, value: Value);
+
+    /// Save the changes made since the last flush
+    fn flush(&self) -> io::Result<()>;
+}
+
+pub type Store = Arc<dyn StateStore>;
+
+/// State kept in one JSON file
+#[derive(Debug)]
+pub struct JsonFile {
+    path: PathBuf,
+    /// The one section the file holds directly, for the files of single-feature flags
+    only: Option<&'static str>,
+    state: Mutex<JsonState>,
+}
+
+#[derive(Debug, Default)]
+struct JsonState {
+    sections: Map<String, Value>,
+    dirty: bool,
+}
+
+impl JsonFile {
+    /// Open a file holding every section as a top-level key, resuming it if it exists
+    pub fn open(path: PathBuf) -> Store {
+        let sections = match read(&path) {
+            Some(Value::Object(sections)) => sections,
+            Some(_) => {
+                warn!("Ignoring state in {}: not a JSON object", path.display());
+                Map::new()
+            }
+            None => Map::new(),
+        };
+        Self::store(path, None, sections)
+    }
+
+    /// Open a file holding only `section`, as `--quota-state` and `--key-usage-state` write
+    pub fn open_section(path: PathBuf, section: &'static str) -> Store {
+        let mut sections = Map::new();
+        if let Some(value) = read(&path) {
+            sections.insert(section.to_string(), value);
+        }
+        Self::store(path, Some(section), sections)
+    }
+
+    fn store(path: PathBuf, only: Option<&'static str>, sections: Map<String, Value>) -> Store {
+        Arc::new(Self {
+            path,
+            only,
+            state: Mutex::new(JsonState {
+                sections,
+                dirty: false,
+            }),
+        })
+    }
+}
+
+impl fmt::Display for JsonFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())
+    }
+}
+
+impl StateStore for JsonFile {
+    fn get(&self, section: &str) -> Option<Value> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.sections.get(section).cloned()
+    }
+
+    fn set(&self, section: &str, value: Value) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.sections.insert(section.to_string(), value);
+        state.dirty = true;
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.dirty {
+            return Ok(());
+        }
+        let json = match self.only {
+            Some(section) => serde_json::to_vec(&state.sections.get(section)),
+            None => serde_json::to_vec(&state.sections),
+        }
+        .expect("JSON values serialize");
+
+        // Write beside the file and rename, so a crash never leaves it truncated
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, json).and_then(|()| fs::rename(&tmp, &self.path))?;
+        state.dirty = false;
+        Ok(())
+    }
+}
+
+/// The JSON in `path`, if it exists and parses
+fn read(path: &Path) -> Option<Value> {
+    let raw = fs::read(path).ok()?;
+    serde_json::from_slice(&raw)
+        .inspect_err(|e| warn!("Ignoring unreadable state in {}: {e}", path.display()))
+        .ok()
+}
+
+/// Flush every store, logging failures
+pub fn flush_all(stores: &[Store]) {
+    for store in stores {
+        if let Err(e) = store.flush() {
+            warn!("Failed to save state to {store}: {e}");
+        }
+    }
+}
+
+/// Serialize where a store keeps its state, for the policy dump
+pub fn location<S: Serializer>(store: &Option<Store>, serializer: S) -> Result<S::Ok, S::Error> {
+    match store {
+        Some(store) => serializer.collect_str(store),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A fresh directory for one test's files
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn state_written_in_one_run_is_read_in_the_next() {
+        let dir = dir("state-roundtrip");
+        let path = dir.join("state.json");
+
+        let first = JsonFile::open(path.clone());
+        first.set("quotas", json!({ "key": 1 }));
+        first.set("key_usage", json!([2, 3]));
+        first.flush().unwrap();
+        drop(first);
+
+        let second = JsonFile::open(path);
+        assert_eq!(second.get("quotas"), Some(json!({ "key": 1 })));
+        assert_eq!(second.get("key_usage"), Some(json!([2, 3])));
+        assert_eq!(second.get("missing"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn single_section_files_hold_the_section_directly() {
+        let dir = dir("state-section");
+        let path = dir.join("quota.json");
+
+        let first = JsonFile::open_section(path.clone(), "quotas");
+        first.set("quotas", json!({ "key": 1 }));
+        first.flush().unwrap();
+        assert_eq!(read(&path), Some(json!({ "key": 1 })));
+
+        let second = JsonFile::open_section(path, "quotas");
+        assert_eq!(second.get("quotas"), Some(json!({ "key": 1 })));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn flushing_leaves_sibling_files_alone() {
+        let dir = dir("state-sibling");
+        let sibling = dir.join("state.tmp");
+        fs::write(&sibling, "unrelated").unwrap();
+
+        let store = JsonFile::open(dir.join("state.json"));
+        store.set("quotas", json!({}));
+        store.flush().unwrap();
+        assert_eq!(fs::read_to_string(&sibling).unwrap(), "unrelated");
+        assert!(!dir.join("state.json.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_state_is_ignored() {
+        let dir = dir("state-unreadable");
+        let path = dir.join("state.json");
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(JsonFile::open(path).get("quotas"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use ssh_agent_lib::ssh_key::Fingerprint;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds each code is valid for
+const STEP_SECS: u64 = 30;
+
+/// Six-digit time-based one-time password (RFC 6238, HMAC-SHA256) required to sign with one key
+#[derive(Clone, Serialize)]
+pub struct KeyTotp {
+    #[serde(serialize_with = "crate::policy::display")]
+    fingerprint: Fingerprint,
+    secret_file: PathBuf,
+    #[serde(skip)]
+    secret: Vec<u8>,
+}
+
+// Written out so the secret never reaches a log through the policy
+impl fmt::Debug for KeyTotp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyTotp")
+            .field("fingerprint", &self.fingerprint)
+            .field("secret_file", &self.secret_file)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for KeyTotp {
+    type Err = String;
+
+    /// Parse `<FINGERPRINT>=<PATH>`, reading the base32 secret from the file at PATH
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Fingerprints never contain '=', so the path may
+        let (fingerprint, secret_file) =
+            s.split_once('=').ok_or("expected <FINGERPRINT>=<PATH>")?;
+
+        let fingerprint = fingerprint.parse().map_err(|e| format!("{e}"))?;
+        let secret_file = PathBuf::from(secret_file);
+        let encoded = std::fs::read_to_string(&secret_file)
+            .map_err(|e| format!("{}: {e}", secret_file.display()))?;
+        let secret = base32_decode(&encoded)
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| format!("{}: not a base32 secret", secret_file.display()))?;
+
+        Ok(Self {
+            fingerprint,
+            secret_file,
+            secret,
+        })
+    }
+}
+
+impl KeyTotp {
+    fn code(&self, step: u64) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // Dynamic truncation (RFC 4226 section 5.3)
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+        format!("{:06}", binary % 1_000_000)
+    }
+}
+
+/// Keys that need a one-time code, and the last code accepted for each
+#[derive(Debug, Serialize)]
+#[serde(transparent)]
+pub struct Totps {
+    keys: Vec<KeyTotp>,
+    /// Time step of the last accepted code per key, so a code cannot be replayed
+    #[serde(skip)]
+    last_steps: Mutex<BTreeMap<Fingerprint, u64>>,
+}
+
+impl Totps {
+    pub fn new(keys: Vec<KeyTotp>) -> Self {
+        Self {
+            keys,
+            last_steps: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn requires(&self, fingerprint: &Fingerprint) -> bool {
+        self.keys.iter().any(|k| &k.fingerprint == fingerprint)
+    }
+
+    /// Whether `code` is valid for `fingerprint` in the current or previous time step and unused
+    pub fn verify(&self, fingerprint: &Fingerprint, code: &str) -> bool {
+        let Some(key) = self.keys.iter().find(|k| &k.fingerprint == fingerprint) else {
+            return false;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let current = now / STEP_SECS;
+
+        let mut last_steps = self.last_steps.lock().unwrap_or_else(|e| e.into_inner());
+        // Accept one step of clock skew
+        for step in [current, current.saturating_sub(1)] {
+            if last_steps
+                .get(fingerprint)
+                .is_some_and(|&last| step <= last)
+            {
+                continue;
+            }
+            if code.trim() == key.code(step) {
+                last_steps.insert(*fingerprint, step);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Decode RFC 4648 base32, ignoring case, whitespace and padding
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bits = 0u32;
+    let mut len = 0;
+    let mut decoded = Vec::new();
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u32 - 'A' as u32,
+            c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+            _ => return None,
+        };
+        bits = (bits << 5) | value;
+        len += 5;
+        if len >= 8 {
+            len -= 8;
+            decoded.push((bits >> len) as u8);
+            bits &= (1 << len) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "SHA256:amPFusGQO0RS+EEKxj3rolcydKxDzOAhhtXGefLztJo";
+
+    fn key_totp(secret: &[u8]) -> KeyTotp {
+        KeyTotp {
+            fingerprint: KEY.parse().unwrap(),
+            secret_file: PathBuf::from("totp.key"),
+            secret: secret.to_vec(),
+        }
+    }
+
+    /// RFC 6238 Appendix B, SHA-256 column, as the last six of its eight digits
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        let key = key_totp(b"12345678901234567890123456789012");
+        let vectors = [
+            (59, "46119246"),
+            (1_111_111_109, "68084774"),
+            (1_111_111_111, "67062674"),
+            (1_234_567_890, "91819424"),
+            (2_000_000_000, "90698825"),
+            (20_000_000_000, "77737706"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(key.code(time / STEP_SECS), code[2..], "time {time}");
+        }
+    }
+
+    /// RFC 4648 section 10
+    #[test]
+    fn decodes_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("MY======", "f"),
+            ("MZXQ====", "fo"),
+            ("MZXW6===", "foo"),
+            ("MZXW6YQ=", "foob"),
+            ("MZXW6YTB", "fooba"),
+            ("MZXW6YTBOI======", "foobar"),
+        ];
+        for (encoded, decoded) in vectors {
+            assert_eq!(base32_decode(encoded).unwrap(), decoded.as_bytes());
+        }
+        assert_eq!(base32_decode("mzxw 6ytb\n").unwrap(), b"fooba");
+        assert_eq!(base32_decode("MZXW1"), None);
+    }
+
+    #[test]
+    fn codes_are_accepted_once() {
+        let totps = Totps::new(vec![key_totp(b"secret")]);
+        let fingerprint = KEY.parse().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let code = totps.keys[0].code(now.as_secs() / STEP_SECS);
+        assert!(totps.verify(&fingerprint, &code));
+        assert!(!totps.verify(&fingerprint, &code));
+    }
+
+    #[test]
+    fn reads_secrets_from_paths_with_equals_signs() {
+        let path = std::env::temp_dir().join(format!("totp={}.key", std::process::id()));
+        std::fs::write(&path, "MZXW6YTB\n").unwrap();
+        let parsed = format!("{KEY}={}", path.display()).parse::<KeyTotp>();
+        let _ = std::fs::remove_file(&path);
+
+        let key = parsed.unwrap();
+        assert_eq!(key.secret_file, path);
+        assert_eq!(key.secret, b"fooba");
+    }
+
+    #[test]
+    fn never_shows_the_secret() {
+        let key = key_totp(b"hunter2");
+        let debug = format!("{key:?}");
+        assert!(!debug.contains("secret:"), "secret in {debug}");
+        let json = serde_json::to_string(&key).unwrap();
+        assert!(!json.contains("\"secret\""), "secret in {json}");
+    }
+}
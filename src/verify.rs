@@ -0,0 +1,87 @@
+use signature::Verifier;
+use ssh_agent_lib::proto::SignRequest;
+use ssh_agent_lib::ssh_key::public::KeyData;
+use ssh_agent_lib::ssh_key::{Algorithm, Signature};
+
+/// Outcome of checking a backend signature against the request that produced it
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Valid,
+    Invalid,
+    /// The key type or algorithm cannot be verified here (DSA, `ssh-rsa` with SHA-1, unknown keys)
+    Unsupported,
+}
+
+pub fn verify_signature(request: &SignRequest, signature: &Signature) -> Verification {
+    let checkable = matches!(
+        request.pubkey,
+        KeyData::Ecdsa(_)
+            | KeyData::Ed25519(_)
+            | KeyData::Rsa(_)
+            | KeyData::SkEcdsaSha2NistP256(_)
+            | KeyData::SkEd25519(_)
+    );
+    if !checkable || signature.algorithm() == (Algorithm::Rsa { hash: None }) {
+        return Verification::Unsupported;
+    }
+    match request.pubkey.verify(&request.data, signature) {
+        Ok(()) => Verification::Valid,
+        Err(_) => Verification::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signature::Signer;
+    use ssh_agent_lib::ssh_key::public::OpaquePublicKey;
+    use ssh_agent_lib::ssh_key::rand_core::OsRng;
+    use ssh_agent_lib::ssh_key::{AlgorithmName, PrivateKey};
+
+    fn request(pubkey: KeyData) -> SignRequest {
+        SignRequest {
+            pubkey,
+            data: b"data".to_vec(),
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn accepts_a_genuine_signature() {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let signature = key.try_sign(b"data").unwrap();
+        let request = request(key.public_key().key_data().clone());
+        assert_eq!(verify_signature(&request, &signature), Verification::Valid);
+    }
+
+    #[test]
+    fn rejects_a_garbage_signature() {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let garbage = Signature::new(Algorithm::Ed25519, vec![7; 64]).unwrap();
+        let request = request(key.public_key().key_data().clone());
+        assert_eq!(verify_signature(&request, &garbage), Verification::Invalid);
+    }
+
+    #[test]
+    fn rejects_a_signature_by_another_key() {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let other = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let signature = other.try_sign(b"data").unwrap();
+        let request = request(key.public_key().key_data().clone());
+        assert_eq!(
+            verify_signature(&request, &signature),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn cannot_check_unknown_key_types() {
+        let algorithm = Algorithm::Other(AlgorithmName::new("foo@example.com").unwrap());
+        let pubkey = KeyData::Other(OpaquePublicKey::new(vec![1, 2, 3], algorithm.clone()));
+        let signature = Signature::new(algorithm, vec![7; 16]).unwrap();
+        assert_eq!(
+            verify_signature(&request(pubkey), &signature),
+            Verification::Unsupported
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use ssh_agent_lib::agent::ListeningSocket;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener, VsockStream};
+
+/// Address to accept vsock connections on, written `CID:PORT`
+///
+/// The CID is the proxy's own (usually `any`), not the guest's.
+#[derive(Clone, Copy, Debug)]
+pub struct VsockTarget {
+    cid: u32,
+    port: u32,
+}
+
+impl FromStr for VsockTarget {
+    type Err = String;
+
+    /// Parse `<CID>:<PORT>`, where the CID may be `any`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (cid, port) = s.split_once(':').ok_or("expected <CID>:<PORT>")?;
+        let cid = match cid {
+            "any" => VMADDR_CID_ANY,
+            cid => cid.parse().map_err(|e| format!("CID: {e}"))?,
+        };
+        let port = port.parse().map_err(|e| format!("port: {e}"))?;
+        Ok(Self { cid, port })
+    }
+}
+
+impl fmt::Display for VsockTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cid {
+            VMADDR_CID_ANY => write!(f, "vsock:any:{}", self.port),
+            cid => write!(f, "vsock:{cid}:{}", self.port),
+        }
+    }
+}
+
+/// Vsock listener accepting agent connections from virtual machines
+#[derive(Debug)]
+pub struct Listener(VsockListener);
+
+impl Listener {
+    pub fn bind(target: VsockTarget) -> io::Result<Self> {
+        VsockListener::bind(VsockAddr::new(target.cid, target.port)).map(Self)
+    }
+}
+
+#[ssh_agent_lib::async_trait]
+impl ListeningSocket for Listener {
+    type Stream = VsockStream;
+
+    async fn accept(&mut self) -> io::Result<Self::Stream> {
+        self.0.accept().await.map(|(stream, _addr)| stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_vsock::VMADDR_CID_LOCAL;
+
+    #[test]
+    fn parses_targets() {
+        let target: VsockTarget = "any:2222".parse().unwrap();
+        assert_eq!((target.cid, target.port), (VMADDR_CID_ANY, 2222));
+        assert_eq!(target.to_string(), "vsock:any:2222");
+        let target: VsockTarget = "3:22".parse().unwrap();
+        assert_eq!(target.to_string(), "vsock:3:22");
+        assert!("2222".parse::<VsockTarget>().is_err());
+        assert!("host:22".parse::<VsockTarget>().is_err());
+    }
+
+    #[tokio::test]
+    async fn accepts_loopback_connections() {
+        let target = VsockTarget {
+            cid: VMADDR_CID_LOCAL,
+            port: 40000 + std::process::id() % 20000,
+        };
+        // Loopback needs the vsock_loopback module, which not every host has
+        let Ok(mut listener) = Listener::bind(target) else {
+            return;
+        };
+        let accepted = tokio::spawn(async move { listener.accept().await });
+        let connected = VsockStream::connect(VsockAddr::new(target.cid, target.port)).await;
+        if connected.is_err() {
+            return;
+        }
+        let stream = accepted.await.unwrap().unwrap();
+        assert_eq!(stream.peer_addr().unwrap().cid(), VMADDR_CID_LOCAL);
+    }
+}
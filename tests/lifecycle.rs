@@ -0,0 +1,182 @@
+//! Starting and stopping the proxy process, as a user or service manager does
+#![cfg(unix)]
+
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn socket(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ssh-agent-ac-{}-{name}.sock", std::process::id()))
+}
+
+/// Start a proxy on `socket` in front of an in-process backend, running a long sleep
+fn start(socket: &Path) -> Child {
+    start_with(socket, &[])
+}
+
+fn start_with(socket: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_ssh-agent-ac"))
+        .arg("--in-process-backend")
+        .args(args)
+        .arg("--sock")
+        .arg(socket)
+        .args(["sleep", "30"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+/// Wait until something accepts connections on `socket`
+fn wait_for(socket: &Path) {
+    let started = Instant::now();
+    while UnixStream::connect(socket).is_err() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "proxy never listened"
+        );
+        sleep(Duration::from_millis(20));
+    }
+}
+
+/// Ask the proxy on `socket` for its keys and return the raw answer
+fn request_identities(socket: &Path) -> Vec<u8> {
+    let mut client = UnixStream::connect(socket).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    client.write_all(&[0, 0, 0, 1, 11]).unwrap();
+    let mut answer = [0; 9];
+    client.read_exact(&mut answer).unwrap();
+    answer.to_vec()
+}
+
+fn terminate(proxy: &mut Child) {
+    let killed = Command::new("kill")
+        .args(["-TERM", &proxy.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    proxy.wait().unwrap();
+}
+
+#[test]
+fn sigterm_removes_the_socket() {
+    let socket = socket("sigterm");
+    let mut proxy = start(&socket);
+    wait_for(&socket);
+    terminate(&mut proxy);
+    assert!(!socket.exists());
+}
+
+#[test]
+fn readiness_file_appears_once_listening() {
+    let socket = socket("ready");
+    let ready = std::env::temp_dir().join(format!("ssh-agent-ac-{}-ready", std::process::id()));
+    // Left over from an earlier run, so it must not count
+    std::fs::write(&ready, "0\n").unwrap();
+
+    let notify = format!("file:{}", ready.display());
+    let mut proxy = start_with(&socket, &["--notify-ready", &notify]);
+    let started = Instant::now();
+    while std::fs::read_to_string(&ready).unwrap_or_default() != format!("{}\n", proxy.id()) {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "proxy never signalled readiness"
+        );
+        sleep(Duration::from_millis(20));
+    }
+    assert!(UnixStream::connect(&socket).is_ok());
+
+    terminate(&mut proxy);
+    let _ = std::fs::remove_file(&ready);
+}
+
+#[test]
+fn exports_the_proxy_socket_for_shells() {
+    let socket = socket("export");
+    let env = std::env::temp_dir().join(format!("ssh-agent-ac-{}-agent.env", std::process::id()));
+    let _ = std::fs::remove_file(&env);
+
+    let mut proxy = start_with(&socket, &["--export-env", env.to_str().unwrap()]);
+    wait_for(&socket);
+    let started = Instant::now();
+    while !env.exists() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "proxy never exported its socket"
+        );
+        sleep(Duration::from_millis(20));
+    }
+
+    let exported = std::fs::read_to_string(&env).unwrap();
+    assert_eq!(
+        exported,
+        format!("export SSH_AUTH_SOCK='{}'\n", socket.display())
+    );
+    let sourced = Command::new("sh")
+        .arg("-c")
+        .arg(r#". "$0" && printf %s "$SSH_AUTH_SOCK""#)
+        .arg(&env)
+        .output()
+        .unwrap();
+    assert!(sourced.status.success());
+    assert_eq!(
+        Path::new(std::str::from_utf8(&sourced.stdout).unwrap()),
+        socket
+    );
+
+    terminate(&mut proxy);
+    let _ = std::fs::remove_file(&env);
+}
+
+#[test]
+fn serves_on_either_runtime_flavor() {
+    for (name, flavor) in [
+        ("current-thread", &["--runtime", "current-thread"][..]),
+        (
+            "multi-thread",
+            &["--runtime", "multi-thread", "--worker-threads", "2"],
+        ),
+    ] {
+        let socket = socket(name);
+        let mut proxy = start_with(&socket, flavor);
+        wait_for(&socket);
+        // An empty identities answer from the in-process backend
+        assert_eq!(
+            request_identities(&socket),
+            [0, 0, 0, 5, 12, 0, 0, 0, 0],
+            "{name}"
+        );
+        terminate(&mut proxy);
+        assert!(!socket.exists(), "{name}");
+    }
+}
+
+#[test]
+fn refuses_to_replace_a_running_proxy() {
+    let socket = socket("live");
+    let mut running = start(&socket);
+    wait_for(&socket);
+
+    let second = start(&socket).wait().unwrap();
+    assert!(!second.success());
+    assert!(UnixStream::connect(&socket).is_ok());
+    terminate(&mut running);
+}
+
+#[test]
+fn replaces_a_stale_socket() {
+    let socket = socket("stale");
+    let _ = std::fs::remove_file(&socket);
+    // The file outlives its listener, as after a crash
+    drop(UnixListener::bind(&socket).unwrap());
+
+    let mut proxy = start(&socket);
+    wait_for(&socket);
+    terminate(&mut proxy);
+    assert!(!socket.exists());
+}